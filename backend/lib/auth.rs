@@ -2,8 +2,23 @@ pub mod Credentials;
 pub mod Session;
 pub mod User;
 pub mod Citizen;
+pub mod CitizenDirectory;
 pub mod Actions;
 pub mod Endpoints;
 pub mod Request;
 pub mod Errors;
 pub mod Employee;
+pub mod Totp;
+pub mod Audit;
+pub mod AuditChain;
+pub mod Outbox;
+pub mod Mailer;
+pub mod MailTemplate;
+pub mod MailOutbox;
+pub mod Letter;
+pub mod LetterOutbox;
+pub mod RegistrationCode;
+pub mod Authenticated;
+pub mod Problem;
+pub mod Redirect;
+pub mod SessionCookie;
@@ -1,19 +1,27 @@
-use anyhow::ensure;
+use diesel::dsl::{IntoBoxed, LeftJoin};
 use diesel::mysql::Mysql;
-use diesel::{BoolExpressionMethods, ExpressionMethods, insert_into, MysqlConnection, QueryDsl, QueryResult, RunQueryDsl};
+use diesel::{BoolExpressionMethods, Connection, ExpressionMethods, insert_into, MysqlConnection, OptionalExtension, QueryDsl, QueryResult, RunQueryDsl, TextExpressionMethods};
 use diesel::result::Error;
-use lettre::{SmtpClient, Transport};
-use lettre_email::EmailBuilder;
-use moon::Utc;
-use crate::auth::Credentials::{CredentialsHolder, CredentialsPair, IdentityHolder};
+use log::debug;
+use moon::{chrono, Utc};
+use crate::auth::Credentials::{hash_secret, CredentialsHolder, CredentialsPair, IdentityHolder};
 use thiserror::Error;
+use crate::auth::Audit::{count_events_from, record_event, try_record_event, AuditEventType, AuditOutcome, AuditParty, NewAuditEvent, RequestOrigin};
 use crate::auth::Citizen::{Citizen, CitizenInfo};
-use crate::auth::Employee::{EmployeeInfoModel, EmployeeLogin, EmployeeSession, NewEmployeeInfo};
-use crate::auth::Errors::{AuthenticationError, AuthenticationResult, DatabaseError, LoginError, LoginResult, SessionInsertionError, SessionInsertionResult, SessionRetrievalError, SessionRetrievalResult, UserRegistrationError, UserRegistrationResult};
-use crate::auth::Request::{UserRegistrationRequest, UserLoginRequest, UserLoginRequestResponse, EmployeeLoginRequestResponse};
-use crate::auth::Session::{NewSession, Session, Token, UserSession};
-use crate::auth::User::{PendingUser, User};
+use crate::auth::Employee::{EmployeeInfoModel, EmployeeInvitation, EmployeeLogin, EmployeeRole, EmployeeSession, EmployeeStatus, NewEmployeeInfo, Role};
+use crate::auth::RegistrationCode::RegistrationCodeFormat;
+use crate::auth::Outbox::{enqueue_event, try_enqueue_event, AccountType, DomainEvent};
+use crate::auth::Errors::{AuthenticationError, AuthenticationResult, CitizenAdministrationError, CitizenAdministrationResult, DatabaseError, EmployeeAdministrationError, EmployeeAdministrationResult, LoginError, LoginResult, MailSenderError, SessionInsertionError, SessionInsertionResult, SessionRetrievalError, SessionRetrievalResult, UserRegistrationError, UserRegistrationResult};
+use crate::auth::Request::{UserRegistrationRequest, UserLoginRequest, UserLoginRequestResponse, EmployeeLoginRequestResponse, EmployeeRegisterRequest, EmployeeListRequest, EmployeeListEntry, CitizenAccountResponse, CitizenSessionEntry};
+use crate::auth::Session::{create_token, NewSession, Session, Token, UserSession};
+use crate::auth::Totp;
+use crate::auth::User::{PasswordReset, PendingUser, User};
 use crate::schema;
+use crate::auth::Letter::LetterRenderer;
+use crate::auth::LetterOutbox::{has_letter, queue_letter};
+use crate::auth::MailOutbox::{find_mail, queue_mail, requeue_mail, MailStatus};
+use crate::auth::Mailer::Mail;
+use crate::auth::MailTemplate::MailTemplates;
 use crate::schema::EmployeeInfo::dsl::EmployeeInfo;
 use crate::schema::EmployeeLogins::dsl::EmployeeLogins;
 use crate::schema::EmployeeSessions::dsl::EmployeeSessions;
@@ -25,6 +33,18 @@ use crate::schema::Users::{hash, id, username};
 
 pub struct Actions;
 
+no_arg_sql_function!(last_insert_id, diesel::sql_types::Unsigned<diesel::sql_types::Bigint>);
+
+const DEFAULT_PAGE_SIZE: i64 = 25;
+const MAX_PAGE_SIZE: i64 = 100;
+const REGISTRATION_CODE_VALIDITY_DAYS: i64 = 30;
+/// Minimum time between two registration codes requested by the citizen
+const REGISTRATION_CODE_RESEND_MINUTES: i64 = 10;
+/// Registration codes that may be requested from one address per hour, for any citizens
+const REGISTRATION_CODE_RESENDS_PER_ORIGIN: i64 = 5;
+
+type EmployeeListQuery<'a> = IntoBoxed<'a, LeftJoin<schema::EmployeeInfo::table, schema::EmployeeLogins::table>, Mysql>;
+
 fn insert_new_user(db: &MysqlConnection, credentials: &impl CredentialsHolder, uid: u64) -> UserRegistrationResult<()> {
     let salt = credentials.create_hash()?;
 
@@ -39,7 +59,19 @@ fn insert_new_user(db: &MysqlConnection, credentials: &impl CredentialsHolder, u
     Ok(())
 }
 
-fn authenticate_user(db: &MysqlConnection, credentials: &impl CredentialsHolder) -> AuthenticationResult<User> {
+fn authenticate_user(db: &MysqlConnection, credentials: &impl CredentialsHolder, origin: &RequestOrigin) -> AuthenticationResult<User> {
+    let result = verify_user_credentials(db, credentials);
+
+    let event = match &result {
+        Ok(user) => NewAuditEvent::from_result(AuditParty::Citizen(user.id), AuditEventType::CitizenAuthentication, &result),
+        Err(e) => NewAuditEvent::from_result(AuditParty::Anonymous, AuditEventType::CitizenAuthentication, &result)
+            .details(format!("{} (username: {})", e, credentials.get_key()))
+    };
+    try_record_event(db, &event.origin(origin));
+    result
+}
+
+fn verify_user_credentials(db: &MysqlConnection, credentials: &impl CredentialsHolder) -> AuthenticationResult<User> {
     let mut results = Users.filter(username.eq(credentials.get_key()))
         .load::<User>(db)
         .map_err(|e| AuthenticationError::Db(e.into()))?;
@@ -52,10 +84,10 @@ fn authenticate_user(db: &MysqlConnection, credentials: &impl CredentialsHolder)
         .ok_or(AuthenticationError::WrongPassword)
 }
 
-fn insert_user_session(db: &MysqlConnection, user: &User) -> SessionInsertionResult<NewSession> {
+fn insert_user_session(db: &MysqlConnection, user: &User, lifetime: chrono::Duration) -> SessionInsertionResult<NewSession> {
     use crate::schema::Sessions::{expires, token, user_id};
 
-    let session = NewSession::new()?;
+    let session = NewSession::new(lifetime)?;
     insert_into(Sessions)
         .values((user_id.eq(&user.id), token.eq(&session.token), expires.eq(&session.expires)))
         .execute(db)
@@ -81,103 +113,439 @@ pub fn check_user_session_token(db: &MysqlConnection, _token: &Token) -> Session
     use crate::schema::Sessions::{expires, token, user_id};
 
     let session: UserSession = Sessions.filter(token.eq(_token))
-        .first(db)
-        .map_err(|err| SessionRetrievalError::Db(err.into()))?;
+        .first(db)?;
 
     session
         .is_valid()
         .then(|| true)
         .ok_or(SessionRetrievalError::InvalidSession)?;
 
-    Users.filter(id.eq(session.user_id))
+    let user: User = Users.filter(id.eq(session.user_id))
         .first(db)
-        .map_err(|err| SessionRetrievalError::Db(err.into()))
+        .map_err(|err| SessionRetrievalError::Db(err.into()))?;
+
+    (!user.locked)
+        .then(|| user)
+        .ok_or(SessionRetrievalError::Locked)
 }
 
-pub fn insert_new_pending_user(db: &MysqlConnection, citizen_id: i64) -> Result<Token, DatabaseError> {
-    use crate::schema::PendingUsers::{citizen, code};
+/// Creates a registration code for the citizen, `email` is the address the code is sent to
+pub fn insert_new_pending_user(db: &MysqlConnection, format: &RegistrationCodeFormat, citizen_id: i64, email: Option<&str>) -> Result<Token, DatabaseError> {
+    use crate::schema::PendingUsers::{citizen, code, email as pending_email, expires as pending_expires, issued};
 
-    let pending_code = User::generate_pending_code();
+    let pending_code = format.generate();
+    let now = Utc::now().naive_utc();
 
     insert_into(PendingUsers)
-        .values((citizen.eq(&citizen_id), code.eq(&pending_code)))
+        .values((citizen.eq(&citizen_id),
+                 code.eq(&pending_code),
+                 pending_email.eq(email),
+                 issued.eq(now),
+                 pending_expires.eq(now + chrono::Duration::days(REGISTRATION_CODE_VALIDITY_DAYS))))
         .execute(db)?;
 
     Ok(pending_code)
 }
-pub fn check_pending_user_token(db: &MysqlConnection, _token: &str) -> Result<PendingUser, diesel::result::Error> {
+
+/// Registration code for a citizen announced by the citizen registry and whether it was created by this call.
+/// Repeated announcements of the same citizen return the code that already exists unless it expired,
+/// citizens who are already registered get none
+pub fn ensure_pending_user(db: &MysqlConnection, format: &RegistrationCodeFormat, citizen_id: u64, email: Option<&str>) -> Result<Option<(Token, bool)>, DatabaseError> {
+    use crate::schema::PendingUsers::citizen;
+
+    db.transaction::<_, DatabaseError, _>(|| {
+        let registered = diesel::dsl::select(diesel::dsl::exists(Users.find(citizen_id)))
+            .get_result::<bool>(db)?;
+        if registered {
+            return Ok(None);
+        }
+
+        let existing: Option<PendingUser> = PendingUsers
+            .filter(citizen.eq(citizen_id as i64))
+            .for_update()
+            .first(db)
+            .optional()?;
+        match existing {
+            Some(pending) if pending.is_valid() => Ok(Some((pending.code, false))),
+            Some(_) => {
+                diesel::delete(PendingUsers.filter(citizen.eq(citizen_id as i64)))
+                    .execute(db)?;
+                Ok(Some((insert_new_pending_user(db, format, citizen_id as i64, email)?, true)))
+            }
+            None => Ok(Some((insert_new_pending_user(db, format, citizen_id as i64, email)?, true)))
+        }
+    })
+}
+
+/// The pending registration of the code, locked until the end of the transaction
+pub fn check_pending_user_token(db: &MysqlConnection, format: &RegistrationCodeFormat, _token: &str) -> UserRegistrationResult<PendingUser> {
     use crate::schema::PendingUsers::code;
-    let pending_user: PendingUser = PendingUsers.filter(code.eq(_token))
-        .first(db)?;
+    //Codes in an older format are looked up as they are
+    let normalized = format.normalize(_token).unwrap_or_else(|| _token.to_string());
+    let pending_user: PendingUser = PendingUsers.filter(code.eq(&normalized))
+        .for_update()
+        .first(db)
+        .map_err(|err| match err {
+            diesel::result::Error::NotFound => UserRegistrationError::InvalidCitizenCode,
+            e => UserRegistrationError::Db(e.into())
+        })?;
 
-    Ok(pending_user)
+    pending_user.is_valid()
+        .then(|| pending_user)
+        .ok_or(UserRegistrationError::ExpiredCitizenCode)
 }
 
-pub fn register_user(db: &MysqlConnection, request: &UserRegistrationRequest) -> UserRegistrationResult<()> {
-    let pending_user = check_pending_user_token(db, &request.code)
-        .map_err(|err| UserRegistrationError::Db(err.into()))?;
+/// The pending registration of a citizen by citizen id, by the address the last code was sent to or by both.
+/// Codes sent by letter are only found by citizen id
+pub fn find_pending_user(db: &MysqlConnection, citizen_id: Option<u64>, address: Option<&str>) -> Result<Option<PendingUser>, DatabaseError> {
+    use crate::schema::PendingUsers::{citizen, email};
 
-    //TODO: Remove pending user
-    insert_new_user(db, &request.credentials, pending_user.citizen as u64)
+    let mut query = PendingUsers.into_boxed();
+    if let Some(citizen_id) = citizen_id {
+        query = query.filter(citizen.eq(citizen_id as i64));
+    }
+    if let Some(address) = address {
+        query = query.filter(email.eq(address.trim()));
+    }
+    Ok(query
+        .first(db)
+        .optional()?)
 }
 
-pub fn login_user(db: &MysqlConnection, request: &UserLoginRequest) -> LoginResult<UserLoginRequestResponse> {
-    let user = authenticate_user(db, &request.credentials)?;
+/// Fails if more than [REGISTRATION_CODE_RESENDS_PER_ORIGIN] codes were requested from the address within the last hour
+pub fn check_resend_origin(db: &MysqlConnection, origin: &RequestOrigin) -> UserRegistrationResult<()> {
+    let since = Utc::now().naive_utc() - chrono::Duration::hours(1);
+    let requested = count_events_from(db, AuditEventType::RegistrationCodeResent, origin.ip.as_deref(), since)?;
+    (requested < REGISTRATION_CODE_RESENDS_PER_ORIGIN)
+        .then(|| ())
+        .ok_or(UserRegistrationError::ResendThrottled)
+}
+
+/// Replaces the registration code of a citizen who asked for it again. Fails if the last code was issued
+/// less than [REGISTRATION_CODE_RESEND_MINUTES] ago
+pub fn renew_pending_user(db: &MysqlConnection, format: &RegistrationCodeFormat, citizen_id: u64, address: Option<&str>) -> UserRegistrationResult<Token> {
+    use crate::schema::PendingUsers::citizen;
+
+    db.transaction::<_, UserRegistrationError, _>(|| {
+        let pending: PendingUser = PendingUsers
+            .filter(citizen.eq(citizen_id as i64))
+            .first(db)
+            .optional()?
+            .ok_or(UserRegistrationError::InvalidCitizenCode)?;
+        pending.can_be_resent(chrono::Duration::minutes(REGISTRATION_CODE_RESEND_MINUTES))
+            .then(|| ())
+            .ok_or(UserRegistrationError::ResendThrottled)?;
 
-    let user_token = get_user_session(db, &user)
-        .map_or_else(|_| insert_user_session(db, &user).map_err(|e| LoginError::SessionInsertion(e)), |s| Ok(NewSession { token: s.token, expires: s.expires }))?;
-    
-    Ok(UserLoginRequestResponse{ user, new_session_token: user_token.token})
+        diesel::delete(PendingUsers.filter(citizen.eq(citizen_id as i64)))
+            .execute(db)?;
+        Ok(insert_new_pending_user(db, format, citizen_id as i64, address)?)
+    })
+}
+
+/// Creates the account of a citizen. The code can only be used once and only together with the address it was sent to.
+/// Codes issued without storing the address need the `directory_email` of the citizen, unless they were sent by letter
+pub fn register_user(db: &MysqlConnection, format: &RegistrationCodeFormat, request: &UserRegistrationRequest, directory_email: Option<&str>, origin: &RequestOrigin) -> UserRegistrationResult<()> {
+    let result = db.transaction::<_, UserRegistrationError, _>(|| {
+        let pending = check_pending_user_token(db, format, &request.code)?;
+        let citizen_id = pending.citizen as u64;
+        let bound = match pending.email.as_deref().or(directory_email) {
+            Some(address) => address.trim().eq_ignore_ascii_case(request.mail.trim()),
+            None => has_letter(db, citizen_id)?
+        };
+        bound.then(|| ())
+            .ok_or(UserRegistrationError::MailMismatch)?;
+
+        insert_new_user(db, &request.credentials, citizen_id)?;
+        diesel::delete(PendingUsers.find(pending.id))
+            .execute(db)?;
+        enqueue_event(db, &DomainEvent::CitizenAccountActivated { citizen_id })?;
+        Ok(citizen_id)
+    });
+
+    let actor = result.as_ref().map_or(AuditParty::Anonymous, |c| AuditParty::Citizen(*c));
+    try_record_event(db, &NewAuditEvent::from_result(actor, AuditEventType::CitizenRegistered, &result)
+        .origin(origin));
+
+    result.map(|_| ())
+}
+
+pub fn login_user(db: &MysqlConnection, request: &UserLoginRequest, session_lifetime: chrono::Duration, origin: &RequestOrigin) -> LoginResult<UserLoginRequestResponse> {
+    let user = authenticate_user(db, &request.credentials, origin)?;
+
+    let result = (!user.locked)
+        .then(|| ())
+        .ok_or(LoginError::Authentication(AuthenticationError::Locked))
+        .and_then(|_| get_user_session(db, &user)
+            .map_or_else(|_| insert_user_session(db, &user, session_lifetime).map_err(|e| LoginError::SessionInsertion(e)), |s| Ok(NewSession { token: s.token, expires: s.expires })));
+
+    try_record_event(db, &NewAuditEvent::from_result(AuditParty::Citizen(user.id), AuditEventType::CitizenLogin, &result)
+        .origin(origin));
+    if result.is_ok() {
+        try_enqueue_event(db, &DomainEvent::UserLoggedIn { citizen_id: user.id });
+    }
+
+    let session = result?;
+    Ok(UserLoginRequestResponse{ user, new_session_token: session.token, session_expires: session.expires })
 }
 
-pub async fn send_citizen_code(mail_client: &SmtpClient, citizen: &CitizenInfo, code: &Token) -> anyhow::Result<()>{
-    ensure!(citizen.email.is_some());
-    let mail_adress = citizen.email.clone().unwrap();
+/// The mail with the registration code of a citizen, in the language of the citizen if it is known
+pub fn citizen_code_mail(templates: &MailTemplates, citizen: &CitizenInfo, code: &Token) -> Result<Mail, MailSenderError> {
+    let address = citizen.email.as_deref().ok_or(MailSenderError::MissingRecipient)?;
     let name = format!("{} {}", citizen.firstname, citizen.lastname);
-    println!("Sending mail to: {}\n with name {}", mail_adress, name);
-
-    let email = EmailBuilder::new()
-        .to(mail_adress)
-        .from("support@mail.smartcityproject.net")
-        .subject("SmartCity: Ihr Registrierungscode")
-        .text(format!("Hallo {}! Ihr persönlicher Registrierungscode lautet: {}. Registrieren Sie sich unter: http://www.supersmartcity.de:9760", name, code))
-        .build()?;
-
-    let mut mailer = mail_client.clone().transport();
-    let result = mailer.send(email.into());
-    println!("Result: {:?}", result);
-    Ok(())
+
+    templates.render("citizen_code", citizen.language.as_deref(), address, &[
+        ("name", name.as_str()),
+        ("code", code.as_str()),
+    ])
 }
 
-pub fn register_employee(db: &MysqlConnection, employee_data: &NewEmployeeInfo, credentials: &CredentialsPair) -> UserRegistrationResult<()> {
-    use crate::schema::EmployeeInfo::{firstname, lastname};
-    use crate::schema::EmployeeInfo::dsl::EmployeeInfo;
-    use crate::schema::EmployeeLogins::{info_id, username, hash};
+/// How a registration code reaches the citizen
+pub enum CodeDelivery {
+    Mail(Mail),
+    /// PDF letter for the print office, for citizens without email address
+    Letter(Vec<u8>),
+}
 
-    insert_into(EmployeeInfo)
-        .values(employee_data)
-        .execute(db)
-        .map_err(|err| UserRegistrationError::Db(err.into()))?;
+pub fn citizen_code_delivery(templates: &MailTemplates, letters: &LetterRenderer, citizen: &CitizenInfo, code: &Token) -> CitizenAdministrationResult<CodeDelivery> {
+    Ok(match citizen.email {
+        Some(_) => CodeDelivery::Mail(citizen_code_mail(templates, citizen, code)?),
+        None => CodeDelivery::Letter(letters.render(citizen, code)?)
+    })
+}
 
-    let mut employees = EmployeeInfo
-        .filter(firstname.eq(&employee_data.firstname).and(lastname.eq(&employee_data.lastname)))
-        .limit(1)
-        .load::<EmployeeInfoModel>(db)
-        .map_err(|err| UserRegistrationError::Db(err.into()))?;
+/// Stores the code for delivery. A mail is only sent as long as the pending code of the citizen is valid
+pub fn queue_code_delivery(db: &MysqlConnection, citizen_id: u64, delivery: &CodeDelivery) -> Result<(), DatabaseError> {
+    use crate::schema::PendingUsers::{citizen, expires};
 
-    let employee_result = employees.pop().ok_or(UserRegistrationError::DataRetrieval)?;
+    match delivery {
+        CodeDelivery::Mail(mail) => {
+            let code_expires = PendingUsers
+                .filter(citizen.eq(citizen_id as i64))
+                .select(expires)
+                .first(db)?;
+            queue_mail(db, mail, code_expires)
+        }
+        CodeDelivery::Letter(letter) => queue_letter(db, citizen_id, letter)
+    }
+}
 
-    let new_hash = credentials.create_hash()?;
-    insert_into(EmployeeLogins)
-        .values((info_id.eq(&employee_result.id), username.eq(credentials.get_key()), hash.eq(&new_hash)))
-        .execute(db)
-        .map_err(|err| UserRegistrationError::Db(err.into()))?;
+/// Creates the registration code of a new citizen and queues its delivery in the same transaction.
+/// Returns the code only if it was created, repeated announcements of the citizen deliver nothing
+pub fn issue_citizen_code(db: &MysqlConnection, format: &RegistrationCodeFormat, templates: &MailTemplates, letters: &LetterRenderer, citizen: &CitizenInfo) -> CitizenAdministrationResult<Option<Token>> {
+    db.transaction::<_, CitizenAdministrationError, _>(|| {
+        match ensure_pending_user(db, format, citizen.citizen_id, citizen.email.as_deref())? {
+            Some((code, true)) => {
+                queue_code_delivery(db, citizen.citizen_id, &citizen_code_delivery(templates, letters, citizen, &code)?)?;
+                Ok(Some(code))
+            }
+            _ => Ok(None)
+        }
+    })
+}
 
-    Ok(())
+/// Replaces the registration code of a citizen whose email address changed and queues the new code for the new
+/// address in the same transaction, so that the old address can no longer be used. Codes issued without
+/// stored address are kept. Returns the new code if one was issued
+pub fn rebind_pending_user(db: &MysqlConnection, format: &RegistrationCodeFormat, templates: &MailTemplates, letters: &LetterRenderer, citizen: &CitizenInfo) -> CitizenAdministrationResult<Option<Token>> {
+    use crate::schema::PendingUsers::citizen as pending_citizen;
+
+    let citizen_id = citizen.citizen_id;
+    db.transaction::<_, CitizenAdministrationError, _>(|| {
+        let pending: Option<PendingUser> = PendingUsers
+            .filter(pending_citizen.eq(citizen_id as i64))
+            .for_update()
+            .first(db)
+            .optional()?;
+        let changed = match pending.and_then(|p| p.email) {
+            Some(address) => Some(address.as_str()) != citizen.email.as_deref(),
+            None => false
+        };
+        if !changed {
+            return Ok(None);
+        }
+
+        diesel::delete(PendingUsers.filter(pending_citizen.eq(citizen_id as i64)))
+            .execute(db)?;
+        let code = insert_new_pending_user(db, format, citizen_id as i64, citizen.email.as_deref())?;
+        queue_code_delivery(db, citizen_id, &citizen_code_delivery(templates, letters, citizen, &code)?)?;
+        Ok(Some(code))
+    })
+}
+
+/// Whether at least one employee login exists, as long as there is none the bootstrap token may be used
+pub fn has_employees(db: &MysqlConnection) -> Result<bool, DatabaseError> {
+    use crate::schema::EmployeeLogins::id;
+
+    Ok(diesel::dsl::select(diesel::dsl::exists(EmployeeLogins.select(id)))
+        .get_result(db)?)
+}
+
+pub fn invite_employee(db: &MysqlConnection, employee_data: &NewEmployeeInfo, mail: &str, inviter: Option<u64>, validity: chrono::Duration, origin: &RequestOrigin) -> UserRegistrationResult<EmployeeInvitation> {
+    use crate::schema::EmployeeInvitations::dsl::EmployeeInvitations;
+    use crate::schema::EmployeeInvitations::{info_id, token, email, invited_by, expires};
+
+    let invitation_token = create_token();
+    let invitation_expires = Utc::now()
+        .naive_utc()
+        .checked_add_signed(validity)
+        .ok_or(UserRegistrationError::DataRetrieval)?;
+
+    let result = db.transaction::<_, UserRegistrationError, _>(|| {
+        insert_into(EmployeeInfo)
+            .values(employee_data)
+            .execute(db)?;
+
+        let new_info_id: u64 = diesel::select(last_insert_id)
+            .get_result(db)?;
+
+        insert_into(EmployeeInvitations)
+            .values((info_id.eq(&new_info_id),
+                     token.eq(&invitation_token),
+                     email.eq(mail),
+                     invited_by.eq(&inviter),
+                     expires.eq(&invitation_expires)))
+            .execute(db)?;
+
+        Ok(EmployeeInvitations
+            .filter(token.eq(&invitation_token))
+            .first(db)?)
+    });
+
+    let actor = inviter.map_or(AuditParty::System, AuditParty::Employee);
+    let event = NewAuditEvent::from_result(actor, AuditEventType::EmployeeInvited, &result);
+    let event = match &result {
+        Ok(invitation) => event.details(format!("info_id: {}", invitation.info_id)),
+        Err(_) => event
+    };
+    try_record_event(db, &event.origin(origin));
+    result
+}
+
+pub fn get_employee_invitation(db: &MysqlConnection, _token: &Token) -> UserRegistrationResult<EmployeeInvitation> {
+    use crate::schema::EmployeeInvitations::dsl::EmployeeInvitations;
+    use crate::schema::EmployeeInvitations::token;
+
+    let invitation: EmployeeInvitation = EmployeeInvitations
+        .filter(token.eq(_token))
+        .first(db)
+        .map_err(|err| match err {
+            diesel::result::Error::NotFound => UserRegistrationError::InvalidInvitation,
+            e => UserRegistrationError::Db(e.into())
+        })?;
+
+    invitation
+        .is_valid()
+        .then(|| invitation)
+        .ok_or(UserRegistrationError::InvalidInvitation)
+}
+
+/// The TOTP secret of a valid invitation. It is created on the first request and stays the same afterwards,
+/// so that reloading the invitation page does not invalidate a secret that was already added to an app
+pub fn employee_invitation_totp_secret(db: &MysqlConnection, _token: &Token) -> UserRegistrationResult<(EmployeeInvitation, String)> {
+    use crate::schema::EmployeeInvitations::dsl::EmployeeInvitations;
+    use crate::schema::EmployeeInvitations::totp_secret;
+
+    db.transaction::<_, UserRegistrationError, _>(|| {
+        let invitation = get_employee_invitation(db, _token)?;
+        let secret = match &invitation.totp_secret {
+            Some(secret) => secret.clone(),
+            None => {
+                let secret = Totp::generate_secret();
+                diesel::update(EmployeeInvitations.find(invitation.id))
+                    .set(totp_secret.eq(&secret))
+                    .execute(db)?;
+                secret
+            }
+        };
+        Ok((invitation, secret))
+    })
+}
+
+pub fn employee_invitation_mail(templates: &MailTemplates, info: &NewEmployeeInfo, invitation: &EmployeeInvitation, link: &str, locale: Option<&str>) -> Result<Mail, MailSenderError> {
+    let name = format!("{} {}", info.firstname, info.lastname);
+    let expires = invitation.expires.format("%d.%m.%Y %H:%M").to_string();
+
+    templates.render("employee_invitation", locale, &invitation.email, &[
+        ("name", name.as_str()),
+        ("expires", expires.as_str()),
+        ("link", link),
+    ])
 }
 
-pub fn login_employee(db: &MysqlConnection, credentials: &CredentialsPair) -> LoginResult<EmployeeLoginRequestResponse> {
-    use schema::EmployeeLogins::{username};
+/// Redeems an invitation: creates the login the invited employee chose and removes the invitation,
+/// so every invitation link can only be used once
+pub fn register_employee(db: &MysqlConnection, request: &EmployeeRegisterRequest, origin: &RequestOrigin) -> UserRegistrationResult<EmployeeLogin> {
+    let result = request.credentials.create_hash()
+        .map_err(UserRegistrationError::from)
+        .and_then(|new_hash| redeem_employee_invitation(db, request, &new_hash));
+
+    let event = match &result {
+        Ok((employee, inviter)) => NewAuditEvent::from_result(inviter.map_or(AuditParty::System, AuditParty::Employee), AuditEventType::EmployeeRegistered, &result)
+            .subject(AuditParty::Employee(employee.id)),
+        Err(e) => NewAuditEvent::from_result(AuditParty::Anonymous, AuditEventType::EmployeeRegistered, &result)
+            .details(format!("{} (username: {})", e, request.credentials.get_key()))
+    };
+    try_record_event(db, &event.origin(origin));
+
+    result.map(|(employee, _)| employee)
+}
+
+/// Returns the new employee together with the employee that sent the invitation. The second factor is the
+/// secret stored with the invitation, the request has to contain a current code for it
+fn redeem_employee_invitation(db: &MysqlConnection, request: &EmployeeRegisterRequest, new_hash: &str) -> UserRegistrationResult<(EmployeeLogin, Option<u64>)> {
+    use crate::schema::EmployeeInvitations::dsl::EmployeeInvitations;
+    use crate::schema::EmployeeLogins::{info_id, username, hash, totp_secret};
+
+    db.transaction::<_, UserRegistrationError, _>(|| {
+        let invitation = get_employee_invitation(db, &request.invitation)?;
+        let secret = invitation.totp_secret.as_deref()
+            .filter(|secret| Totp::verify_code(secret, &request.totp_code))
+            .ok_or(UserRegistrationError::InvalidSecondFactor)?;
+        // Only the very first employee, invited with the bootstrap token, becomes administrator
+        let first_admin = invitation.invited_by.is_none() && !has_employees(db)?;
+
+        insert_into(EmployeeLogins)
+            .values((info_id.eq(&invitation.info_id),
+                     username.eq(request.credentials.get_key()),
+                     hash.eq(new_hash),
+                     totp_secret.eq(Some(secret))))
+            .execute(db)?;
+
+        diesel::delete(EmployeeInvitations.find(invitation.id))
+            .execute(db)?;
+
+        let employee: EmployeeLogin = EmployeeLogins
+            .filter(username.eq(request.credentials.get_key()))
+            .first(db)?;
+
+        if first_admin {
+            use crate::schema::EmployeeRoles::dsl::EmployeeRoles;
+            use crate::schema::EmployeeRoles::{e_id, role};
+
+            insert_into(EmployeeRoles)
+                .values((e_id.eq(employee.id), role.eq(Role::Admin.as_str())))
+                .execute(db)?;
+        }
+
+        enqueue_event(db, &DomainEvent::EmployeeCreated { employee_id: employee.id })?;
+        Ok((employee, invitation.invited_by))
+    })
+}
+
+pub fn login_employee(db: &MysqlConnection, credentials: &CredentialsPair, otp: Option<&str>, session_lifetime: chrono::Duration, origin: &RequestOrigin) -> LoginResult<EmployeeLoginRequestResponse> {
+    let result = start_employee_session(db, credentials, otp, session_lifetime);
+
+    let event = match &result {
+        Ok(r) => NewAuditEvent::from_result(AuditParty::Employee(r.employee.id), AuditEventType::EmployeeLogin, &result),
+        Err(e) => NewAuditEvent::from_result(AuditParty::Anonymous, AuditEventType::EmployeeLogin, &result)
+            .details(format!("{} (username: {})", e, credentials.get_key()))
+    };
+    try_record_event(db, &event.origin(origin));
+    result
+}
+
+fn start_employee_session(db: &MysqlConnection, credentials: &CredentialsPair, otp: Option<&str>, session_lifetime: chrono::Duration) -> LoginResult<EmployeeLoginRequestResponse> {
+    use schema::EmployeeLogins::{username, last_login};
     use schema::EmployeeSessions;
     use schema::EmployeeSessions::{e_id, token, expires};
     use crate::diesel::BelongingToDsl;
@@ -194,29 +562,43 @@ pub fn login_employee(db: &MysqlConnection, credentials: &CredentialsPair) -> Lo
         .map_err(|e| LoginError::Authentication(AuthenticationError::Verification(e)))?
         .then(|| true).ok_or(LoginError::Authentication(AuthenticationError::UserNotFound))?;
 
+    (!emp_result.disabled)
+        .then(|| true)
+        .ok_or(LoginError::Authentication(AuthenticationError::Disabled))?;
+
+    if let Some(secret) = &emp_result.totp_secret {
+        let code = otp.ok_or(LoginError::Authentication(AuthenticationError::MissingSecondFactor))?;
+        Totp::verify_code(secret, code)
+            .then(|| true)
+            .ok_or(LoginError::Authentication(AuthenticationError::WrongSecondFactor))?;
+    }
+
+    diesel::update(EmployeeLogins.find(emp_result.id))
+        .set(last_login.eq(Utc::now().naive_utc()))
+        .execute(db)
+        .map_err(|e| LoginError::Db(e.into()))?;
 
     let sessions_result: Result<Vec<EmployeeSession>, diesel::result::Error>= EmployeeSession::belonging_to(&emp_result)
         .load(db);
 
     if let Ok(sessions) = sessions_result {
-        let session_count = sessions.len();
-        if session_count > 0 {
-            let s = &sessions[session_count - 1];
-            println!("Found a session...");
-            if s.is_valid(){
-                println!("Session is valid, returning");
+        if let Some(s) = sessions.last() {
+            if s.is_valid() {
+                debug!("Reusing the session of employee {}", emp_result.id);
                 return Ok(EmployeeLoginRequestResponse {
                     employee: emp_result.clone(),
-                    new_employee_token: s.token.clone()
+                    new_employee_token: s.token.clone(),
+                    session_expires: s.expires
                 });
             }
 
-            println!("Expiration: {:?} current: {:?}", s.expires, Utc::now().naive_utc());
-            println!("Session is invalid, returning a new one");
-            //TODO: Remove invalid session
+            debug!("Session of employee {} expired at {:?}, creating a new one", emp_result.id, s.expires);
+            diesel::delete(EmployeeSessions.filter(e_id.eq(emp_result.id)).filter(expires.lt(Utc::now().naive_utc())))
+                .execute(db)
+                .map_err(|e| LoginError::Db(e.into()))?;
         }
     }
-    let session = NewSession::new()?;
+    let session = NewSession::new(session_lifetime)?;
     insert_into(EmployeeSessions)
         .values((e_id.eq(&emp_result.id), token.eq(&session.token), expires.eq(&session.expires)))
         .execute(db)
@@ -224,7 +606,8 @@ pub fn login_employee(db: &MysqlConnection, credentials: &CredentialsPair) -> Lo
 
     Ok(EmployeeLoginRequestResponse{
         employee: emp_result,
-        new_employee_token: session.token
+        new_employee_token: session.token,
+        session_expires: session.expires
     })
 }
 
@@ -235,17 +618,21 @@ pub fn verify_employee(db: &MysqlConnection, _token: &Token) -> SessionRetrieval
     use schema::EmployeeLogins::{id};
 
     let session: EmployeeSession = EmployeeSessions.filter(token.eq(_token))
-        .first(db)
-        .map_err(|err| SessionRetrievalError::Db(err.into()))?;
+        .first(db)?;
 
-    let employee = EmployeeLogins.filter(id.eq(&session.e_id))
+    let employee: EmployeeLogin = EmployeeLogins.filter(id.eq(&session.e_id))
         .first(db)
         .map_err(|e| SessionRetrievalError::Db(e.into()))?;
 
+    (!employee.disabled)
+        .then(|| true)
+        .ok_or(SessionRetrievalError::Disabled)?;
+
     session.is_valid().then(|| {
         EmployeeLoginRequestResponse {
             employee,
-            new_employee_token: session.token
+            new_employee_token: session.token,
+            session_expires: session.expires
         }
     }).ok_or(SessionRetrievalError::InvalidSession)
 }
@@ -258,3 +645,411 @@ pub fn get_employee_info(db: &MysqlConnection, employee: &EmployeeLogin) -> Sess
         .first(db)
         .map_err(|err| SessionRetrievalError::Db(err.into()))
 }
+
+pub fn get_employee_roles(db: &MysqlConnection, employee: &EmployeeLogin) -> Result<Vec<Role>, DatabaseError> {
+    use crate::diesel::BelongingToDsl;
+
+    let roles: Vec<EmployeeRole> = EmployeeRole::belonging_to(employee)
+        .load(db)?;
+
+    Ok(roles
+        .iter()
+        .filter_map(|r| r.role.parse().ok())
+        .collect())
+}
+
+/// Verifies the session token and checks that the employee holds the given role, admins hold every role
+pub fn authorize_employee(db: &MysqlConnection, _token: &Token, role: Role) -> SessionRetrievalResult<EmployeeLogin> {
+    let employee = verify_employee(db, _token)?.employee;
+    require_role(db, employee, role)
+}
+
+/// Makes sure an already verified employee has the role, administrators have every role
+pub fn require_role(db: &MysqlConnection, employee: EmployeeLogin, role: Role) -> SessionRetrievalResult<EmployeeLogin> {
+    let roles = get_employee_roles(db, &employee)?;
+
+    (roles.contains(&role) || roles.contains(&Role::Admin))
+        .then(|| employee)
+        .ok_or(SessionRetrievalError::MissingPermission)
+}
+
+pub fn set_employee_role(db: &MysqlConnection, employee_id: u64, role: Role, granted: bool) -> EmployeeAdministrationResult<()> {
+    use crate::schema::EmployeeRoles::dsl::EmployeeRoles;
+    use crate::schema::EmployeeRoles::{e_id, role as role_name};
+
+    let existing = diesel::dsl::select(diesel::dsl::exists(EmployeeRoles.filter(e_id.eq(employee_id).and(role_name.eq(role.as_str())))))
+        .get_result::<bool>(db)?;
+
+    match (granted, existing) {
+        (true, false) => {
+            insert_into(EmployeeRoles)
+                .values((e_id.eq(employee_id), role_name.eq(role.as_str())))
+                .execute(db)?;
+        }
+        (false, true) => {
+            diesel::delete(EmployeeRoles.filter(e_id.eq(employee_id).and(role_name.eq(role.as_str()))))
+                .execute(db)?;
+        }
+        _ => {}
+    };
+    Ok(())
+}
+
+/// Disabled employees can neither log in nor use existing sessions, disabling also removes all of their sessions
+pub fn set_employee_disabled(db: &MysqlConnection, employee_id: u64, is_disabled: bool) -> EmployeeAdministrationResult<()> {
+    use crate::schema::EmployeeLogins::disabled;
+    use crate::schema::EmployeeSessions::dsl::EmployeeSessions;
+    use crate::schema::EmployeeSessions::e_id;
+
+    db.transaction::<_, EmployeeAdministrationError, _>(|| {
+        let updated = diesel::update(EmployeeLogins.find(employee_id))
+            .set(disabled.eq(is_disabled))
+            .execute(db)?;
+        (updated > 0)
+            .then(|| ())
+            .ok_or(EmployeeAdministrationError::EmployeeNotFound)?;
+
+        if is_disabled {
+            diesel::delete(EmployeeSessions.filter(e_id.eq(employee_id)))
+                .execute(db)?;
+            enqueue_event(db, &DomainEvent::AccountLocked { account_type: AccountType::Employee, account_id: employee_id })?;
+        }
+        Ok(())
+    })
+}
+
+/// Permanently removes an employee, their logins, sessions, roles and invitations are removed by the database
+pub fn delete_employee(db: &MysqlConnection, employee_id: u64) -> EmployeeAdministrationResult<()> {
+    let employee: EmployeeLogin = EmployeeLogins
+        .find(employee_id)
+        .first(db)?;
+
+    diesel::delete(EmployeeInfo.find(employee.info_id))
+        .execute(db)?;
+    Ok(())
+}
+
+/// Returns the requested page and page size, clamped to sensible values
+pub fn page_bounds(page: Option<i64>, per_page: Option<i64>) -> (i64, i64) {
+    (page.unwrap_or(1).max(1), per_page.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE))
+}
+
+/// LIKE pattern matching values that contain `term`, wildcards in the term are matched literally
+pub fn contains_pattern(term: &str) -> String {
+    let escaped = term
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+fn employee_list_query(request: &EmployeeListRequest) -> EmployeeListQuery<'static> {
+    use crate::schema::EmployeeInfo::{department, firstname, lastname};
+    use crate::schema::EmployeeLogins::{disabled, id as login_id, username};
+    use crate::schema::EmployeeRoles;
+
+    let mut query = schema::EmployeeInfo::table
+        .left_join(schema::EmployeeLogins::table)
+        .into_boxed();
+
+    if let Some(search) = &request.search {
+        let pattern = contains_pattern(search);
+        query = query.filter(firstname.like(pattern.clone())
+            .or(lastname.like(pattern.clone()))
+            .or(username.like(pattern)));
+    }
+    if let Some(d) = &request.department {
+        query = query.filter(department.eq(d.clone()));
+    }
+    if let Some(status) = request.status {
+        query = match status {
+            EmployeeStatus::Invited => query.filter(login_id.is_null()),
+            EmployeeStatus::Active => query.filter(disabled.eq(false)),
+            EmployeeStatus::Disabled => query.filter(disabled.eq(true)),
+        };
+    }
+    if let Some(r) = request.role {
+        query = query.filter(login_id.eq_any(EmployeeRoles::table
+            .select(EmployeeRoles::e_id)
+            .filter(EmployeeRoles::role.eq(r.as_str()))));
+    }
+    query
+}
+
+/// Loads one page of employees matching the filters of the request, together with the total number of matches
+pub fn list_employees(db: &MysqlConnection, request: &EmployeeListRequest) -> Result<(i64, Vec<(EmployeeInfoModel, Option<EmployeeLogin>)>), DatabaseError> {
+    use crate::schema::EmployeeInfo::id as info_id;
+
+    let (page, per_page) = page_bounds(request.page, request.per_page);
+
+    let total: i64 = employee_list_query(request)
+        .count()
+        .get_result(db)?;
+
+    let employees = employee_list_query(request)
+        .order(info_id.asc())
+        .limit(per_page)
+        .offset((page - 1) * per_page)
+        .load::<(EmployeeInfoModel, Option<EmployeeLogin>)>(db)?;
+
+    Ok((total, employees))
+}
+
+/// Adds roles and the number of active sessions to a page of employees, with one query each for the whole page
+pub fn describe_employees(db: &MysqlConnection, employees: Vec<(EmployeeInfoModel, Option<EmployeeLogin>)>) -> Result<Vec<EmployeeListEntry>, DatabaseError> {
+    use crate::schema::EmployeeRoles::dsl::EmployeeRoles;
+    use crate::schema::EmployeeRoles::e_id as role_e_id;
+    use crate::schema::EmployeeSessions::dsl::EmployeeSessions;
+    use crate::schema::EmployeeSessions::{e_id, expires};
+
+    let ids: Vec<u64> = employees.iter().filter_map(|(_, login)| login.as_ref().map(|l| l.id)).collect();
+
+    let roles: Vec<EmployeeRole> = EmployeeRoles
+        .filter(role_e_id.eq_any(&ids))
+        .load(db)?;
+    let active_sessions: Vec<(u64, i64)> = EmployeeSessions
+        .filter(e_id.eq_any(&ids).and(expires.ge(Utc::now().naive_utc())))
+        .group_by(e_id)
+        .select((e_id, diesel::dsl::count_star()))
+        .load(db)?;
+
+    Ok(employees
+        .into_iter()
+        .map(|(info, login)| {
+            let employee_id = login.as_ref().map(|l| l.id);
+            EmployeeListEntry {
+                info_id: info.id,
+                employee_id,
+                username: login.as_ref().map(|l| l.username.clone()),
+                roles: roles.iter()
+                    .filter(|r| Some(r.e_id) == employee_id)
+                    .filter_map(|r| r.role.parse().ok())
+                    .collect(),
+                status: EmployeeStatus::of(login.as_ref()),
+                last_login: login.as_ref().and_then(|l| l.last_login),
+                active_sessions: active_sessions.iter()
+                    .find(|(id, _)| Some(*id) == employee_id)
+                    .map_or(0, |(_, count)| *count),
+                info: info.into()
+            }
+        })
+        .collect())
+}
+
+fn find_user(db: &MysqlConnection, citizen_id: Option<u64>, name: Option<&str>) -> CitizenAdministrationResult<Option<User>> {
+    let user = match (citizen_id, name) {
+        (Some(c_id), _) => Users.find(c_id).first(db),
+        (None, Some(n)) => Users.filter(username.eq(n)).first(db),
+        (None, None) => return Err(CitizenAdministrationError::MissingIdentifier)
+    };
+
+    match user {
+        Ok(u) => Ok(Some(u)),
+        Err(diesel::result::Error::NotFound) => Ok(None),
+        Err(e) => Err(e.into())
+    }
+}
+
+/// Collects everything support staff needs to know about a citizen account,
+/// citizens that only have a pending registration code are reported without username
+pub fn find_citizen_account(db: &MysqlConnection, citizen_id: Option<u64>, name: Option<&str>) -> CitizenAdministrationResult<CitizenAccountResponse> {
+    use crate::diesel::BelongingToDsl;
+    use crate::schema::PendingUsers::citizen;
+
+    let user = find_user(db, citizen_id, name)?;
+    let c_id = user.as_ref().map(|u| u.id).or(citizen_id).ok_or(CitizenAdministrationError::CitizenNotFound)?;
+
+    let pending_registration = diesel::dsl::select(diesel::dsl::exists(PendingUsers.filter(citizen.eq(c_id as i64))))
+        .get_result::<bool>(db)?;
+
+    let user = match user {
+        Some(u) => u,
+        None if pending_registration => return Ok(CitizenAccountResponse {
+            citizen_id: c_id,
+            username: None,
+            locked: false,
+            pending_registration,
+            sessions: vec![]
+        }),
+        None => return Err(CitizenAdministrationError::CitizenNotFound)
+    };
+
+    let sessions: Vec<UserSession> = UserSession::belonging_to(&user)
+        .load(db)?;
+
+    Ok(CitizenAccountResponse {
+        citizen_id: user.id,
+        username: Some(user.username),
+        locked: user.locked,
+        pending_registration,
+        sessions: sessions
+            .iter()
+            .map(|s| CitizenSessionEntry { id: s.id, expires: s.expires, valid: s.is_valid() })
+            .collect()
+    })
+}
+
+pub fn logout_user(db: &MysqlConnection, citizen_id: u64) -> CitizenAdministrationResult<usize> {
+    use crate::schema::Sessions::user_id;
+
+    Users.find(citizen_id).first::<User>(db)?;
+    Ok(diesel::delete(Sessions.filter(user_id.eq(citizen_id)))
+        .execute(db)?)
+}
+
+/// Locked citizens can neither log in nor use existing sessions, locking also removes all of their sessions
+pub fn set_user_locked(db: &MysqlConnection, citizen_id: u64, is_locked: bool) -> CitizenAdministrationResult<()> {
+    use crate::schema::Users::locked;
+
+    db.transaction::<_, CitizenAdministrationError, _>(|| {
+        let updated = diesel::update(Users.find(citizen_id))
+            .set(locked.eq(is_locked))
+            .execute(db)?;
+        (updated > 0)
+            .then(|| ())
+            .ok_or(CitizenAdministrationError::CitizenNotFound)?;
+
+        if is_locked {
+            logout_user(db, citizen_id)?;
+            enqueue_event(db, &DomainEvent::AccountLocked { account_type: AccountType::Citizen, account_id: citizen_id })?;
+        }
+        Ok(())
+    })
+}
+
+/// Locks the account of a citizen who died or moved away and removes everything that would still grant access.
+/// The account itself is kept, it may still be referenced by other services
+pub fn deactivate_citizen(db: &MysqlConnection, citizen_id: u64, reason: &str) -> Result<(), DatabaseError> {
+    use crate::schema::PasswordResets::dsl::PasswordResets;
+    use crate::schema::Users::locked;
+
+    db.transaction::<_, DatabaseError, _>(|| {
+        let locked_accounts = diesel::update(Users.find(citizen_id))
+            .set(locked.eq(true))
+            .execute(db)?;
+        diesel::delete(Sessions.filter(schema::Sessions::user_id.eq(citizen_id)))
+            .execute(db)?;
+        diesel::delete(PasswordResets.filter(schema::PasswordResets::user_id.eq(citizen_id)))
+            .execute(db)?;
+        let pending_codes = diesel::delete(PendingUsers.filter(schema::PendingUsers::citizen.eq(citizen_id as i64)))
+            .execute(db)?;
+
+        if locked_accounts > 0 {
+            enqueue_event(db, &DomainEvent::AccountLocked { account_type: AccountType::Citizen, account_id: citizen_id })?;
+        }
+        if locked_accounts + pending_codes > 0 {
+            record_event(db, &NewAuditEvent::new(AuditParty::System, AuditEventType::CitizenDeactivated, AuditOutcome::Success)
+                .subject(AuditParty::Citizen(citizen_id))
+                .details(reason))?;
+        }
+        Ok(())
+    })
+}
+
+/// Deletes the account of a citizen who was removed from the citizen registry, including sessions and open registration codes
+pub fn delete_citizen(db: &MysqlConnection, citizen_id: u64) -> Result<(), DatabaseError> {
+    db.transaction::<_, DatabaseError, _>(|| {
+        let pending_codes = diesel::delete(PendingUsers.filter(schema::PendingUsers::citizen.eq(citizen_id as i64)))
+            .execute(db)?;
+        //Sessions and password resets are removed by the database
+        let accounts = diesel::delete(Users.find(citizen_id))
+            .execute(db)?;
+
+        if accounts + pending_codes > 0 {
+            record_event(db, &NewAuditEvent::new(AuditParty::System, AuditEventType::CitizenDeleted, AuditOutcome::Success)
+                .subject(AuditParty::Citizen(citizen_id)))?;
+        }
+        Ok(())
+    })
+}
+
+pub fn create_password_reset(db: &MysqlConnection, citizen_id: u64, validity: chrono::Duration) -> CitizenAdministrationResult<PasswordReset> {
+    use crate::schema::PasswordResets::dsl::PasswordResets;
+    use crate::schema::PasswordResets::{user_id, token, expires};
+
+    let user: User = Users.find(citizen_id).first(db)?;
+    let reset_token = create_token();
+    let reset_expires = Utc::now().naive_utc() + validity;
+
+    insert_into(PasswordResets)
+        .values((user_id.eq(user.id), token.eq(&reset_token), expires.eq(&reset_expires)))
+        .execute(db)?;
+
+    Ok(PasswordResets
+        .filter(token.eq(&reset_token))
+        .first(db)?)
+}
+
+/// Sets a new password using a reset token, the token and all sessions of the citizen are removed afterwards
+pub fn reset_password(db: &MysqlConnection, reset_token: &Token, password: &str) -> UserRegistrationResult<u64> {
+    use crate::schema::PasswordResets::dsl::PasswordResets;
+    use crate::schema::PasswordResets::token;
+    use crate::schema::Sessions::user_id;
+
+    let new_hash = hash_secret(password)?;
+
+    db.transaction::<_, UserRegistrationError, _>(|| {
+        let reset: PasswordReset = PasswordResets
+            .filter(token.eq(reset_token))
+            .first(db)
+            .map_err(|err| match err {
+                diesel::result::Error::NotFound => UserRegistrationError::InvalidResetToken,
+                e => UserRegistrationError::Db(e.into())
+            })?;
+        reset.is_valid()
+            .then(|| ())
+            .ok_or(UserRegistrationError::InvalidResetToken)?;
+
+        diesel::update(Users.find(reset.user_id))
+            .set(hash.eq(&new_hash))
+            .execute(db)?;
+        diesel::delete(PasswordResets.filter(crate::schema::PasswordResets::user_id.eq(reset.user_id)))
+            .execute(db)?;
+        diesel::delete(Sessions.filter(user_id.eq(reset.user_id)))
+            .execute(db)?;
+        enqueue_event(db, &DomainEvent::PasswordChanged { citizen_id: reset.user_id })?;
+
+        Ok(reset.user_id)
+    })
+}
+
+/// Replaces the registration code of a citizen that did not register yet
+pub fn reissue_pending_user(db: &MysqlConnection, format: &RegistrationCodeFormat, citizen_id: u64, email: Option<&str>) -> CitizenAdministrationResult<Token> {
+    use crate::schema::PendingUsers::citizen;
+
+    db.transaction::<_, CitizenAdministrationError, _>(|| {
+        let registered = diesel::dsl::select(diesel::dsl::exists(Users.find(citizen_id)))
+            .get_result::<bool>(db)?;
+        (!registered)
+            .then(|| ())
+            .ok_or(CitizenAdministrationError::AlreadyRegistered)?;
+
+        diesel::delete(PendingUsers.filter(citizen.eq(citizen_id as i64)))
+            .execute(db)?;
+        Ok(insert_new_pending_user(db, format, citizen_id as i64, email)?)
+    })
+}
+
+pub fn password_reset_mail(templates: &MailTemplates, citizen: &CitizenInfo, link: &str) -> Result<Mail, MailSenderError> {
+    let address = citizen.email.as_deref().ok_or(MailSenderError::MissingRecipient)?;
+    let name = format!("{} {}", citizen.firstname, citizen.lastname);
+
+    templates.render("password_reset", citizen.language.as_deref(), address, &[
+        ("name", name.as_str()),
+        ("link", link),
+    ])
+}
+
+/// Sends a failed mail again. Sent and expired mails can not be sent again, their content is no longer stored
+pub fn resend_mail(db: &MysqlConnection, mail_id: u64) -> EmployeeAdministrationResult<()> {
+    let queued = find_mail(db, mail_id)?.ok_or(EmployeeAdministrationError::MailNotFound)?;
+    (queued.status() == Some(MailStatus::Failed))
+        .then(|| ())
+        .ok_or(EmployeeAdministrationError::MailNotFailed)?;
+    (!queued.is_expired() && queued.mail.is_some())
+        .then(|| ())
+        .ok_or(EmployeeAdministrationError::MailExpired)?;
+
+    requeue_mail(db, mail_id)?;
+    Ok(())
+}
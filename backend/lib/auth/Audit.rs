@@ -0,0 +1,448 @@
+use std::fmt::Display;
+use std::future::{ready, Ready};
+use actix_web::{web, FromRequest, HttpRequest};
+use actix_web::dev::Payload;
+use actix_web::http::header::USER_AGENT;
+use diesel::{insert_into, Connection, ExpressionMethods, Insertable, MysqlConnection, OptionalExtension, QueryDsl, Queryable, RunQueryDsl};
+use log::error;
+use moon::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use sha2::{Digest, Sha256};
+use crate::auth::Errors::DatabaseError;
+use crate::auth::Request::AuditQueryRequest;
+use crate::schema::{AuditChainLock, AuditEvents};
+use crate::server::BackendServerInfo;
+
+const MAX_USER_AGENT_LENGTH: usize = 512;
+const CHAIN_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEventType {
+    CitizenAuthentication,
+    CitizenLogin,
+    CitizenRegistered,
+    CitizenLookup,
+    CitizenLogout,
+    CitizenLocked,
+    CitizenUnlocked,
+    CitizenDeactivated,
+    CitizenDeleted,
+    PasswordResetRequested,
+    PasswordReset,
+    RegistrationCodeIssued,
+    RegistrationCodeResent,
+    LetterDownloaded,
+    EmployeeInvited,
+    EmployeeRegistered,
+    EmployeeLogin,
+    EmployeeDisabled,
+    EmployeeEnabled,
+    EmployeeDeleted,
+    EmployeeRoleGranted,
+    EmployeeRoleRevoked,
+    MailResent,
+}
+
+impl AuditEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEventType::CitizenAuthentication => "citizen_authentication",
+            AuditEventType::CitizenLogin => "citizen_login",
+            AuditEventType::CitizenRegistered => "citizen_registered",
+            AuditEventType::CitizenLookup => "citizen_lookup",
+            AuditEventType::CitizenLogout => "citizen_logout",
+            AuditEventType::CitizenLocked => "citizen_locked",
+            AuditEventType::CitizenUnlocked => "citizen_unlocked",
+            AuditEventType::CitizenDeactivated => "citizen_deactivated",
+            AuditEventType::CitizenDeleted => "citizen_deleted",
+            AuditEventType::PasswordResetRequested => "password_reset_requested",
+            AuditEventType::PasswordReset => "password_reset",
+            AuditEventType::RegistrationCodeIssued => "registration_code_issued",
+            AuditEventType::RegistrationCodeResent => "registration_code_resent",
+            AuditEventType::LetterDownloaded => "letter_downloaded",
+            AuditEventType::EmployeeInvited => "employee_invited",
+            AuditEventType::EmployeeRegistered => "employee_registered",
+            AuditEventType::EmployeeLogin => "employee_login",
+            AuditEventType::EmployeeDisabled => "employee_disabled",
+            AuditEventType::EmployeeEnabled => "employee_enabled",
+            AuditEventType::EmployeeDeleted => "employee_deleted",
+            AuditEventType::EmployeeRoleGranted => "employee_role_granted",
+            AuditEventType::EmployeeRoleRevoked => "employee_role_revoked",
+            AuditEventType::MailResent => "mail_resent",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+impl AuditOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditOutcome::Success => "success",
+            AuditOutcome::Failure => "failure",
+        }
+    }
+}
+
+/// Who triggered an event or whom it affected
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditParty {
+    Employee(u64),
+    Citizen(u64),
+    System,
+    /// Someone who could not be identified, e.g. a failed login with an unknown username
+    Anonymous,
+}
+
+impl AuditParty {
+    fn type_name(&self) -> &'static str {
+        match self {
+            AuditParty::Employee(_) => "employee",
+            AuditParty::Citizen(_) => "citizen",
+            AuditParty::System => "system",
+            AuditParty::Anonymous => "anonymous",
+        }
+    }
+
+    fn id(&self) -> Option<u64> {
+        match self {
+            AuditParty::Employee(id) | AuditParty::Citizen(id) => Some(*id),
+            AuditParty::System | AuditParty::Anonymous => None,
+        }
+    }
+}
+
+/// Where a request came from. The address of the connection is used, forwarding headers only if the request
+/// comes from one of the `audit.trusted_proxies`, because clients can send them too
+#[derive(Clone, Debug, Default)]
+pub struct RequestOrigin {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>
+}
+
+impl From<&HttpRequest> for RequestOrigin {
+    fn from(req: &HttpRequest) -> Self {
+        RequestOrigin {
+            ip: client_ip(req),
+            user_agent: req.headers()
+                .get(USER_AGENT)
+                .and_then(|h| h.to_str().ok())
+                .map(|h| h.chars().take(MAX_USER_AGENT_LENGTH).collect())
+        }
+    }
+}
+
+fn client_ip(req: &HttpRequest) -> Option<String> {
+    let peer = req.peer_addr()?.ip();
+    let trusted = req.app_data::<web::Data<BackendServerInfo>>()
+        .map_or(false, |config| config.audit.trusts_proxy(peer));
+    match trusted {
+        true => req.connection_info().realip_remote_addr().map(String::from),
+        false => Some(peer.to_string())
+    }
+}
+
+impl FromRequest for RequestOrigin {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(RequestOrigin::from(req)))
+    }
+}
+
+#[derive(Queryable, Serialize, Clone, Debug, JsonSchema)]
+pub struct AuditEvent {
+    pub id: u64,
+    pub created: NaiveDateTime,
+    pub actor_type: String,
+    pub actor_id: Option<u64>,
+    pub subject_type: Option<String>,
+    pub subject_id: Option<u64>,
+    pub event_type: String,
+    pub outcome: String,
+    pub details: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub prev_hash: Option<String>,
+    /// Missing for events recorded before the hash chain was introduced
+    pub hash: Option<String>
+}
+
+impl AuditEvent {
+    /// The hashed fields, taken from the event as it was recorded
+    pub(crate) fn chain_fields(&self) -> [Option<String>; 10] {
+        NewAuditEvent::from(self).chain_fields()
+    }
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[table_name="AuditEvents"]
+pub struct NewAuditEvent {
+    created: NaiveDateTime,
+    actor_type: String,
+    actor_id: Option<u64>,
+    subject_type: Option<String>,
+    subject_id: Option<u64>,
+    event_type: String,
+    outcome: String,
+    details: Option<String>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    prev_hash: Option<String>,
+    hash: Option<String>
+}
+
+impl NewAuditEvent {
+    pub fn new(actor: AuditParty, event_type: AuditEventType, outcome: AuditOutcome) -> Self {
+        NewAuditEvent {
+            //The database only stores whole seconds, the hash has to be computed from the stored value
+            created: NaiveDateTime::from_timestamp(Utc::now().timestamp(), 0),
+            actor_type: actor.type_name().to_string(),
+            actor_id: actor.id(),
+            subject_type: None,
+            subject_id: None,
+            event_type: event_type.as_str().to_string(),
+            outcome: outcome.as_str().to_string(),
+            details: None,
+            ip: None,
+            user_agent: None,
+            prev_hash: None,
+            hash: None
+        }
+    }
+
+    /// Creates an event whose outcome depends on the result, failures keep the error message as details
+    pub fn from_result<T, E: Display>(actor: AuditParty, event_type: AuditEventType, result: &Result<T, E>) -> Self {
+        match result {
+            Ok(_) => Self::new(actor, event_type, AuditOutcome::Success),
+            Err(e) => Self::new(actor, event_type, AuditOutcome::Failure).details(e.to_string())
+        }
+    }
+
+    pub fn subject(mut self, subject: AuditParty) -> Self {
+        self.subject_type = Some(subject.type_name().to_string());
+        self.subject_id = subject.id();
+        self
+    }
+
+    pub fn details(mut self, details: impl Into<String>) -> Self {
+        self.details = Some(details.into());
+        self
+    }
+
+    pub fn origin(mut self, origin: &RequestOrigin) -> Self {
+        self.ip = origin.ip.clone();
+        self.user_agent = origin.user_agent.clone();
+        self
+    }
+
+    /// Fields covered by the hash, in the order they are hashed
+    fn chain_fields(&self) -> [Option<String>; 10] {
+        [
+            Some(self.created.format(CHAIN_TIME_FORMAT).to_string()),
+            Some(self.actor_type.clone()),
+            self.actor_id.map(|i| i.to_string()),
+            self.subject_type.clone(),
+            self.subject_id.map(|i| i.to_string()),
+            Some(self.event_type.clone()),
+            Some(self.outcome.clone()),
+            self.details.clone(),
+            self.ip.clone(),
+            self.user_agent.clone(),
+        ]
+    }
+}
+
+impl From<&AuditEvent> for NewAuditEvent {
+    fn from(event: &AuditEvent) -> Self {
+        NewAuditEvent {
+            created: event.created,
+            actor_type: event.actor_type.clone(),
+            actor_id: event.actor_id,
+            subject_type: event.subject_type.clone(),
+            subject_id: event.subject_id,
+            event_type: event.event_type.clone(),
+            outcome: event.outcome.clone(),
+            details: event.details.clone(),
+            ip: event.ip.clone(),
+            user_agent: event.user_agent.clone(),
+            prev_hash: event.prev_hash.clone(),
+            hash: event.hash.clone()
+        }
+    }
+}
+
+/// Hashes the fields of an event together with the hash of its predecessor,
+/// so editing or removing an event breaks the chain from that event on
+pub(crate) fn chain_hash(prev_hash: Option<&str>, fields: &[Option<String>]) -> String {
+    let mut hasher = Sha256::new();
+    for field in std::iter::once(prev_hash).chain(fields.iter().map(|f| f.as_deref())) {
+        match field {
+            //Length prefixes keep the field boundaries unambiguous
+            Some(f) => {
+                hasher.update([1u8]);
+                hasher.update((f.len() as u64).to_be_bytes());
+                hasher.update(f.as_bytes());
+            }
+            None => hasher.update([0u8])
+        }
+    }
+    hasher.finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Hash of the newest event. The lock row is held until the surrounding transaction ends so that concurrent events
+/// are chained one after another, locking the newest event itself would not work while there is none
+fn latest_hash(db: &MysqlConnection) -> Result<Option<String>, DatabaseError> {
+    use crate::schema::AuditEvents::{hash, id};
+
+    AuditChainLock::table
+        .find(1u8)
+        .select(AuditChainLock::id)
+        .for_update()
+        .first::<u8>(db)?;
+
+    Ok(AuditEvents::table
+        .select(hash)
+        .order(id.desc())
+        .first::<Option<String>>(db)
+        .optional()?
+        .flatten())
+}
+
+/// Appends an event to the audit trail, events are never updated or removed afterwards
+pub fn record_event(db: &MysqlConnection, event: &NewAuditEvent) -> Result<(), DatabaseError> {
+    db.transaction::<_, DatabaseError, _>(|| {
+        let prev_hash = latest_hash(db)?;
+        let mut event = event.clone();
+        event.hash = Some(chain_hash(prev_hash.as_deref(), &event.chain_fields()));
+        event.prev_hash = prev_hash;
+
+        insert_into(AuditEvents::table)
+            .values(&event)
+            .execute(db)?;
+        Ok(())
+    })
+}
+
+/// Like [record_event], but only logs a failure so that authentication itself does not break when the audit trail can not be written
+pub fn try_record_event(db: &MysqlConnection, event: &NewAuditEvent) {
+    if let Err(e) = record_event(db, event) {
+        error!("Unable to write audit event {:?}: {}", event, e);
+    }
+}
+
+/// Number of events of the type recorded for requests from the address since the given time
+pub fn count_events_from(db: &MysqlConnection, event: AuditEventType, address: Option<&str>, since: NaiveDateTime) -> Result<i64, DatabaseError> {
+    use crate::schema::AuditEvents::{created, event_type, ip};
+
+    let query = AuditEvents::table
+        .filter(event_type.eq(event.as_str()))
+        .filter(created.ge(since))
+        .into_boxed();
+    let query = match address {
+        Some(a) => query.filter(ip.eq(a)),
+        None => query.filter(ip.is_null())
+    };
+    Ok(query.count().get_result(db)?)
+}
+
+fn audit_query(request: &AuditQueryRequest) -> AuditEvents::BoxedQuery<'static, diesel::mysql::Mysql> {
+    use crate::schema::AuditEvents::{actor_id, actor_type, created, event_type, outcome, subject_id, subject_type};
+
+    let mut query = AuditEvents::table.into_boxed();
+    if let Some(t) = request.event_type {
+        query = query.filter(event_type.eq(t.as_str()));
+    }
+    if let Some(o) = request.outcome {
+        query = query.filter(outcome.eq(o.as_str()));
+    }
+    if let Some(t) = &request.actor_type {
+        query = query.filter(actor_type.eq(t.clone()));
+    }
+    if let Some(a) = request.actor_id {
+        query = query.filter(actor_id.eq(a));
+    }
+    if let Some(t) = &request.subject_type {
+        query = query.filter(subject_type.eq(t.clone()));
+    }
+    if let Some(s) = request.subject_id {
+        query = query.filter(subject_id.eq(s));
+    }
+    if let Some(from) = request.from {
+        query = query.filter(created.ge(from));
+    }
+    if let Some(to) = request.to {
+        query = query.filter(created.lt(to));
+    }
+    query
+}
+
+/// Loads the matching events, newest first, together with the total number of matches
+pub fn query_events(db: &MysqlConnection, request: &AuditQueryRequest, offset: i64, limit: i64) -> Result<(i64, Vec<AuditEvent>), DatabaseError> {
+    use crate::schema::AuditEvents::id;
+
+    let total: i64 = audit_query(request)
+        .count()
+        .get_result(db)?;
+
+    let events = audit_query(request)
+        .order(id.desc())
+        .offset(offset)
+        .limit(limit)
+        .load(db)?;
+
+    Ok((total, events))
+}
+
+/// Quotes the value if needed. Values that spreadsheets would read as formula get a leading `'`
+fn csv_field(value: &str) -> String {
+    let value = match value.starts_with(|c| matches!(c, '=' | '+' | '-' | '@' | '\t' | '\r')) {
+        true => format!("'{}", value),
+        false => value.to_string()
+    };
+    if value.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+pub fn events_to_csv(events: &[AuditEvent]) -> String {
+    let mut csv = String::from("id,created,actor_type,actor_id,subject_type,subject_id,event_type,outcome,details,ip,user_agent,prev_hash,hash\r\n");
+    for e in events {
+        let fields = [
+            e.id.to_string(),
+            e.created.format("%Y-%m-%dT%H:%M:%S").to_string(),
+            e.actor_type.clone(),
+            e.actor_id.map(|i| i.to_string()).unwrap_or_default(),
+            e.subject_type.clone().unwrap_or_default(),
+            e.subject_id.map(|i| i.to_string()).unwrap_or_default(),
+            e.event_type.clone(),
+            e.outcome.clone(),
+            e.details.clone().unwrap_or_default(),
+            e.ip.clone().unwrap_or_default(),
+            e.user_agent.clone().unwrap_or_default(),
+            e.prev_hash.clone().unwrap_or_default(),
+            e.hash.clone().unwrap_or_default(),
+        ];
+        let line = fields
+            .iter()
+            .map(|f| csv_field(f))
+            .collect::<Vec<_>>()
+            .join(",");
+        csv.push_str(&line);
+        csv.push_str("\r\n");
+    }
+    csv
+}
@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::Path;
+use diesel::{ExpressionMethods, MysqlConnection, QueryDsl, RunQueryDsl};
+use hmac::{Hmac, Mac};
+use moon::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use sha2::Sha256;
+use crate::auth::Audit::{chain_hash, AuditEvent};
+use crate::auth::Errors::{AuditChainError, AuditChainResult};
+use crate::schema::AuditEvents;
+use crate::server::AuditConfig;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const VERIFICATION_BATCH_SIZE: i64 = 1000;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChainBreak {
+    /// The event does not match its own hash, it was edited
+    ContentModified,
+    /// The event does not reference the hash of its predecessor, events were removed or inserted
+    LinkMismatch,
+    /// The event has no hash although earlier events have one
+    MissingHash,
+    /// The chain differs from a signed checkpoint, it was rewritten or truncated
+    CheckpointMismatch,
+}
+
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct BrokenLink {
+    pub event_id: u64,
+    pub reason: ChainBreak,
+}
+
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct ChainHead {
+    pub event_id: u64,
+    pub hash: String,
+}
+
+#[derive(Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct ChainVerification {
+    /// Number of chained events that were verified
+    pub verified: u64,
+    /// Events recorded before the hash chain was introduced, these can not be verified
+    pub unchained: u64,
+    /// Number of signed checkpoints the chain was compared against
+    pub checkpoints: u64,
+    /// Last verified event
+    pub head: Option<ChainHead>,
+    /// First broken link, verification stops there
+    pub broken: Option<BrokenLink>,
+}
+
+/// Signed snapshot of the head of the chain. Once exported, rewriting the chain
+/// up to this event can be detected even by someone with write access to the database
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditCheckpoint {
+    pub created: NaiveDateTime,
+    pub event_id: u64,
+    pub hash: String,
+    pub signature: String,
+}
+
+impl AuditCheckpoint {
+    fn mac(event_id: u64, hash: &str, created: &NaiveDateTime, key: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+        mac.update(format!("{}:{}:{}", event_id, hash, created.format("%Y-%m-%dT%H:%M:%S")).as_bytes());
+        mac
+    }
+
+    pub fn sign(head: ChainHead, key: &str) -> Self {
+        let created = NaiveDateTime::from_timestamp(Utc::now().timestamp(), 0);
+        let signature = Self::mac(head.event_id, &head.hash, &created, key)
+            .finalize()
+            .into_bytes();
+
+        AuditCheckpoint {
+            created,
+            event_id: head.event_id,
+            hash: head.hash,
+            signature: base64::encode(signature),
+        }
+    }
+
+    pub fn has_valid_signature(&self, key: &str) -> bool {
+        match base64::decode(&self.signature) {
+            Ok(signature) => Self::mac(self.event_id, &self.hash, &self.created, key)
+                .verify_slice(&signature)
+                .is_ok(),
+            Err(_) => false
+        }
+    }
+}
+
+/// Reads all checkpoints from the file, fails if any of them is not signed with the key.
+/// A missing file means no checkpoints have been written yet
+pub fn read_checkpoints(path: &Path, key: &str) -> AuditChainResult<Vec<AuditCheckpoint>> {
+    let file = match std::fs::File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into())
+    };
+
+    let mut checkpoints = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let checkpoint: AuditCheckpoint = serde_json::from_str(&line)
+            .map_err(|_| AuditChainError::InvalidCheckpoint(number + 1))?;
+        if !checkpoint.has_valid_signature(key) {
+            return Err(AuditChainError::InvalidCheckpoint(number + 1));
+        }
+        checkpoints.push(checkpoint);
+    }
+    Ok(checkpoints)
+}
+
+/// Walks the whole audit trail, oldest event first, and reports the first broken link
+pub fn verify_chain(db: &MysqlConnection, checkpoints: &[AuditCheckpoint]) -> AuditChainResult<ChainVerification> {
+    use crate::schema::AuditEvents::id;
+
+    let mut expected: HashMap<u64, &str> = checkpoints
+        .iter()
+        .map(|c| (c.event_id, c.hash.as_str()))
+        .collect();
+    let mut result = ChainVerification::default();
+    let mut prev_hash: Option<String> = None;
+    let mut last_id = 0;
+
+    'walk: loop {
+        let events: Vec<AuditEvent> = AuditEvents::table
+            .filter(id.gt(last_id))
+            .order(id.asc())
+            .limit(VERIFICATION_BATCH_SIZE)
+            .load(db)?;
+        if events.is_empty() {
+            break;
+        }
+
+        for event in events {
+            last_id = event.id;
+            let hash = match (&event.hash, &prev_hash) {
+                (Some(h), _) => h,
+                (None, None) => {
+                    result.unchained += 1;
+                    continue;
+                }
+                (None, Some(_)) => {
+                    result.broken = Some(BrokenLink { event_id: event.id, reason: ChainBreak::MissingHash });
+                    break 'walk;
+                }
+            };
+
+            if event.prev_hash != prev_hash {
+                result.broken = Some(BrokenLink { event_id: event.id, reason: ChainBreak::LinkMismatch });
+                break 'walk;
+            }
+            if chain_hash(prev_hash.as_deref(), &event.chain_fields()) != *hash {
+                result.broken = Some(BrokenLink { event_id: event.id, reason: ChainBreak::ContentModified });
+                break 'walk;
+            }
+            if let Some(checkpoint_hash) = expected.remove(&event.id) {
+                if checkpoint_hash != hash.as_str() {
+                    result.broken = Some(BrokenLink { event_id: event.id, reason: ChainBreak::CheckpointMismatch });
+                    break 'walk;
+                }
+                result.checkpoints += 1;
+            }
+
+            result.verified += 1;
+            result.head = Some(ChainHead { event_id: event.id, hash: hash.clone() });
+            prev_hash = Some(hash.clone());
+        }
+    }
+
+    //Checkpoints pointing to events that are no longer there mean the end of the trail was cut off
+    if result.broken.is_none() {
+        if let Some(missing) = expected.keys().min() {
+            result.broken = Some(BrokenLink { event_id: *missing, reason: ChainBreak::CheckpointMismatch });
+        }
+    }
+    Ok(result)
+}
+
+/// Verifies the chain against the checkpoints of the configured file, if there is one
+pub fn verify_audit_trail(db: &MysqlConnection, config: &AuditConfig) -> AuditChainResult<ChainVerification> {
+    let checkpoints = match config.checkpoints() {
+        Some((path, key)) => read_checkpoints(path, key)?,
+        None => Vec::new()
+    };
+    verify_chain(db, &checkpoints)
+}
+
+/// Verifies the chain and appends a signed checkpoint of its head to the file.
+/// Nothing is written if the chain is empty or has not grown since the last checkpoint
+pub fn write_checkpoint(db: &MysqlConnection, path: &Path, key: &str) -> AuditChainResult<Option<AuditCheckpoint>> {
+    let checkpoints = read_checkpoints(path, key)?;
+    let verification = verify_chain(db, &checkpoints)?;
+    if let Some(link) = verification.broken {
+        return Err(AuditChainError::Broken(link.event_id));
+    }
+
+    let head = match verification.head {
+        Some(h) => h,
+        None => return Ok(None)
+    };
+    if checkpoints.last().map(|c| c.event_id) == Some(head.event_id) {
+        return Ok(None);
+    }
+
+    let checkpoint = AuditCheckpoint::sign(head, key);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    let line = serde_json::to_string(&checkpoint).expect("Checkpoints are always serializable");
+    writeln!(file, "{}", line)?;
+
+    Ok(Some(checkpoint))
+}
@@ -0,0 +1,100 @@
+use std::future::Future;
+use std::pin::Pin;
+use actix_web::{web, FromRequest, HttpRequest};
+use actix_web::dev::Payload;
+use actix_web::http::header::AUTHORIZATION;
+use crate::auth::Actions::{check_user_session_token, verify_employee};
+use crate::auth::Employee::EmployeeLogin;
+use crate::auth::Errors::{DatabaseError, SessionRetrievalError};
+use crate::auth::Request::{FormOrJson, TokenValidateRequest};
+use crate::auth::Session::Token;
+use crate::auth::SessionCookie::SessionCookies;
+use crate::auth::User::User;
+use crate::server::DBPool;
+
+pub const USER_SESSION_COOKIE: &str = "user_session_token";
+pub const EMPLOYEE_SESSION_COOKIE: &str = "employee_session_token";
+
+type AuthenticationFuture<T> = Pin<Box<dyn Future<Output = Result<T, SessionRetrievalError>>>>;
+
+/// Citizen with a valid session. The session token is read from `Authorization: Bearer <token>`,
+/// the `user_session_token` cookie (named as configured in [SessionCookies]) or the `code` field of the body, in this order
+pub struct AuthenticatedUser {
+    pub user: User,
+    pub token: Token,
+}
+
+/// Employee with a valid session, the token is read like for [AuthenticatedUser] from the `employee_session_token` cookie
+pub struct AuthenticatedEmployee {
+    pub employee: EmployeeLogin,
+    pub token: Token,
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = SessionRetrievalError;
+    type Future = AuthenticationFuture<Self>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let pool = req.app_data::<web::Data<DBPool>>().cloned();
+        let token = session_token(req, payload, USER_SESSION_COOKIE);
+
+        Box::pin(async move {
+            let token = token.await?;
+            let pool = pool.ok_or(SessionRetrievalError::Db(DatabaseError::Connection))?;
+            let session_token = token.clone();
+            let user = web::block(move || {
+                let db = pool.get().map_err(|_| SessionRetrievalError::Db(DatabaseError::Connection))?;
+                check_user_session_token(&db, &session_token)
+            }).await??;
+
+            Ok(AuthenticatedUser { user, token })
+        })
+    }
+}
+
+impl FromRequest for AuthenticatedEmployee {
+    type Error = SessionRetrievalError;
+    type Future = AuthenticationFuture<Self>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let pool = req.app_data::<web::Data<DBPool>>().cloned();
+        let token = session_token(req, payload, EMPLOYEE_SESSION_COOKIE);
+
+        Box::pin(async move {
+            let token = token.await?;
+            let pool = pool.ok_or(SessionRetrievalError::Db(DatabaseError::Connection))?;
+            let verification = web::block(move || {
+                let db = pool.get().map_err(|_| SessionRetrievalError::Db(DatabaseError::Connection))?;
+                verify_employee(&db, &token)
+            }).await??;
+
+            Ok(AuthenticatedEmployee {
+                employee: verification.employee,
+                token: verification.new_employee_token,
+            })
+        })
+    }
+}
+
+fn session_token(req: &HttpRequest, payload: &mut Payload, cookie: &str) -> AuthenticationFuture<Token> {
+    let cookie = req.app_data::<web::Data<SessionCookies>>()
+        .map_or_else(|| cookie.to_string(), |cookies| cookies.name(cookie));
+    let bearer = req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+    let token = bearer.or_else(|| req.cookie(&cookie).map(|c| c.value().to_string()));
+
+    match token {
+        Some(token) => Box::pin(async move { Ok(token) }),
+        None => {
+            let body = FormOrJson::<TokenValidateRequest>::from_request(req, payload);
+            Box::pin(async move {
+                body.await
+                    .map(|request| request.into_inner().code)
+                    .map_err(|_| SessionRetrievalError::MissingToken)
+            })
+        }
+    }
+}
@@ -3,9 +3,11 @@ use anyhow::Result;
 use async_trait::async_trait;
 use diesel::Identifiable;
 use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+use crate::auth::CitizenDirectory::CitizenDirectory;
 use crate::auth::Errors::CitizenInfoRetrievalResult;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct CitizenAddress {
     pub street: Option<String>,
     pub housenumber: Option<String>,
@@ -13,7 +15,7 @@ pub struct CitizenAddress {
     pub city: Option<String>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
 pub struct CitizenInfo {
     pub citizen_id: u64,
     pub firstname: String,
@@ -25,6 +27,9 @@ pub struct CitizenInfo {
     pub email: Option<String>,
     pub spouse_id: Option<u64>,
     pub child_ids: Option<Vec<u64>>,
+    /// Preferred language for mails, e.g. `de` or `en`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
 
     pub address: CitizenAddress
 }
@@ -34,13 +39,8 @@ pub struct CitizenInfo {
 pub trait IsCitizen {
     fn get_citizen_id(&self) -> u64;
 
-    async fn get_citizen_info(&self) -> CitizenInfoRetrievalResult<CitizenInfo> {
-        let user_info = reqwest::get(format!("http://www.smartcityproject.net:9710/api/citizen/{}", self.get_citizen_id()))
-            .await?
-            .text()
-            .await?;
-
-        Ok(serde_json::from_str(&user_info)?)
+    async fn get_citizen_info(&self, directory: &dyn CitizenDirectory) -> CitizenInfoRetrievalResult<CitizenInfo> {
+        directory.citizen_info(self.get_citizen_id()).await
     }
 }
 
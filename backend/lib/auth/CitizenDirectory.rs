@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use log::{debug, warn};
+use reqwest::StatusCode;
+use crate::auth::Citizen::CitizenInfo;
+use crate::auth::Errors::{CitizenInfoRetrievalError, CitizenInfoRetrievalResult};
+use crate::server::CitizenDirectoryConfig;
+
+/// Cached entries are purged once the cache grows beyond this size
+const MAX_CACHE_ENTRIES: usize = 10000;
+
+/// Source of the personal information of citizens
+#[async_trait]
+pub trait CitizenDirectory: Send + Sync {
+    async fn citizen_info(&self, citizen_id: u64) -> CitizenInfoRetrievalResult<CitizenInfo>;
+
+    /// Most recent info known without asking the source, regardless of its age
+    fn last_known_info(&self, _citizen_id: u64) -> Option<CitizenInfo> {
+        None
+    }
+
+    /// Forgets everything known about the citizen, the next request asks the source again
+    fn invalidate(&self, _citizen_id: u64) {}
+}
+
+/// Queries the citizen service of the city, `{id}` in the url is replaced with the citizen id
+pub struct HttpCitizenDirectory {
+    client: reqwest::Client,
+    url: String,
+    retries: u32,
+    retry_delay: Duration,
+}
+
+impl HttpCitizenDirectory {
+    pub fn new(url: &str, timeout: Duration, retries: u32, retry_delay: Duration) -> Result<Self> {
+        Ok(HttpCitizenDirectory {
+            client: reqwest::Client::builder()
+                .timeout(timeout)
+                .build()
+                .context("Failed to create the http client for the citizen service")?,
+            url: url.to_string(),
+            retries,
+            retry_delay,
+        })
+    }
+
+    async fn request(&self, citizen_id: u64) -> CitizenInfoRetrievalResult<CitizenInfo> {
+        let response = self.client
+            .get(self.url.replace("{id}", &citizen_id.to_string()))
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Err(CitizenInfoRetrievalError::NotFound(citizen_id)),
+            s if !s.is_success() => Err(CitizenInfoRetrievalError::Status(s)),
+            _ => Ok(serde_json::from_str(&response.text().await?)?)
+        }
+    }
+}
+
+#[async_trait]
+impl CitizenDirectory for HttpCitizenDirectory {
+    async fn citizen_info(&self, citizen_id: u64) -> CitizenInfoRetrievalResult<CitizenInfo> {
+        let mut attempt = 0;
+        loop {
+            match self.request(citizen_id).await {
+                Err(e) if e.is_transient() && attempt < self.retries => {
+                    attempt += 1;
+                    debug!("Citizen service request for {} failed, retrying ({}/{}): {}", citizen_id, attempt, self.retries, e);
+                    tokio::time::sleep(self.retry_delay * attempt).await;
+                }
+                result => return result
+            }
+        }
+    }
+}
+
+/// Fixed set of citizens, used to run the service without the citizen service
+pub struct StaticCitizenDirectory {
+    citizens: HashMap<u64, CitizenInfo>,
+}
+
+impl StaticCitizenDirectory {
+    pub fn new(citizens: impl IntoIterator<Item = CitizenInfo>) -> Self {
+        StaticCitizenDirectory {
+            citizens: citizens
+                .into_iter()
+                .map(|c| (c.citizen_id, c))
+                .collect()
+        }
+    }
+
+    /// Reads a JSON array of citizens in the format of the citizen service
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read citizen file {}", path.display()))?;
+        let citizens: Vec<CitizenInfo> = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse citizen file {}", path.display()))?;
+        Ok(Self::new(citizens))
+    }
+}
+
+#[async_trait]
+impl CitizenDirectory for StaticCitizenDirectory {
+    async fn citizen_info(&self, citizen_id: u64) -> CitizenInfoRetrievalResult<CitizenInfo> {
+        self.citizens
+            .get(&citizen_id)
+            .cloned()
+            .ok_or(CitizenInfoRetrievalError::NotFound(citizen_id))
+    }
+}
+
+struct CacheEntry {
+    expires: Instant,
+    /// None if the citizen is unknown to the directory
+    info: Option<CitizenInfo>,
+}
+
+/// Keeps citizen info for `ttl` and unknown citizens for `negative_ttl`. Other errors are not cached
+pub struct CachedCitizenDirectory<D> {
+    inner: D,
+    ttl: Duration,
+    negative_ttl: Duration,
+    entries: Mutex<HashMap<u64, CacheEntry>>,
+}
+
+impl<D: CitizenDirectory> CachedCitizenDirectory<D> {
+    pub fn new(inner: D, ttl: Duration, negative_ttl: Duration) -> Self {
+        CachedCitizenDirectory {
+            inner,
+            ttl,
+            negative_ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    fn cached(&self, citizen_id: u64) -> Option<CitizenInfoRetrievalResult<CitizenInfo>> {
+        let entries = self.entries.lock().unwrap();
+        entries.get(&citizen_id)
+            .filter(|e| e.expires > Instant::now())
+            .map(|e| e.info.clone().ok_or(CitizenInfoRetrievalError::NotFound(citizen_id)))
+    }
+
+    fn store(&self, citizen_id: u64, info: Option<CitizenInfo>) {
+        let now = Instant::now();
+        let ttl = if info.is_some() { self.ttl } else { self.negative_ttl };
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_CACHE_ENTRIES {
+            entries.retain(|_, e| e.expires > now);
+        }
+        entries.insert(citizen_id, CacheEntry { expires: now + ttl, info });
+    }
+}
+
+#[async_trait]
+impl<D: CitizenDirectory> CitizenDirectory for CachedCitizenDirectory<D> {
+    async fn citizen_info(&self, citizen_id: u64) -> CitizenInfoRetrievalResult<CitizenInfo> {
+        if let Some(cached) = self.cached(citizen_id) {
+            return cached;
+        }
+
+        let result = self.inner.citizen_info(citizen_id).await;
+        match &result {
+            Ok(info) => self.store(citizen_id, Some(info.clone())),
+            Err(CitizenInfoRetrievalError::NotFound(_)) => self.store(citizen_id, None),
+            Err(_) => {}
+        };
+        result
+    }
+
+    fn last_known_info(&self, citizen_id: u64) -> Option<CitizenInfo> {
+        let entries = self.entries.lock().unwrap();
+        entries.get(&citizen_id)
+            .and_then(|e| e.info.clone())
+            .or_else(|| self.inner.last_known_info(citizen_id))
+    }
+
+    fn invalidate(&self, citizen_id: u64) {
+        self.entries.lock().unwrap().remove(&citizen_id);
+        self.inner.invalidate(citizen_id);
+    }
+}
+
+enum BreakerState {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    /// A single trial request is on its way, further requests are rejected until it completes or `until` passes
+    HalfOpen { until: Instant },
+}
+
+/// Stops asking a failing directory for `open_duration` after `failure_threshold` consecutive transient failures
+pub struct CircuitBreakerDirectory<D> {
+    inner: D,
+    failure_threshold: u32,
+    open_duration: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl<D: CitizenDirectory> CircuitBreakerDirectory<D> {
+    pub fn new(inner: D, failure_threshold: u32, open_duration: Duration) -> Self {
+        CircuitBreakerDirectory {
+            inner,
+            failure_threshold: failure_threshold.max(1),
+            open_duration,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    fn permit_request(&self) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until } | BreakerState::HalfOpen { until } if until <= now => {
+                *state = BreakerState::HalfOpen { until: now + self.open_duration };
+                true
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => false,
+        }
+    }
+
+    fn record(&self, failed: bool) {
+        let mut state = self.state.lock().unwrap();
+        *state = match (&*state, failed) {
+            (_, false) => BreakerState::Closed { failures: 0 },
+            (BreakerState::Closed { failures }, true) if failures + 1 < self.failure_threshold => {
+                BreakerState::Closed { failures: failures + 1 }
+            }
+            (_, true) => {
+                warn!("Citizen service is failing, pausing requests for {:?}", self.open_duration);
+                BreakerState::Open { until: Instant::now() + self.open_duration }
+            }
+        };
+    }
+}
+
+#[async_trait]
+impl<D: CitizenDirectory> CitizenDirectory for CircuitBreakerDirectory<D> {
+    async fn citizen_info(&self, citizen_id: u64) -> CitizenInfoRetrievalResult<CitizenInfo> {
+        if !self.permit_request() {
+            return Err(CitizenInfoRetrievalError::Unavailable);
+        }
+
+        let result = self.inner.citizen_info(citizen_id).await;
+        self.record(matches!(&result, Err(e) if e.is_transient()));
+        result
+    }
+
+    fn last_known_info(&self, citizen_id: u64) -> Option<CitizenInfo> {
+        self.inner.last_known_info(citizen_id)
+    }
+
+    fn invalidate(&self, citizen_id: u64) {
+        self.inner.invalidate(citizen_id)
+    }
+}
+
+/// Creates the directory described by the config, a file directory if a file is set, otherwise the cached citizen service
+pub fn citizen_directory(config: &CitizenDirectoryConfig) -> Result<Arc<dyn CitizenDirectory>> {
+    if let Some(file) = &config.file {
+        return Ok(Arc::new(StaticCitizenDirectory::from_file(file)?));
+    }
+
+    let http = HttpCitizenDirectory::new(
+        &config.url,
+        Duration::from_millis(config.timeout_ms),
+        config.retries,
+        Duration::from_millis(config.retry_delay_ms),
+    )?;
+    let breaker = CircuitBreakerDirectory::new(
+        http,
+        config.breaker_failure_threshold,
+        Duration::from_secs(config.breaker_open_seconds),
+    );
+    Ok(Arc::new(CachedCitizenDirectory::new(
+        breaker,
+        Duration::from_secs(config.cache_ttl_seconds),
+        Duration::from_secs(config.negative_cache_ttl_seconds),
+    )))
+}
@@ -3,23 +3,28 @@ use diesel::Insertable;
 use rand::{CryptoRng, RngCore};
 use crate::auth::Errors::{CredentialsCreationError, CredentialsCreationResult, CredentialsVerificationError, CredentialsVerificationResult};
 use serde::Deserialize;
+use schemars::JsonSchema;
 
 pub trait CredentialsHolder {
     fn get_secret(&self) -> &str;
     fn get_key(&self) -> &str;
 
     fn create_hash(&self) -> CredentialsCreationResult<String> {
-        let mut rng = rand::thread_rng();
-        let mut salt = vec![0; 128];
-        rng.try_fill_bytes(&mut salt)?;
-
-        let mut config = argon2::Config::default();
-        config.hash_length = 128;
-        Ok(argon2::hash_encoded(self.get_secret().as_bytes(), &salt, &config)?)
+        hash_secret(self.get_secret())
     }
 }
 
-#[derive(Deserialize, Debug)]
+pub fn hash_secret(secret: &str) -> CredentialsCreationResult<String> {
+    let mut rng = rand::thread_rng();
+    let mut salt = vec![0; 128];
+    rng.try_fill_bytes(&mut salt)?;
+
+    let mut config = argon2::Config::default();
+    config.hash_length = 128;
+    Ok(argon2::hash_encoded(secret.as_bytes(), &salt, &config)?)
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
 pub struct CredentialsPair {
     username: String,
     password: String
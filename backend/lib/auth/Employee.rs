@@ -1,23 +1,43 @@
 use crate::auth::Credentials::{CredentialsHolder, IdentityHolder};
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 use diesel::{Insertable, Identifiable, Queryable, Associations};
-use moon::{chrono, NaiveDateTime};
+use moon::{chrono, NaiveDateTime, Utc};
 use crate::auth::Errors::SessionCreationError;
 use crate::auth::Session::{Session, Token};
-use crate::schema::{EmployeeInfo, EmployeeLogins, EmployeeSessions};
+use std::str::FromStr;
+use crate::schema::{EmployeeInfo, EmployeeInvitations, EmployeeLogins, EmployeeRoles, EmployeeSessions};
 #[derive(Queryable, Identifiable, PartialEq, Associations)]
 #[table_name="EmployeeInfo"]
 pub struct EmployeeInfoModel {
     pub id: u64,
     pub firstname: String,
-    pub lastname: String
+    pub lastname: String,
+    pub department: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>
 }
 
-#[derive(Deserialize, Serialize, Insertable, PartialEq, Associations, Clone)]
+#[derive(Deserialize, Serialize, Insertable, PartialEq, Associations, Clone, JsonSchema)]
 #[table_name="EmployeeInfo"]
 pub struct NewEmployeeInfo {
     pub firstname: String,
-    pub lastname: String
+    pub lastname: String,
+    pub department: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>
+}
+
+impl From<EmployeeInfoModel> for NewEmployeeInfo {
+    fn from(info: EmployeeInfoModel) -> Self {
+        NewEmployeeInfo {
+            firstname: info.firstname,
+            lastname: info.lastname,
+            department: info.department,
+            email: info.email,
+            phone: info.phone
+        }
+    }
 }
 
 #[derive(Queryable, Identifiable, PartialEq, Associations, Clone, Debug)]
@@ -27,7 +47,10 @@ pub struct EmployeeLogin {
     pub id: u64,
     pub info_id: u64,
     pub username: String,
-    pub hash: String
+    pub hash: String,
+    pub totp_secret: Option<String>,
+    pub disabled: bool,
+    pub last_login: Option<NaiveDateTime>
 }
 
 impl IdentityHolder for EmployeeLogin {
@@ -58,4 +81,86 @@ impl Session for EmployeeSession {
     fn token(&self) -> &Token {
         &self.token
     }
-}
\ No newline at end of file
+}
+
+#[derive(Queryable, Identifiable, PartialEq, Associations, Debug)]
+#[belongs_to(EmployeeInfoModel, foreign_key = "info_id")]
+#[table_name="EmployeeInvitations"]
+pub struct EmployeeInvitation {
+    pub id: u64,
+    pub info_id: u64,
+    pub token: Token,
+    pub email: String,
+    pub invited_by: Option<u64>,
+    pub expires: chrono::NaiveDateTime,
+    /// Secret shown on the invitation page, it becomes the second factor of the employee
+    pub totp_secret: Option<String>
+}
+
+impl EmployeeInvitation {
+    pub fn is_valid(&self) -> bool {
+        self.expires >= Utc::now().naive_utc()
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// May invite, disable, re-enable and delete employees and grant roles, implies every other role
+    Admin,
+    /// May look up and administer citizen accounts
+    Support,
+    /// May read and export the audit trail
+    Auditor,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Support => "support",
+            Role::Auditor => "auditor",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Role::Admin),
+            "support" => Ok(Role::Support),
+            "auditor" => Ok(Role::Auditor),
+            _ => Err(())
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EmployeeStatus {
+    /// Invitation was sent but not redeemed yet
+    Invited,
+    Active,
+    Disabled,
+}
+
+impl EmployeeStatus {
+    pub fn of(login: Option<&EmployeeLogin>) -> Self {
+        match login {
+            None => EmployeeStatus::Invited,
+            Some(l) if l.disabled => EmployeeStatus::Disabled,
+            Some(_) => EmployeeStatus::Active
+        }
+    }
+}
+
+#[derive(Queryable, Identifiable, PartialEq, Associations, Debug)]
+#[belongs_to(EmployeeLogin, foreign_key = "e_id")]
+#[table_name="EmployeeRoles"]
+pub struct EmployeeRole {
+    pub id: u64,
+    pub e_id: u64,
+    pub role: String
+}
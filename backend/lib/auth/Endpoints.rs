@@ -1,89 +1,145 @@
 use std::path::PathBuf;
-use actix_web::{Either, Responder, web};
-use actix_web::cookie::Cookie;
+use actix_web::{Either, HttpRequest, Responder, web};
 use actix_web::error::Kind::Http;
 use actix_web::http::{HeaderValue, StatusCode};
 use actix_web::web::{Data, HttpResponse};
+use diesel::{Connection, MysqlConnection};
 use lettre::smtp::authentication::Mechanism::Login;
+use log::warn;
 use moon::actix_files::NamedFile;
-use reqwest::header::LOCATION;
-use crate::auth::Actions::{check_user_session_token, get_employee_info, login_employee, login_user, register_employee, register_user, verify_employee};
-use crate::auth::Citizen::IsCitizen;
+use moon::chrono;
+use reqwest::header::{CONTENT_DISPOSITION, LOCATION};
+use crate::auth::Actions::{authorize_employee, check_pending_user_token, check_resend_origin, create_password_reset, delete_employee, describe_employees, citizen_code_delivery, CodeDelivery, find_citizen_account, find_pending_user, get_employee_info, employee_invitation_totp_secret, has_employees, invite_employee, list_employees, login_employee, login_user, logout_user, page_bounds, queue_code_delivery, register_employee, register_user, reissue_pending_user, renew_pending_user, require_role, resend_mail, reset_password, citizen_code_mail, employee_invitation_mail, password_reset_mail, set_employee_disabled, set_employee_role, set_user_locked};
+use crate::auth::Audit::{events_to_csv, query_events, record_event, try_record_event, AuditEventType, AuditOutcome, AuditParty, NewAuditEvent, RequestOrigin};
+use crate::auth::AuditChain::verify_audit_trail;
+use crate::auth::Authenticated::{AuthenticatedEmployee, AuthenticatedUser, EMPLOYEE_SESSION_COOKIE, USER_SESSION_COOKIE};
+use crate::auth::Citizen::{Citizen, CitizenInfo, IsCitizen};
+use crate::auth::CitizenDirectory::CitizenDirectory;
 use crate::auth::Credentials::CredentialsPair;
-use crate::auth::Employee::NewEmployeeInfo;
-use crate::auth::Errors::{DatabaseError, IntoHttpError, LoginError, LoginResult, SessionRetrievalError, SessionRetrievalResult, UserRegistrationError};
-use crate::auth::Request::{EmployeeInfoRequestResponse, EmployeeLoginRequestResponse, EmployeeRegisterRequest, ExternalUserLoginRequest, TokenValidateRequest, UserInfoRequestResponse, UserLoginRequest, UserLoginRequestResponse, UserRegistrationRequest};
-use crate::server::DBPool;
-
-pub async fn user_register(pool: Data<DBPool>, request: web::Form<UserRegistrationRequest>) -> Result<HttpResponse, UserRegistrationError> {
-    let redirect_error = request.redirect_error.clone();
-    let redirect_success = request.redirect_success.clone();
-    let insert_user = {
+use crate::auth::Employee::{EmployeeLogin, NewEmployeeInfo, Role};
+use crate::auth::Errors::{CitizenAdministrationError, CitizenAdministrationResult, DatabaseError, EmployeeAdministrationError, EmployeeAdministrationResult, CitizenInfoRetrievalResult, IntoHttpError, LoginError, LoginResult, RedirectError, SessionRetrievalError, SessionRetrievalResult, UserRegistrationError};
+use crate::auth::Request::{AuditExportFormat, AuditQueryRequest, AuditQueryResponse, CitizenAdministrationRequest, CitizenInfoStatus, CitizenLookupRequest, EmployeeAdministrationRequest, EmployeeDirectoryEntry, EmployeeInfoRequestResponse, EmployeeInvitationQuery, EmployeeInvitationResponse, EmployeeInviteRequest, EmployeeListRequest, EmployeeListResponse, EmployeeLoginRequest, EmployeeLoginRequestResponse, EmployeeRegisterRequest, EmployeeRegisterResponse, EmployeeRoleRequest, ExternalUserLoginRequest, FormOrJson, is_json_request, LetterDownloadRequest, LetterListRequest, LetterListResponse, MailListRequest, MailListResponse, MailResendRequest, PasswordResetQuery, PasswordResetRequest, RegistrationResendRequest, TotpEnrollmentResponse, UserInfoRequestResponse, UserLoginRequest, UserLoginRequestResponse, UserRegistrationRequest};
+use crate::auth::LetterOutbox::{download_letter, list_letters};
+use crate::auth::MailOutbox::{list_mails, queue_mail};
+use crate::auth::Problem::ProblemCode;
+use crate::auth::Redirect::{redirect_to, Redirects};
+use crate::auth::Session::Token;
+use crate::auth::SessionCookie::SessionCookies;
+use crate::auth::Totp;
+use crate::server::{BackendServerInfo, DBPool, MailServer};
+
+const PASSWORD_RESET_VALIDITY_MINUTES: i64 = 60;
+const MAX_AUDIT_EXPORT_SIZE: i64 = 10000;
+
+pub async fn user_register(pool: Data<DBPool>, directory: Data<dyn CitizenDirectory>, config: web::Data<BackendServerInfo>, redirects: Data<Redirects>, origin: RequestOrigin, request: FormOrJson<UserRegistrationRequest>) -> Result<HttpResponse, UserRegistrationError> {
+    let redirect_error = redirects.optional_target(request.redirect_error.as_deref())?;
+    let redirect_success = redirects.optional_target(request.redirect_success.as_deref())?;
+    let state = request.state.clone();
+    return match register_citizen(pool, directory, config, request.into_inner(), origin).await {
+        Err(e) => match redirect_error {
+            Some(url) => Ok(redirect_to(url, &[("error", e.code())], state.as_deref())?),
+            None => Err(e)
+        },
+        Ok(()) => match redirect_success {
+            Some(url) => Ok(redirect_to(url, &[], state.as_deref())?),
+            None => Ok(HttpResponse::Found().append_header((LOCATION, HeaderValue::try_from("/page/login").unwrap())).finish())
+        }
+    };
+}
+
+/// Codes that were issued without storing the address are checked against the address of the citizen office
+async fn register_citizen(pool: Data<DBPool>, directory: Data<dyn CitizenDirectory>, config: web::Data<BackendServerInfo>, request: UserRegistrationRequest, origin: RequestOrigin) -> Result<(), UserRegistrationError> {
+    let lookup_pool = pool.clone();
+    let lookup_config = config.clone();
+    let code = request.code.clone();
+    let lookup = move || {
+        let db = lookup_pool.get().map_err(|_| UserRegistrationError::Db(DatabaseError::Connection))?;
+        check_pending_user_token(&db, &lookup_config.registration_code, &code)
+    };
+    //Unknown or expired codes are rejected and recorded by register_user
+    let directory_email = match web::block(lookup).await? {
+        Ok(pending) if pending.email.is_none() => Citizen { citizen_id: pending.citizen as u64 }
+            .get_citizen_info(directory.get_ref())
+            .await
+            .map_err(|_| UserRegistrationError::DataRetrieval)?
+            .email,
+        _ => None
+    };
+
+    let insert_user = move || {
         let db = pool.get().map_err(|_| UserRegistrationError::Db(DatabaseError::Connection))?;
-        register_user(&db, &request.into_inner())
+        register_user(&db, &config.registration_code, &request, directory_email.as_deref(), &origin)
     };
-    return match web::block(|| insert_user).await? {
-        Err(e) => {
-            redirect_error.map_or_else(|| Err(e), |url| Ok(HttpResponse::Found().append_header((LOCATION, HeaderValue::try_from(url).unwrap())).finish()))
-        }
-        Ok(()) => {
-            redirect_success.map_or_else(|| Ok(HttpResponse::Found().append_header((LOCATION, HeaderValue::try_from("/page/login").unwrap())).finish()),
-                                                 |url| Ok(HttpResponse::Found().append_header((LOCATION, HeaderValue::try_from(url).unwrap())).finish()))
+    web::block(insert_user).await?
+}
+
+/// Fetches the info for a login or verify response. If degraded responses are enabled,
+/// an unavailable citizen service leads to the last known info or none at all instead of an error
+async fn response_citizen_info(directory: &dyn CitizenDirectory, citizen: &impl IsCitizen, config: &BackendServerInfo) -> CitizenInfoRetrievalResult<(Option<CitizenInfo>, CitizenInfoStatus)> {
+    match citizen.get_citizen_info(directory).await {
+        Ok(info) => Ok((Some(info), CitizenInfoStatus::Current)),
+        Err(e) if e.is_transient() && config.citizen_directory.degraded_responses => {
+            warn!("Responding without current citizen info: {}", e);
+            Ok(match directory.last_known_info(citizen.get_citizen_id()) {
+                Some(info) => (Some(info), CitizenInfoStatus::Stale),
+                None => (None, CitizenInfoStatus::Unavailable)
+            })
         }
-    };
+        Err(e) => Err(e)
+    }
 }
 
-pub async fn user_login(pool: Data<DBPool>, request: web::Form<UserLoginRequest>) -> Result<HttpResponse, LoginError> {
+pub async fn user_login(pool: Data<DBPool>, directory: Data<dyn CitizenDirectory>, config: Data<BackendServerInfo>, redirects: Data<Redirects>, cookies: Data<SessionCookies>, origin: RequestOrigin, request: FormOrJson<UserLoginRequest>) -> Result<HttpResponse, LoginError> {
     let db = pool.get().map_err(|_| LoginError::Db(DatabaseError::Connection))?;
-    let redirect_success = request.redirect_success.clone();
-    let redirect_error = request.redirect_error.clone();
+    let redirect_success = redirects.optional_target(request.redirect_success.as_deref())?;
+    let redirect_error = redirects.optional_target(request.redirect_error.as_deref())?;
+    let state = request.state.clone();
 
     let request = request.into_inner();
+    let session_lifetime = chrono::Duration::hours(config.session.lifetime_hours);
 
-    let result = web::block(move || login_user(&db, &request))
+    let result = web::block(move || login_user(&db, &request, session_lifetime, &origin))
         .await?;
 
     let result = match result {
-        Err(e) => {
-            return redirect_error.map_or_else(|| Err(e), |url| Ok(HttpResponse::Found().append_header((LOCATION, HeaderValue::try_from(url).unwrap())).finish()));
-        }
+        Err(e) => return match redirect_error {
+            Some(url) => Ok(redirect_to(url, &[("error", e.code())], state.as_deref())?),
+            None => Err(e)
+        },
         Ok(r) => r
     };
 
+    let (info, info_status) = response_citizen_info(directory.get_ref(), &result.user, &config).await?;
     let response = UserInfoRequestResponse {
         citizen_id: result.user.id.clone(),
         username: result.user.username.clone(),
         user_session_token: result.new_session_token,
-        info: result.user.get_citizen_info().await?
+        info,
+        info_status
     };
 
-    let cookie = Cookie::build("user_session_token", response.user_session_token.clone())
-        .domain("supersmartcity.de")
-        .finish();
-
-    return redirect_success.map_or_else(|| Ok(HttpResponse::Ok().cookie(cookie.clone()).json(response.clone())), |url| {
-        Ok(HttpResponse::Found()
-            .append_header((LOCATION, HeaderValue::try_from(format!("{}?token={}", url, &response.user_session_token)).unwrap()))
-            .cookie(cookie.clone())
-            .finish())
-    });
-}
+    let cookie = cookies.build(USER_SESSION_COOKIE, &response.user_session_token, &result.session_expires);
 
-pub async fn user_verify(pool: Data<DBPool>, request: web::Form<TokenValidateRequest>) -> Result<HttpResponse, SessionRetrievalError> {
-    let check_token_from_request = {
-        let db = pool.get().map_err(|_| SessionRetrievalError::Db(DatabaseError::Connection))?;
-        let code = &request.code;
-        check_user_session_token(&db, code)
+    return match redirect_success {
+        Some(url) => {
+            let mut redirect = redirect_to(url, &[("token", &response.user_session_token)], state.as_deref())?;
+            redirect.add_cookie(&cookie).map_err(|_| RedirectError::Invalid)?;
+            Ok(redirect)
+        }
+        None => Ok(HttpResponse::Ok().cookie(cookie).json(response))
     };
+}
 
-    let user = web::block(|| check_token_from_request)
-        .await??;
+pub async fn user_verify(directory: Data<dyn CitizenDirectory>, config: Data<BackendServerInfo>, authenticated: AuthenticatedUser) -> Result<HttpResponse, SessionRetrievalError> {
+    let AuthenticatedUser { user, token } = authenticated;
 
+    let (info, info_status) = response_citizen_info(directory.get_ref(), &user, &config).await?;
     Ok(HttpResponse::Ok()
         .json(UserInfoRequestResponse {
             citizen_id: user.id,
-            user_session_token: request.code.clone(),
-            info: user.get_citizen_info().await?,
+            user_session_token: token,
+            info,
+            info_status,
             username: user.username,
         }))
 }
@@ -101,43 +157,86 @@ pub async fn employee_login_external(_: web::Query<ExternalUserLoginRequest>) ->
 
 }
 
-pub async fn employee_register(pool: web::Data<DBPool>, data: web::Form<EmployeeRegisterRequest>) -> Result<HttpResponse, UserRegistrationError> {
+pub async fn employee_invite(pool: web::Data<DBPool>, mail: web::Data<MailServer>, config: web::Data<BackendServerInfo>, origin: RequestOrigin, data: FormOrJson<EmployeeInviteRequest>) -> Result<HttpResponse, UserRegistrationError> {
     let data = data.into_inner();
-    let db = pool.get().map_err(|e| UserRegistrationError::Db(DatabaseError::Connection))?;
-
-    if !(&data.code == "ROOT") {
-        let user_verification = move || {
-            verify_employee(&db, &data.code.clone())
+    let validity = chrono::Duration::hours(config.invitation.validity_hours);
+    let public_url = config.public_url.clone();
+    let bootstrap_token = config.invitation.bootstrap_token.clone().filter(|t| !t.is_empty());
+    let invitation_creation = move || {
+        let db = pool.get().map_err(|_| UserRegistrationError::Db(DatabaseError::Connection))?;
+        // The bootstrap token only works as long as there is no employee yet
+        let bootstrap = bootstrap_token.map_or(false, |t| t == data.code) && !has_employees(&db)?;
+        let inviter = match bootstrap {
+            true => None,
+            false => Some(authorize_employee(&db, &data.code, Role::Admin)?.id)
         };
-
-        web::block(user_verification)
-            .await??;
+        let email = data.info.email.clone().ok_or(UserRegistrationError::MissingEmail)?;
+
+        db.transaction::<_, UserRegistrationError, _>(|| {
+            let invitation = invite_employee(&db, &data.info, &email, inviter, validity, &origin)?;
+            let link = format!("{}/employee/invitation?token={}", public_url, &invitation.token);
+            queue_mail(&db, &employee_invitation_mail(&mail.templates, &data.info, &invitation, &link, data.locale.as_deref())?, invitation.expires)?;
+            Ok(invitation)
+        })
     };
+    let invitation = web::block(invitation_creation).await??;
 
-    let user_creation = move || {
-        let db = pool.clone()
-            .get()
-            .map_err(|e| UserRegistrationError::Db(DatabaseError::Connection))?;
-        register_employee(&db, &data.info, &data.credentials)
-    };
-    web::block(user_creation).await??;
+    Ok(HttpResponse::Ok().json(EmployeeInvitationResponse {
+        info_id: invitation.info_id,
+        email: invitation.email,
+        expires: invitation.expires
+    }))
+}
 
-    Ok(HttpResponse::Ok().finish())
+pub async fn employee_invitation_page(_: web::Query<EmployeeInvitationQuery>) -> impl Responder {
+    NamedFile::open(PathBuf::from(r"static_content/employee_invitation.html")).unwrap()
+}
+
+pub async fn employee_invitation_totp(pool: web::Data<DBPool>, query: web::Query<EmployeeInvitationQuery>) -> Result<HttpResponse, UserRegistrationError> {
+    let db = pool.get().map_err(|_| UserRegistrationError::Db(DatabaseError::Connection))?;
+    let token = query.into_inner().token;
+
+    let (invitation, totp_secret) = web::block(move || employee_invitation_totp_secret(&db, &token)).await??;
+
+    let provisioning_uri = Totp::provisioning_uri(&totp_secret, &invitation.email, "SmartCity");
+    Ok(HttpResponse::Ok().json(TotpEnrollmentResponse {
+        totp_secret,
+        provisioning_uri
+    }))
 }
 
-pub async fn employee_login(pool: web::Data<DBPool>, credentials: web::Form<UserLoginRequest>) -> LoginResult<HttpResponse> {
+pub async fn employee_register(req: HttpRequest, pool: web::Data<DBPool>, origin: RequestOrigin, data: FormOrJson<EmployeeRegisterRequest>) -> Result<HttpResponse, UserRegistrationError> {
+    let data = data.into_inner();
+    let db = pool.get().map_err(|_| UserRegistrationError::Db(DatabaseError::Connection))?;
+
+    let employee = web::block(move || register_employee(&db, &data, &origin)).await??;
+
+    Ok(match is_json_request(&req) {
+        true => HttpResponse::Created().json(EmployeeRegisterResponse {
+            employee_id: employee.id,
+            username: employee.username
+        }),
+        false => HttpResponse::Found().append_header((LOCATION, HeaderValue::try_from("/employee/external").unwrap())).finish()
+    })
+}
+
+pub async fn employee_login(pool: web::Data<DBPool>, config: web::Data<BackendServerInfo>, redirects: web::Data<Redirects>, cookies: web::Data<SessionCookies>, origin: RequestOrigin, credentials: FormOrJson<EmployeeLoginRequest>) -> LoginResult<HttpResponse> {
     let db = pool.get().map_err(|_| LoginError::Db(DatabaseError::Connection))?;
-    let redirect_error = credentials.redirect_error.clone();
-    let redirect_success = credentials.redirect_success.clone();
-    let login_response = match web::block(move || login_employee(&db, &credentials.credentials)).await? {
-        Err(e) => {
-            return redirect_error.map_or_else(|| Err(e), |url| Ok(HttpResponse::Found().append_header((LOCATION, HeaderValue::try_from(url).unwrap())).finish()));
+    let redirect_error = redirects.optional_target(credentials.redirect_error.as_deref())?;
+    let redirect_success = redirects.optional_target(credentials.redirect_success.as_deref())?;
+    let state = credentials.state.clone();
+    let session_lifetime = chrono::Duration::hours(config.session.lifetime_hours);
+    let login_response = match web::block(move || login_employee(&db, &credentials.credentials, credentials.otp.as_deref(), session_lifetime, &origin)).await? {
+        Err(e) => return match redirect_error {
+            Some(url) => Ok(redirect_to(url, &[("error", e.code())], state.as_deref())?),
+            None => Err(e)
         },
         Ok(r) => r
     };
 
     let username = login_response.employee.username.clone();
     let e_id = login_response.employee.id;
+    let session_expires = login_response.session_expires;
     let get_info = move ||  {
         let db = pool.get().map_err(|_| LoginError::Db(DatabaseError::Connection))?;
         get_employee_info(&db, &login_response.employee).map_err(|e| LoginError::SessionRetrieval(e.into()))
@@ -148,37 +247,28 @@ pub async fn employee_login(pool: web::Data<DBPool>, credentials: web::Form<User
         id: e_id,
         username,
         employee_session_token: login_response.new_employee_token.clone(),
-        info: NewEmployeeInfo {firstname: info.firstname, lastname: info.lastname}
-    };
-
-    let cookie = Cookie::build("employee_session_token", response.employee_session_token.clone())
-        .domain("supersmartcity.de")
-        .finish();
-    redirect_success.map_or_else(|| {
-        Ok(HttpResponse::Ok()
-            .cookie(cookie.clone())
-            .json(response.clone()))
-    }, |url| {
-        let http_response = HttpResponse::Found()
-            .append_header((LOCATION, HeaderValue::try_from(format!("{}?token={}", url, &response.employee_session_token)).unwrap()))
-            .cookie(cookie.clone())
-            .finish();
-        Ok(http_response)
-    })
-}
-
-pub async fn employee_verify(pool: web::Data<DBPool>, token: web::Form<TokenValidateRequest>) -> SessionRetrievalResult<HttpResponse> {
-    let db = pool.get().map_err(|_| SessionRetrievalError::Db(DatabaseError::Connection))?;
-    let token = token.into_inner().code;
+        info: info.into()
+    };
 
-    let verify_result = web::block(move || verify_employee(&db, &token)).await??;
+    let cookie = cookies.build(EMPLOYEE_SESSION_COOKIE, &response.employee_session_token, &session_expires);
+    match redirect_success {
+        Some(url) => {
+            let mut redirect = redirect_to(url, &[("token", &response.employee_session_token)], state.as_deref())?;
+            redirect.add_cookie(&cookie).map_err(|_| RedirectError::Invalid)?;
+            Ok(redirect)
+        }
+        None => Ok(HttpResponse::Ok().cookie(cookie).json(response))
+    }
+}
 
-    let e_id = verify_result.employee.id.clone();
-    let username = verify_result.employee.username.clone();
+pub async fn employee_verify(pool: web::Data<DBPool>, authenticated: AuthenticatedEmployee) -> SessionRetrievalResult<HttpResponse> {
+    let e_id = authenticated.employee.id;
+    let username = authenticated.employee.username.clone();
+    let token = authenticated.token.clone();
 
     let get_info = move ||  {
         let db = pool.get().map_err(|_| SessionRetrievalError::Db(DatabaseError::Connection))?;
-        get_employee_info(&db, &verify_result.employee)
+        get_employee_info(&db, &authenticated.employee)
     };
 
     let info = web::block(get_info)
@@ -187,10 +277,436 @@ pub async fn employee_verify(pool: web::Data<DBPool>, token: web::Form<TokenVali
     let response = EmployeeInfoRequestResponse {
         id: e_id,
         username,
-        employee_session_token: verify_result.new_employee_token.clone(),
-        info: NewEmployeeInfo {firstname: info.firstname, lastname: info.lastname}
+        employee_session_token: token,
+        info: info.into()
     };
 
     Ok(HttpResponse::Ok().json(response))
 
-}
\ No newline at end of file
+}
+
+async fn administer_employee<F>(pool: web::Data<DBPool>, code: Token, employee_id: u64, event_type: AuditEventType, origin: RequestOrigin, action: F) -> EmployeeAdministrationResult<HttpResponse>
+    where F: FnOnce(&MysqlConnection, &EmployeeLogin) -> EmployeeAdministrationResult<Option<String>> + Send + 'static {
+    let administration = move || {
+        let db = pool.get().map_err(|_| EmployeeAdministrationError::Db(DatabaseError::Connection))?;
+        let admin = authorize_employee(&db, &code, Role::Admin)?;
+
+        let result = action(&db, &admin);
+        let mut event = NewAuditEvent::from_result(AuditParty::Employee(admin.id), event_type, &result)
+            .subject(AuditParty::Employee(employee_id))
+            .origin(&origin);
+        if let Ok(Some(details)) = &result {
+            event = event.details(details.clone());
+        }
+        record_event(&db, &event)?;
+        result
+    };
+    web::block(administration).await??;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn ensure_not_own_account(admin: &EmployeeLogin, employee_id: u64) -> EmployeeAdministrationResult<()> {
+    (admin.id != employee_id)
+        .then(|| ())
+        .ok_or(EmployeeAdministrationError::OwnAccount)
+}
+
+pub async fn employee_disable(pool: web::Data<DBPool>, origin: RequestOrigin, request: FormOrJson<EmployeeAdministrationRequest>) -> EmployeeAdministrationResult<HttpResponse> {
+    let request = request.into_inner();
+    administer_employee(pool, request.code, request.employee_id, AuditEventType::EmployeeDisabled, origin, move |db, admin| {
+        ensure_not_own_account(admin, request.employee_id)?;
+        set_employee_disabled(db, request.employee_id, true).map(|_| None)
+    }).await
+}
+
+pub async fn employee_enable(pool: web::Data<DBPool>, origin: RequestOrigin, request: FormOrJson<EmployeeAdministrationRequest>) -> EmployeeAdministrationResult<HttpResponse> {
+    let request = request.into_inner();
+    administer_employee(pool, request.code, request.employee_id, AuditEventType::EmployeeEnabled, origin, move |db, _| {
+        set_employee_disabled(db, request.employee_id, false).map(|_| None)
+    }).await
+}
+
+pub async fn employee_delete(pool: web::Data<DBPool>, origin: RequestOrigin, request: FormOrJson<EmployeeAdministrationRequest>) -> EmployeeAdministrationResult<HttpResponse> {
+    let request = request.into_inner();
+    administer_employee(pool, request.code, request.employee_id, AuditEventType::EmployeeDeleted, origin, move |db, admin| {
+        ensure_not_own_account(admin, request.employee_id)?;
+        delete_employee(db, request.employee_id).map(|_| None)
+    }).await
+}
+
+pub async fn employee_grant_role(pool: web::Data<DBPool>, origin: RequestOrigin, request: FormOrJson<EmployeeRoleRequest>) -> EmployeeAdministrationResult<HttpResponse> {
+    let request = request.into_inner();
+    administer_employee(pool, request.code, request.employee_id, AuditEventType::EmployeeRoleGranted, origin, move |db, _| {
+        set_employee_role(db, request.employee_id, request.role, true).map(|_| Some(format!("role: {}", request.role.as_str())))
+    }).await
+}
+
+pub async fn employee_revoke_role(pool: web::Data<DBPool>, origin: RequestOrigin, request: FormOrJson<EmployeeRoleRequest>) -> EmployeeAdministrationResult<HttpResponse> {
+    let request = request.into_inner();
+    administer_employee(pool, request.code, request.employee_id, AuditEventType::EmployeeRoleRevoked, origin, move |db, admin| {
+        ensure_not_own_account(admin, request.employee_id)?;
+        set_employee_role(db, request.employee_id, request.role, false).map(|_| Some(format!("role: {}", request.role.as_str())))
+    }).await
+}
+
+pub async fn employee_list(pool: web::Data<DBPool>, authenticated: AuthenticatedEmployee, request: web::Query<EmployeeListRequest>) -> EmployeeAdministrationResult<HttpResponse> {
+    let request = request.into_inner();
+    let listing = move || {
+        let db = pool.get().map_err(|_| EmployeeAdministrationError::Db(DatabaseError::Connection))?;
+        require_role(&db, authenticated.employee, Role::Admin)?;
+
+        let (page, per_page) = page_bounds(request.page, request.per_page);
+        let (total, employees) = list_employees(&db, &request)?;
+        let employees = describe_employees(&db, employees)?;
+
+        Ok::<_, EmployeeAdministrationError>(EmployeeListResponse { page, per_page, total, employees })
+    };
+
+    Ok(HttpResponse::Ok().json(web::block(listing).await??))
+}
+
+pub async fn employee_directory(pool: web::Data<DBPool>, _: AuthenticatedEmployee, request: web::Query<EmployeeListRequest>) -> EmployeeAdministrationResult<HttpResponse> {
+    let request = request.into_inner();
+    let listing = move || {
+        let db = pool.get().map_err(|_| EmployeeAdministrationError::Db(DatabaseError::Connection))?;
+
+        let (page, per_page) = page_bounds(request.page, request.per_page);
+        let (total, employees) = list_employees(&db, &request)?;
+        let employees = employees
+            .into_iter()
+            .map(|(info, _)| EmployeeDirectoryEntry { info_id: info.id, info: info.into() })
+            .collect::<Vec<_>>();
+
+        Ok::<_, EmployeeAdministrationError>(EmployeeListResponse { page, per_page, total, employees })
+    };
+
+    Ok(HttpResponse::Ok().json(web::block(listing).await??))
+}
+
+async fn administer_citizen<F, T>(pool: web::Data<DBPool>, code: Token, citizen_id: u64, event_type: AuditEventType, origin: RequestOrigin, action: F) -> CitizenAdministrationResult<T>
+    where F: FnOnce(&MysqlConnection) -> CitizenAdministrationResult<T> + Send + 'static,
+          T: Send + 'static {
+    let administration = move || {
+        let db = pool.get().map_err(|_| CitizenAdministrationError::Db(DatabaseError::Connection))?;
+        let employee = authorize_employee(&db, &code, Role::Support)?;
+
+        let result = action(&db);
+        record_event(&db, &NewAuditEvent::from_result(AuditParty::Employee(employee.id), event_type, &result)
+            .subject(AuditParty::Citizen(citizen_id))
+            .origin(&origin))?;
+        result
+    };
+    web::block(administration).await?
+}
+
+/// Checks the token before the citizen service is asked, so it can not be probed without permission
+async fn authorize_support(pool: &web::Data<DBPool>, code: &Token) -> CitizenAdministrationResult<()> {
+    let pool = pool.clone();
+    let code = code.clone();
+    web::block(move || {
+        let db = pool.get().map_err(|_| CitizenAdministrationError::Db(DatabaseError::Connection))?;
+        authorize_employee(&db, &code, Role::Support)?;
+        Ok(())
+    }).await?
+}
+
+/// Fetches the citizen info and makes sure the citizen can be reached by mail
+async fn citizen_mail_info(directory: &dyn CitizenDirectory, citizen_id: u64) -> CitizenAdministrationResult<CitizenInfo> {
+    let info = Citizen { citizen_id }.get_citizen_info(directory).await?;
+    info.email
+        .is_some()
+        .then(|| info)
+        .ok_or(CitizenAdministrationError::MissingEmail)
+}
+
+pub async fn citizen_lookup(pool: web::Data<DBPool>, origin: RequestOrigin, authenticated: AuthenticatedEmployee, request: web::Query<CitizenLookupRequest>) -> CitizenAdministrationResult<HttpResponse> {
+    let request = request.into_inner();
+    let lookup = move || {
+        let db = pool.get().map_err(|_| CitizenAdministrationError::Db(DatabaseError::Connection))?;
+        let employee = require_role(&db, authenticated.employee, Role::Support)?;
+
+        let account = find_citizen_account(&db, request.citizen_id, request.username.as_deref());
+        let mut event = NewAuditEvent::from_result(AuditParty::Employee(employee.id), AuditEventType::CitizenLookup, &account)
+            .origin(&origin);
+        if let Some(c_id) = account.as_ref().map(|a| a.citizen_id).ok().or(request.citizen_id) {
+            event = event.subject(AuditParty::Citizen(c_id));
+        }
+        if let Some(name) = &request.username {
+            event = event.details(format!("username: {}", name));
+        }
+        record_event(&db, &event)?;
+        account
+    };
+
+    Ok(HttpResponse::Ok().json(web::block(lookup).await??))
+}
+
+pub async fn citizen_logout(pool: web::Data<DBPool>, origin: RequestOrigin, request: FormOrJson<CitizenAdministrationRequest>) -> CitizenAdministrationResult<HttpResponse> {
+    let request = request.into_inner();
+    let citizen_id = request.citizen_id;
+    administer_citizen(pool, request.code, citizen_id, AuditEventType::CitizenLogout, origin, move |db| {
+        logout_user(db, citizen_id)
+    }).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn citizen_lock(pool: web::Data<DBPool>, origin: RequestOrigin, request: FormOrJson<CitizenAdministrationRequest>) -> CitizenAdministrationResult<HttpResponse> {
+    let request = request.into_inner();
+    let citizen_id = request.citizen_id;
+    administer_citizen(pool, request.code, citizen_id, AuditEventType::CitizenLocked, origin, move |db| {
+        set_user_locked(db, citizen_id, true)
+    }).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn citizen_unlock(pool: web::Data<DBPool>, origin: RequestOrigin, request: FormOrJson<CitizenAdministrationRequest>) -> CitizenAdministrationResult<HttpResponse> {
+    let request = request.into_inner();
+    let citizen_id = request.citizen_id;
+    administer_citizen(pool, request.code, citizen_id, AuditEventType::CitizenUnlocked, origin, move |db| {
+        set_user_locked(db, citizen_id, false)
+    }).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn citizen_password_reset(pool: web::Data<DBPool>, mail: web::Data<MailServer>, directory: web::Data<dyn CitizenDirectory>, config: web::Data<BackendServerInfo>, origin: RequestOrigin, request: FormOrJson<CitizenAdministrationRequest>) -> CitizenAdministrationResult<HttpResponse> {
+    let request = request.into_inner();
+    let citizen_id = request.citizen_id;
+    authorize_support(&pool, &request.code).await?;
+    let info = citizen_mail_info(directory.get_ref(), citizen_id).await?;
+
+    administer_citizen(pool, request.code, citizen_id, AuditEventType::PasswordResetRequested, origin, move |db| {
+        let reset = create_password_reset(db, citizen_id, chrono::Duration::minutes(PASSWORD_RESET_VALIDITY_MINUTES))?;
+        let link = format!("{}/password/reset?token={}", config.public_url, reset.token);
+        queue_mail(db, &password_reset_mail(&mail.templates, &info, &link)?, reset.expires)?;
+        Ok(())
+    }).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn citizen_registration_code(pool: web::Data<DBPool>, mail: web::Data<MailServer>, directory: web::Data<dyn CitizenDirectory>, config: web::Data<BackendServerInfo>, origin: RequestOrigin, request: FormOrJson<CitizenAdministrationRequest>) -> CitizenAdministrationResult<HttpResponse> {
+    let request = request.into_inner();
+    let citizen_id = request.citizen_id;
+    authorize_support(&pool, &request.code).await?;
+    let info = Citizen { citizen_id }.get_citizen_info(directory.get_ref()).await?;
+
+    administer_citizen(pool, request.code, citizen_id, AuditEventType::RegistrationCodeIssued, origin, move |db| {
+        let code = reissue_pending_user(db, &config.registration_code, citizen_id, info.email.as_deref())?;
+        queue_code_delivery(db, citizen_id, &citizen_code_delivery(&mail.templates, &mail.letters, &info, &code)?)?;
+        Ok(())
+    }).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Sends a new registration code to the citizen, by mail to the address of the citizen office or by letter.
+/// The answer is the same whether or not a code was sent, only callers who asked too often from the same address get an error
+pub async fn citizen_code_resend(pool: web::Data<DBPool>, mail: web::Data<MailServer>, directory: web::Data<dyn CitizenDirectory>, config: web::Data<BackendServerInfo>, origin: RequestOrigin, request: FormOrJson<RegistrationResendRequest>) -> Result<HttpResponse, UserRegistrationError> {
+    let request = request.into_inner();
+    if request.citizen_id.is_none() && request.email.is_none() {
+        return Err(UserRegistrationError::MissingIdentifier);
+    }
+    let email = request.email;
+
+    let lookup_pool = pool.clone();
+    let lookup_origin = origin.clone();
+    let lookup_email = email.clone();
+    let lookup = move || {
+        let db = lookup_pool.get().map_err(|_| UserRegistrationError::Db(DatabaseError::Connection))?;
+        check_resend_origin(&db, &lookup_origin)?;
+        Ok::<_, UserRegistrationError>(find_pending_user(&db, request.citizen_id, lookup_email.as_deref())?)
+    };
+    let pending = web::block(lookup).await??;
+
+    let citizen_id = pending.as_ref().map(|p| p.citizen as u64);
+    let result = match citizen_id {
+        Some(citizen_id) => resend_citizen_code(pool.clone(), mail, directory, config, citizen_id, email).await,
+        None => Err(UserRegistrationError::InvalidCitizenCode)
+    };
+    if let Err(e) = &result {
+        warn!("No registration code resent: {}", e);
+    }
+
+    let record = move || {
+        let db = pool.get().map_err(|_| UserRegistrationError::Db(DatabaseError::Connection))?;
+        let mut event = NewAuditEvent::from_result(AuditParty::Anonymous, AuditEventType::RegistrationCodeResent, &result)
+            .origin(&origin);
+        if let Some(citizen_id) = citizen_id {
+            event = event.subject(AuditParty::Citizen(citizen_id));
+        }
+        try_record_event(&db, &event);
+        Ok::<_, UserRegistrationError>(())
+    };
+    web::block(record).await??;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Replaces the code of the citizen and sends it to the address of the citizen office, or by letter if there is none.
+/// A given `email` has to be the address of the citizen office
+async fn resend_citizen_code(pool: web::Data<DBPool>, mail: web::Data<MailServer>, directory: web::Data<dyn CitizenDirectory>, config: web::Data<BackendServerInfo>, citizen_id: u64, email: Option<String>) -> Result<(), UserRegistrationError> {
+    let info = Citizen { citizen_id }
+        .get_citizen_info(directory.get_ref())
+        .await
+        .map_err(|_| UserRegistrationError::DataRetrieval)?;
+    if let Some(email) = &email {
+        info.email.as_deref()
+            .map_or(false, |address| address.trim().eq_ignore_ascii_case(email.trim()))
+            .then(|| ())
+            .ok_or(UserRegistrationError::MailMismatch)?;
+    }
+
+    let resend = move || {
+        let db = pool.get().map_err(|_| UserRegistrationError::Db(DatabaseError::Connection))?;
+        db.transaction::<_, UserRegistrationError, _>(|| {
+            let code = renew_pending_user(&db, &config.registration_code, citizen_id, info.email.as_deref())?;
+            let delivery = match info.email {
+                Some(_) => CodeDelivery::Mail(citizen_code_mail(&mail.templates, &info, &code)?),
+                None => CodeDelivery::Letter(mail.letters.render(&info, &code)?)
+            };
+            queue_code_delivery(&db, citizen_id, &delivery)?;
+            Ok(())
+        })
+    };
+    web::block(resend).await?
+}
+
+pub async fn password_reset_page(_: web::Query<PasswordResetQuery>) -> impl Responder {
+    NamedFile::open(PathBuf::from(r"static_content/password_reset.html")).unwrap()
+}
+
+pub async fn password_reset(pool: web::Data<DBPool>, origin: RequestOrigin, request: FormOrJson<PasswordResetRequest>) -> Result<HttpResponse, UserRegistrationError> {
+    let request = request.into_inner();
+    let reset = move || {
+        let db = pool.get().map_err(|_| UserRegistrationError::Db(DatabaseError::Connection))?;
+        let citizen_id = reset_password(&db, &request.token, &request.password)?;
+
+        record_event(&db, &NewAuditEvent::new(AuditParty::Citizen(citizen_id), AuditEventType::PasswordReset, AuditOutcome::Success)
+            .subject(AuditParty::Citizen(citizen_id))
+            .origin(&origin))?;
+        Ok::<_, UserRegistrationError>(())
+    };
+    web::block(reset).await??;
+
+    Ok(HttpResponse::Found().append_header((LOCATION, HeaderValue::try_from("/page/login").unwrap())).finish())
+}
+
+pub async fn audit_events(pool: web::Data<DBPool>, authenticated: AuthenticatedEmployee, request: web::Query<AuditQueryRequest>) -> EmployeeAdministrationResult<HttpResponse> {
+    let request = request.into_inner();
+    let export = request.format == Some(AuditExportFormat::Csv);
+    let (page, per_page) = if export {
+        (1, MAX_AUDIT_EXPORT_SIZE)
+    } else {
+        page_bounds(request.page, request.per_page)
+    };
+
+    let query = move || {
+        let db = pool.get().map_err(|_| EmployeeAdministrationError::Db(DatabaseError::Connection))?;
+        require_role(&db, authenticated.employee, Role::Auditor)?;
+        Ok::<_, EmployeeAdministrationError>(query_events(&db, &request, (page - 1) * per_page, per_page)?)
+    };
+    let (total, events) = web::block(query).await??;
+
+    if export && total > MAX_AUDIT_EXPORT_SIZE {
+        return Err(EmployeeAdministrationError::ExportTooLarge);
+    }
+    if export {
+        return Ok(HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .append_header((CONTENT_DISPOSITION, r#"attachment; filename="audit_events.csv""#))
+            .body(events_to_csv(&events)));
+    }
+    Ok(HttpResponse::Ok().json(AuditQueryResponse { page, per_page, total, events }))
+}
+
+pub async fn audit_verify(pool: web::Data<DBPool>, config: web::Data<BackendServerInfo>, authenticated: AuthenticatedEmployee) -> EmployeeAdministrationResult<HttpResponse> {
+    let verification = move || {
+        let db = pool.get().map_err(|_| EmployeeAdministrationError::Db(DatabaseError::Connection))?;
+        require_role(&db, authenticated.employee, Role::Auditor)?;
+        Ok::<_, EmployeeAdministrationError>(verify_audit_trail(&db, &config.audit)?)
+    };
+
+    Ok(HttpResponse::Ok().json(web::block(verification).await??))
+}
+
+pub async fn employee_mails(pool: web::Data<DBPool>, authenticated: AuthenticatedEmployee, request: web::Query<MailListRequest>) -> EmployeeAdministrationResult<HttpResponse> {
+    let request = request.into_inner();
+    let listing = move || {
+        let db = pool.get().map_err(|_| EmployeeAdministrationError::Db(DatabaseError::Connection))?;
+        require_role(&db, authenticated.employee, Role::Admin)?;
+
+        let (page, per_page) = page_bounds(request.page, request.per_page);
+        let (total, mails) = list_mails(&db, request.status, (page - 1) * per_page, per_page)?;
+        let mails = mails.into_iter().map(Into::into).collect();
+
+        Ok::<_, EmployeeAdministrationError>(MailListResponse { page, per_page, total, mails })
+    };
+
+    Ok(HttpResponse::Ok().json(web::block(listing).await??))
+}
+
+pub async fn employee_mail_resend(pool: web::Data<DBPool>, origin: RequestOrigin, request: FormOrJson<MailResendRequest>) -> EmployeeAdministrationResult<HttpResponse> {
+    let request = request.into_inner();
+    let resend = move || {
+        let db = pool.get().map_err(|_| EmployeeAdministrationError::Db(DatabaseError::Connection))?;
+        let admin = authorize_employee(&db, &request.code, Role::Admin)?;
+
+        let result = resend_mail(&db, request.mail_id);
+        let details = match &result {
+            Ok(_) => format!("mail: {}", request.mail_id),
+            Err(e) => format!("{} (mail: {})", e, request.mail_id)
+        };
+        record_event(&db, &NewAuditEvent::from_result(AuditParty::Employee(admin.id), AuditEventType::MailResent, &result)
+            .details(details)
+            .origin(&origin))?;
+        result
+    };
+    web::block(resend).await??;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn employee_letters(pool: web::Data<DBPool>, authenticated: AuthenticatedEmployee, request: web::Query<LetterListRequest>) -> CitizenAdministrationResult<HttpResponse> {
+    let request = request.into_inner();
+    let listing = move || {
+        let db = pool.get().map_err(|_| CitizenAdministrationError::Db(DatabaseError::Connection))?;
+        require_role(&db, authenticated.employee, Role::Support)?;
+
+        let (page, per_page) = page_bounds(request.page, request.per_page);
+        let (total, letters) = list_letters(&db, !request.all, (page - 1) * per_page, per_page)?;
+
+        Ok::<_, CitizenAdministrationError>(LetterListResponse { page, per_page, total, letters })
+    };
+
+    Ok(HttpResponse::Ok().json(web::block(listing).await??))
+}
+
+pub async fn employee_letter_download(pool: web::Data<DBPool>, origin: RequestOrigin, authenticated: AuthenticatedEmployee, request: web::Query<LetterDownloadRequest>) -> CitizenAdministrationResult<HttpResponse> {
+    let letter_id = request.letter_id;
+    let download = move || {
+        let db = pool.get().map_err(|_| CitizenAdministrationError::Db(DatabaseError::Connection))?;
+        let employee = require_role(&db, authenticated.employee, Role::Support)?;
+
+        let result = download_letter(&db, letter_id)
+            .map_err(CitizenAdministrationError::from)
+            .and_then(|letter| letter.ok_or(CitizenAdministrationError::LetterNotFound));
+        let mut event = NewAuditEvent::from_result(AuditParty::Employee(employee.id), AuditEventType::LetterDownloaded, &result)
+            .details(format!("letter: {}", letter_id))
+            .origin(&origin);
+        if let Ok((letter, _)) = &result {
+            event = event.subject(AuditParty::Citizen(letter.citizen_id));
+        }
+        record_event(&db, &event)?;
+        result
+    };
+    let (_, pdf) = web::block(download).await??;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/pdf")
+        .append_header((CONTENT_DISPOSITION, format!(r#"attachment; filename="letter_{}.pdf""#, letter_id)))
+        .body(pdf))
+}
@@ -1,11 +1,11 @@
 use std::fmt::{Display, Formatter};
 use actix_web::{error, HttpRequest, HttpResponse, ResponseError};
-use actix_web::error::{QueryPayloadError, UrlencodedError};
+use actix_web::error::{JsonPayloadError, QueryPayloadError, UrlencodedError};
 use actix_web::http::StatusCode;
 use diesel::r2d2;
 use diesel::result::{DatabaseErrorKind, Error};
-use serde_json::json;
 use thiserror::Error;
+use crate::auth::Problem::{Problem, ProblemCode};
 
 
 pub trait IntoHttpError<T> {
@@ -25,21 +25,21 @@ impl <T, E: std::fmt::Debug> IntoHttpError<T> for Result<T, E> {
     }
 }
 
-pub fn query_error_handler(err: QueryPayloadError, _: &HttpRequest) -> actix_web::Error{
-    error::InternalError::from_response("",
-                                        HttpResponse::BadRequest()
-                                            .content_type("application/json")
-                                            .body(format!(r#"{{"type": "InvalidRequest", "error": "{:?}"}}"#, err))).into()
+pub fn query_error_handler(err: QueryPayloadError, _: &HttpRequest) -> actix_web::Error {
+    invalid_request(err)
 }
 
-
 pub fn form_error_handler(err: UrlencodedError, _: &HttpRequest) -> actix_web::Error {
-    error::InternalError::from_response(
-        "",
-        HttpResponse::BadRequest()
-            .content_type("application/json")
-            .body(format!(r#"{{"type": "InvalidRequest", "error": "{:?}"}}"#, err)),
-    ).into()
+    invalid_request(err)
+}
+
+pub fn json_error_handler(err: JsonPayloadError, _: &HttpRequest) -> actix_web::Error {
+    invalid_request(err)
+}
+
+fn invalid_request<E: std::fmt::Display>(err: E) -> actix_web::Error {
+    let problem = Problem::new(StatusCode::BAD_REQUEST, "invalid_request", &err.to_string());
+    error::InternalError::from_response("", problem.response()).into()
 }
 
 pub type CredentialsCreationResult<T> =  Result<T, CredentialsCreationError>;
@@ -61,12 +61,28 @@ pub enum DatabaseError {
 
 impl ResponseError for DatabaseError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code())
-            .json(json!({"type": "database", "error": &self.to_string()}))
+        Problem::new(self.status_code(), self.code(), &self.to_string()).response()
     }
 
     fn status_code(&self) -> StatusCode {
-        StatusCode::INTERNAL_SERVER_ERROR
+        match self {
+            Self::Connection => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Duplicate => StatusCode::CONFLICT,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Constraint => StatusCode::CONFLICT,
+            Self::Other(_) => StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+impl ProblemCode for DatabaseError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Connection => "database_unavailable",
+            Self::Duplicate => "duplicate",
+            Self::NotFound => "not_found",
+            Self::Constraint => "constraint_violation",
+            Self::Other(_) => "database_error"
+        }
     }
 }
 impl From<diesel::result::Error> for DatabaseError {
@@ -80,6 +96,7 @@ impl From<diesel::result::Error> for DatabaseError {
                     _ => DatabaseError::Other(err)
                 }
             },
+            Error::NotFound => Self::NotFound,
             _ => Self::Other(err)
         }
     }
@@ -92,7 +109,45 @@ pub enum CitizenInfoRetrievalError {
     Request(#[from] reqwest::Error),
 
     #[error("Unable to parse citizen info")]
-    Parse(#[from] serde_json::error::Error)
+    Parse(#[from] serde_json::error::Error),
+
+    #[error("Citizen {0} is unknown")]
+    NotFound(u64),
+
+    #[error("Citizen service responded with {0}")]
+    Status(reqwest::StatusCode),
+
+    #[error("Citizen service is currently unavailable")]
+    Unavailable,
+}
+
+impl CitizenInfoRetrievalError {
+    /// Whether asking again later might succeed
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Request(_) | Self::Unavailable => true,
+            Self::Status(s) => s.is_server_error(),
+            Self::Parse(_) | Self::NotFound(_) => false,
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            _ if self.is_transient() => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::BAD_GATEWAY
+        }
+    }
+}
+
+impl ProblemCode for CitizenInfoRetrievalError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::NotFound(_) => "citizen_not_found",
+            _ if self.is_transient() => "citizen_service_unavailable",
+            _ => "citizen_service_error"
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -122,6 +177,15 @@ pub enum UserRegistrationError {
     #[error("Citizen Code could not be found")]
     InvalidCitizenCode,
 
+    #[error("Citizen Code has expired")]
+    ExpiredCitizenCode,
+
+    #[error("Email address does not match the citizen")]
+    MailMismatch,
+
+    #[error("A new code was sent recently, try again later")]
+    ResendThrottled,
+
     #[error("Connection issue")]
     Connection(#[from] actix_web::error::BlockingError),
 
@@ -130,21 +194,81 @@ pub enum UserRegistrationError {
 
     #[error("Unable to authenticate")]
     Auth(#[from] SessionRetrievalError),
+
+    #[error("Invitation is invalid or expired")]
+    InvalidInvitation,
+
+    #[error("An email address is required")]
+    MissingEmail,
+
+    #[error("Either a citizen id or an email address is required")]
+    MissingIdentifier,
+
+    #[error("Password reset link is invalid or expired")]
+    InvalidResetToken,
+
+    #[error("Second factor could not be verified")]
+    InvalidSecondFactor,
+
+    #[error("Unable to send mail")]
+    Mail(#[from] MailSenderError),
+
+    #[error("Unable to create letter")]
+    Letter(#[from] LetterError),
+
+    #[error("Redirect target is not allowed")]
+    Redirect(#[from] RedirectError),
+}
+
+impl From<diesel::result::Error> for UserRegistrationError {
+    fn from(err: diesel::result::Error) -> Self {
+        Self::Db(err.into())
+    }
 }
 
 impl ResponseError for UserRegistrationError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code())
-            .json(json!({"type": "user_registration", "error": &self.to_string()}))
+        Problem::new(self.status_code(), self.code(), &self.to_string()).response()
     }
     fn status_code(&self) -> StatusCode {
         match &self {
             Self::Db(e) => e.status_code(),
             Self::InvalidCitizenCode => StatusCode::FORBIDDEN,
+            Self::ExpiredCitizenCode => StatusCode::FORBIDDEN,
+            Self::MailMismatch => StatusCode::FORBIDDEN,
+            Self::ResendThrottled => StatusCode::TOO_MANY_REQUESTS,
+            Self::InvalidInvitation => StatusCode::FORBIDDEN,
+            Self::InvalidSecondFactor => StatusCode::FORBIDDEN,
+            Self::MissingEmail => StatusCode::BAD_REQUEST,
+            Self::MissingIdentifier => StatusCode::BAD_REQUEST,
+            Self::InvalidResetToken => StatusCode::FORBIDDEN,
+            Self::Auth(e) => e.status_code(),
+            Self::Redirect(e) => e.status_code(),
             _ => StatusCode::INTERNAL_SERVER_ERROR
         }
     }
 }
+impl ProblemCode for UserRegistrationError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Db(e) => e.code(),
+            Self::InvalidCitizenCode => "invalid_registration_code",
+            Self::ExpiredCitizenCode => "expired_registration_code",
+            Self::MailMismatch => "mail_mismatch",
+            Self::ResendThrottled => "resend_throttled",
+            Self::InvalidInvitation => "invalid_invitation",
+            Self::InvalidSecondFactor => "invalid_second_factor",
+            Self::MissingEmail => "missing_email",
+            Self::MissingIdentifier => "missing_identifier",
+            Self::InvalidResetToken => "invalid_reset_token",
+            Self::Auth(e) => e.code(),
+            Self::Mail(_) => "mail_error",
+            Self::Letter(_) => "letter_error",
+            Self::Redirect(e) => e.code(),
+            Self::UserCreation(_) | Self::Connection(_) | Self::DataRetrieval => "internal_error"
+        }
+    }
+}
 
 pub type AuthenticationResult<T> = Result<T, AuthenticationError>;
 
@@ -160,7 +284,44 @@ pub enum AuthenticationError {
     Verification(#[from] CredentialsVerificationError),
 
     #[error("The provided password is wrong")]
-    WrongPassword
+    WrongPassword,
+
+    #[error("A second factor is required")]
+    MissingSecondFactor,
+
+    #[error("The provided second factor is wrong")]
+    WrongSecondFactor,
+
+    #[error("The account is disabled")]
+    Disabled,
+
+    #[error("The account is locked")]
+    Locked
+}
+
+impl AuthenticationError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Db(e) => e.status_code(),
+            Self::Verification(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::UserNotFound | Self::WrongPassword | Self::MissingSecondFactor | Self::WrongSecondFactor => StatusCode::UNAUTHORIZED,
+            Self::Disabled | Self::Locked => StatusCode::FORBIDDEN
+        }
+    }
+}
+impl ProblemCode for AuthenticationError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Db(e) => e.code(),
+            Self::Verification(_) => "internal_error",
+            // Unknown users and wrong passwords look the same, so usernames can not be guessed
+            Self::UserNotFound | Self::WrongPassword => "invalid_credentials",
+            Self::MissingSecondFactor => "second_factor_required",
+            Self::WrongSecondFactor => "invalid_second_factor",
+            Self::Disabled => "account_disabled",
+            Self::Locked => "account_locked"
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -187,22 +348,62 @@ pub enum SessionRetrievalError {
     #[error("Session is invalid")]
     InvalidSession,
 
+    #[error("No session token was sent")]
+    MissingToken,
+
     #[error("Connection issue")]
     Connection(#[from] actix_web::error::BlockingError),
 
     #[error("Unable to retrieve citizen info")]
-    Info(#[from] CitizenInfoRetrievalError)
+    Info(#[from] CitizenInfoRetrievalError),
+
+    #[error("The account is disabled")]
+    Disabled,
+
+    #[error("The account is locked")]
+    Locked,
+
+    #[error("Missing permission for this action")]
+    MissingPermission
+}
+/// Unknown or revoked session tokens are not found in the database
+impl From<diesel::result::Error> for SessionRetrievalError {
+    fn from(err: diesel::result::Error) -> Self {
+        match err {
+            Error::NotFound => Self::InvalidSession,
+            e => Self::Db(e.into())
+        }
+    }
 }
+
 impl ResponseError for SessionRetrievalError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code())
-            .json(json!({"type": "session_retrieval", "error": &self.to_string()}))
+        Problem::new(self.status_code(), self.code(), &self.to_string()).response()
     }
     fn status_code(&self) -> StatusCode {
         match &self {
             Self::Db(e) => e.status_code(),
-            Self::InvalidSession => StatusCode::FORBIDDEN,
-            _ => StatusCode::INTERNAL_SERVER_ERROR
+            Self::InvalidSession => StatusCode::UNAUTHORIZED,
+            Self::MissingToken => StatusCode::UNAUTHORIZED,
+            Self::Disabled => StatusCode::FORBIDDEN,
+            Self::Locked => StatusCode::FORBIDDEN,
+            Self::MissingPermission => StatusCode::FORBIDDEN,
+            Self::Info(e) => e.status_code(),
+            Self::Connection(_) => StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+impl ProblemCode for SessionRetrievalError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Db(e) => e.code(),
+            Self::InvalidSession => "invalid_session",
+            Self::MissingToken => "missing_token",
+            Self::Disabled => "account_disabled",
+            Self::Locked => "account_locked",
+            Self::MissingPermission => "missing_permission",
+            Self::Info(e) => e.code(),
+            Self::Connection(_) => "internal_error"
         }
     }
 }
@@ -228,25 +429,317 @@ pub enum LoginError {
     Info(#[from] CitizenInfoRetrievalError),
 
     #[error("Unable to retrieve citizen info")]
-    SessionRetrieval(#[from] SessionRetrievalError)
+    SessionRetrieval(#[from] SessionRetrievalError),
+
+    #[error("Redirect target is not allowed")]
+    Redirect(#[from] RedirectError)
 }
 
 impl ResponseError for LoginError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code())
-            .json(json!({"type": "login", "error": &self.to_string()}))
+        Problem::new(self.status_code(), self.code(), &self.to_string()).response()
     }
     fn status_code(&self) -> StatusCode {
         match &self {
             LoginError::Db(e) => e.status_code(),
             LoginError::Connection(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            LoginError::Authentication(_) => StatusCode::FORBIDDEN,
-            LoginError::SessionInsertion(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            LoginError::Authentication(e) => e.status_code(),
+            LoginError::SessionInsertion(SessionInsertionError::Db(e)) => e.status_code(),
+            LoginError::Info(e) => e.status_code(),
+            LoginError::SessionRetrieval(e) => e.status_code(),
+            LoginError::Redirect(e) => e.status_code(),
             _ => StatusCode::INTERNAL_SERVER_ERROR
         }
     }
 }
+impl ProblemCode for LoginError {
+    fn code(&self) -> &'static str {
+        match self {
+            LoginError::Db(e) => e.code(),
+            LoginError::Authentication(e) => e.code(),
+            LoginError::SessionInsertion(SessionInsertionError::Db(e)) => e.code(),
+            LoginError::Info(e) => e.code(),
+            LoginError::SessionRetrieval(e) => e.code(),
+            LoginError::Redirect(e) => e.code(),
+            _ => "internal_error"
+        }
+    }
+}
+pub type EmployeeAdministrationResult<T> = Result<T, EmployeeAdministrationError>;
+#[derive(Error, Debug)]
+pub enum EmployeeAdministrationError {
+    #[error("Database issue")]
+    Db(#[from] DatabaseError),
+
+    #[error("Connection issue")]
+    Connection(#[from] actix_web::error::BlockingError),
+
+    #[error("Unable to authenticate")]
+    Auth(#[from] SessionRetrievalError),
+
+    #[error("Employee could not be found")]
+    EmployeeNotFound,
+
+    #[error("Employees can not disable or delete their own account")]
+    OwnAccount,
+
+    #[error("Unable to verify the audit trail")]
+    AuditChain(#[from] AuditChainError),
+
+    #[error("Mail could not be found")]
+    MailNotFound,
+
+    #[error("Only failed mails can be sent again")]
+    MailNotFailed,
+
+    #[error("The mail expired, a new code or link has to be requested")]
+    MailExpired,
+
+    #[error("Too many events to export, the filters have to be narrowed")]
+    ExportTooLarge,
+}
+
+impl From<diesel::result::Error> for EmployeeAdministrationError {
+    fn from(err: diesel::result::Error) -> Self {
+        match err {
+            Error::NotFound => Self::EmployeeNotFound,
+            e => Self::Db(e.into())
+        }
+    }
+}
+
+impl ResponseError for EmployeeAdministrationError {
+    fn error_response(&self) -> HttpResponse {
+        Problem::new(self.status_code(), self.code(), &self.to_string()).response()
+    }
+    fn status_code(&self) -> StatusCode {
+        match &self {
+            Self::Db(e) => e.status_code(),
+            Self::Auth(e) => e.status_code(),
+            Self::EmployeeNotFound => StatusCode::NOT_FOUND,
+            Self::OwnAccount => StatusCode::BAD_REQUEST,
+            Self::MailNotFound => StatusCode::NOT_FOUND,
+            Self::MailNotFailed => StatusCode::CONFLICT,
+            Self::MailExpired => StatusCode::CONFLICT,
+            Self::ExportTooLarge => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+impl ProblemCode for EmployeeAdministrationError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Db(e) => e.code(),
+            Self::Auth(e) => e.code(),
+            Self::EmployeeNotFound => "employee_not_found",
+            Self::OwnAccount => "own_account",
+            Self::AuditChain(_) => "audit_chain_error",
+            Self::MailNotFound => "mail_not_found",
+            Self::MailNotFailed => "mail_not_failed",
+            Self::MailExpired => "mail_expired",
+            Self::ExportTooLarge => "export_too_large",
+            Self::Connection(_) => "internal_error"
+        }
+    }
+}
+
+pub type CitizenAdministrationResult<T> = Result<T, CitizenAdministrationError>;
+#[derive(Error, Debug)]
+pub enum CitizenAdministrationError {
+    #[error("Database issue")]
+    Db(#[from] DatabaseError),
+
+    #[error("Connection issue")]
+    Connection(#[from] actix_web::error::BlockingError),
+
+    #[error("Unable to authenticate")]
+    Auth(#[from] SessionRetrievalError),
+
+    #[error("Citizen account could not be found")]
+    CitizenNotFound,
+
+    #[error("Citizen is already registered")]
+    AlreadyRegistered,
+
+    #[error("Either a citizen id or a username is required")]
+    MissingIdentifier,
+
+    #[error("No email address is known for the citizen")]
+    MissingEmail,
+
+    #[error("Unable to retrieve citizen info")]
+    Info(#[from] CitizenInfoRetrievalError),
+
+    #[error("Unable to send mail")]
+    Mail(#[from] MailSenderError),
+
+    #[error("Unable to create letter")]
+    Letter(#[from] LetterError),
+
+    #[error("Letter could not be found")]
+    LetterNotFound,
+}
+
+impl From<diesel::result::Error> for CitizenAdministrationError {
+    fn from(err: diesel::result::Error) -> Self {
+        match err {
+            Error::NotFound => Self::CitizenNotFound,
+            e => Self::Db(e.into())
+        }
+    }
+}
+
+impl ResponseError for CitizenAdministrationError {
+    fn error_response(&self) -> HttpResponse {
+        Problem::new(self.status_code(), self.code(), &self.to_string()).response()
+    }
+    fn status_code(&self) -> StatusCode {
+        match &self {
+            Self::Db(e) => e.status_code(),
+            Self::Auth(e) => e.status_code(),
+            Self::CitizenNotFound => StatusCode::NOT_FOUND,
+            Self::AlreadyRegistered => StatusCode::CONFLICT,
+            Self::MissingIdentifier => StatusCode::BAD_REQUEST,
+            Self::MissingEmail => StatusCode::CONFLICT,
+            Self::Letter(LetterError::MissingAddress) => StatusCode::CONFLICT,
+            Self::LetterNotFound => StatusCode::NOT_FOUND,
+            Self::Info(e) => e.status_code(),
+            _ => StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+impl ProblemCode for CitizenAdministrationError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Db(e) => e.code(),
+            Self::Auth(e) => e.code(),
+            Self::CitizenNotFound => "citizen_not_found",
+            Self::AlreadyRegistered => "already_registered",
+            Self::MissingIdentifier => "missing_identifier",
+            Self::MissingEmail => "citizen_without_email",
+            Self::Info(e) => e.code(),
+            Self::Mail(_) => "mail_error",
+            Self::Letter(LetterError::MissingAddress) => "missing_address",
+            Self::Letter(_) => "letter_error",
+            Self::LetterNotFound => "letter_not_found",
+            Self::Connection(_) => "internal_error"
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum MailSenderError {
+    #[error("Unable to build mail")]
+    Build(#[from] lettre_email::error::Error),
+
+    #[error("Unable to send mail")]
+    Send(#[from] lettre::smtp::error::Error),
+
+    #[error("Unable to write mail")]
+    Io(#[from] std::io::Error),
+
+    #[error("The recipient has no email address")]
+    MissingRecipient,
+
+    #[error("Mail template {0} does not exist")]
+    MissingTemplate(String),
+
+    #[error("Mail template {0} is malformed")]
+    InvalidTemplate(String),
+
+    #[error("Mail template {0} uses the unknown variable {1}")]
+    UnknownVariable(String, String),
+}
+
+#[derive(Error, Debug)]
+pub enum LetterError {
+    #[error("No postal address is known for the citizen")]
+    MissingAddress,
+
+    #[error("Unable to render letter")]
+    Pdf(#[from] printpdf::Error),
+
+    #[error("Unable to create QR code")]
+    QrCode(#[from] qrcode::types::QrError),
+}
 
-}
\ No newline at end of file
+#[derive(Error, Debug)]
+pub enum RedirectError {
+    #[error("Redirect target is not a valid URL")]
+    Invalid,
+
+    #[error("Redirect target is not on an allowed origin")]
+    NotAllowed,
+}
+
+impl RedirectError {
+    pub fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+impl ProblemCode for RedirectError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Invalid => "invalid_redirect",
+            Self::NotAllowed => "redirect_not_allowed"
+        }
+    }
+}
+
+pub type AuditChainResult<T> = Result<T, AuditChainError>;
+#[derive(Error, Debug)]
+pub enum AuditChainError {
+    #[error("Database issue")]
+    Db(#[from] DatabaseError),
+
+    #[error("Unable to access the checkpoint file")]
+    Io(#[from] std::io::Error),
+
+    #[error("Checkpoint in line {0} is malformed or its signature is invalid")]
+    InvalidCheckpoint(usize),
+
+    #[error("The audit trail is broken at event {0}")]
+    Broken(u64),
+}
+
+impl From<diesel::result::Error> for AuditChainError {
+    fn from(err: diesel::result::Error) -> Self {
+        Self::Db(err.into())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum EventHandlingError {
+    /// The event can never be handled, e.g. because it is malformed or refers to an unknown citizen
+    #[error("Event can not be handled: {0}")]
+    Permanent(String),
+
+    /// Handling might succeed when the event is delivered again later
+    #[error("Event handling failed: {0:#}")]
+    Transient(#[from] anyhow::Error),
+}
+
+impl From<DatabaseError> for EventHandlingError {
+    fn from(err: DatabaseError) -> Self {
+        Self::Transient(err.into())
+    }
+}
+
+impl From<CitizenAdministrationError> for EventHandlingError {
+    fn from(err: CitizenAdministrationError) -> Self {
+        match err {
+            CitizenAdministrationError::Db(e) => e.into(),
+            CitizenAdministrationError::Info(e) => e.into(),
+            e => Self::Permanent(e.to_string())
+        }
+    }
+}
+
+impl From<CitizenInfoRetrievalError> for EventHandlingError {
+    fn from(err: CitizenInfoRetrievalError) -> Self {
+        match err.is_transient() {
+            true => Self::Transient(err.into()),
+            false => Self::Permanent(err.to_string())
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use printpdf::{BuiltinFont, IndirectFontRef, Line, Mm, PdfDocument, PdfDocumentReference, PdfLayerReference, Point};
+use qrcode::QrCode;
+use crate::auth::Citizen::CitizenInfo;
+use crate::auth::Errors::LetterError;
+use crate::auth::Session::Token;
+
+const PAGE_WIDTH: f64 = 210.0;
+const PAGE_HEIGHT: f64 = 297.0;
+const MARGIN_LEFT: f64 = 25.0;
+const QR_SIZE: f64 = 40.0;
+
+/// Renders registration codes as A4 letters (DIN 5008, window envelope) for citizens without email address
+#[derive(Clone, Debug)]
+pub struct LetterRenderer {
+    /// Return address, one line each
+    sender: Vec<String>,
+    portal_url: String,
+    /// TrueType font, the builtin Helvetica can not print umlauts
+    font: Option<Vec<u8>>,
+}
+
+impl LetterRenderer {
+    pub fn new(sender: Vec<String>, portal_url: &str) -> Self {
+        LetterRenderer {
+            sender,
+            portal_url: portal_url.to_string(),
+            font: None,
+        }
+    }
+
+    pub fn font(mut self, font: Vec<u8>) -> Self {
+        self.font = Some(font);
+        self
+    }
+
+    /// Link in the QR code of the letter
+    pub fn registration_link(&self, code: &Token) -> String {
+        format!("{}?code={}", self.portal_url, code)
+    }
+
+    /// QR code with the [registration link](Self::registration_link)
+    pub fn qr_code(&self, code: &Token) -> Result<QrCode, LetterError> {
+        Ok(QrCode::new(self.registration_link(code).as_bytes())?)
+    }
+
+    pub fn render(&self, citizen: &CitizenInfo, code: &Token) -> Result<Vec<u8>, LetterError> {
+        let recipient = recipient_lines(citizen)?;
+        let (doc, page, layer) = PdfDocument::new("Registrierungscode", Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Brief");
+        let layer = doc.get_page(page).get_layer(layer);
+        let font = self.load_font(&doc)?;
+        let text = |line: &str, size: f64, top: f64| layer.use_text(line, size, Mm(MARGIN_LEFT), Mm(PAGE_HEIGHT - top), &font);
+
+        text(&self.sender.join(" · "), 7.0, 50.0);
+        for (i, line) in recipient.iter().enumerate() {
+            text(line, 11.0, 60.0 + 5.0 * i as f64);
+        }
+
+        text("Ihr Registrierungscode für das Bürgerportal", 13.0, 110.0);
+        text(&format!("Guten Tag {} {},", citizen.firstname, citizen.lastname), 11.0, 125.0);
+        text("mit dem folgenden Code können Sie sich im Bürgerportal registrieren:", 11.0, 135.0);
+        text(code, 18.0, 150.0);
+        text(&format!("Registrieren Sie sich unter {}", self.portal_url), 11.0, 165.0);
+        text("oder scannen Sie den QR-Code mit Ihrem Smartphone.", 11.0, 171.0);
+        draw_qr_code(&layer, &self.qr_code(code)?, MARGIN_LEFT, PAGE_HEIGHT - 180.0 - QR_SIZE);
+
+        Ok(doc.save_to_bytes()?)
+    }
+
+    fn load_font(&self, doc: &PdfDocumentReference) -> Result<IndirectFontRef, LetterError> {
+        Ok(match &self.font {
+            Some(font) => doc.add_external_font(font.as_slice())?,
+            None => doc.add_builtin_font(BuiltinFont::Helvetica)?
+        })
+    }
+}
+
+fn recipient_lines(citizen: &CitizenInfo) -> Result<Vec<String>, LetterError> {
+    let address = &citizen.address;
+    let street = address.street.as_deref().ok_or(LetterError::MissingAddress)?;
+    let city = address.city.as_deref().ok_or(LetterError::MissingAddress)?;
+
+    let street_line = match &address.housenumber {
+        Some(number) => format!("{} {}", street, number),
+        None => street.to_string()
+    };
+    let city_line = match address.city_code {
+        Some(city_code) => format!("{:05} {}", city_code, city),
+        None => city.to_string()
+    };
+    Ok(vec![format!("{} {}", citizen.firstname, citizen.lastname), street_line, city_line])
+}
+
+/// Draws the QR code as square of [QR_SIZE] with its lower left corner at `x`, `y`
+fn draw_qr_code(layer: &PdfLayerReference, code: &QrCode, x: f64, y: f64) {
+    let width = code.width();
+    let module = QR_SIZE / width as f64;
+
+    for row in 0..width {
+        for column in 0..width {
+            if code[(column, row)] != qrcode::Color::Dark {
+                continue;
+            }
+            let left = x + column as f64 * module;
+            let top = y + QR_SIZE - row as f64 * module;
+            layer.add_shape(Line {
+                points: vec![
+                    (Point::new(Mm(left), Mm(top)), false),
+                    (Point::new(Mm(left + module), Mm(top)), false),
+                    (Point::new(Mm(left + module), Mm(top - module)), false),
+                    (Point::new(Mm(left), Mm(top - module)), false),
+                ],
+                is_closed: true,
+                has_fill: true,
+                has_stroke: false,
+                is_clipping_path: false,
+            });
+        }
+    }
+}
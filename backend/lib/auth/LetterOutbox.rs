@@ -0,0 +1,83 @@
+use diesel::{insert_into, ExpressionMethods, MysqlConnection, OptionalExtension, QueryDsl, Queryable, RunQueryDsl};
+use moon::{NaiveDateTime, Utc};
+use serde::Serialize;
+use schemars::JsonSchema;
+use crate::auth::Errors::DatabaseError;
+use crate::schema::RegistrationLetters;
+
+/// A letter waiting to be printed, without the PDF itself
+#[derive(Queryable, Serialize, Clone, Debug, JsonSchema)]
+pub struct QueuedLetter {
+    pub id: u64,
+    pub citizen_id: u64,
+    pub created: NaiveDateTime,
+    /// When the letter was downloaded by the print office
+    pub downloaded: Option<NaiveDateTime>,
+}
+
+/// Stores the letter for the print office. Letters of the citizen that were not downloaded yet contain an
+/// outdated code and are removed
+pub fn queue_letter(db: &MysqlConnection, letter_citizen: u64, letter: &[u8]) -> Result<(), DatabaseError> {
+    use crate::schema::RegistrationLetters::{citizen_id, created, downloaded, pdf};
+
+    diesel::delete(RegistrationLetters::table
+        .filter(citizen_id.eq(letter_citizen))
+        .filter(downloaded.is_null()))
+        .execute(db)?;
+    insert_into(RegistrationLetters::table)
+        .values((citizen_id.eq(letter_citizen),
+                 created.eq(Utc::now().naive_utc()),
+                 pdf.eq(letter)))
+        .execute(db)?;
+    Ok(())
+}
+
+/// Whether a registration letter was ever created for the citizen
+pub fn has_letter(db: &MysqlConnection, letter_citizen: u64) -> Result<bool, DatabaseError> {
+    use crate::schema::RegistrationLetters::{citizen_id, id};
+
+    Ok(diesel::dsl::select(diesel::dsl::exists(RegistrationLetters::table
+        .filter(citizen_id.eq(letter_citizen))
+        .select(id)))
+        .get_result(db)?)
+}
+
+/// Oldest letters first, by default only those that were not downloaded yet. Returns the total number of matching letters as well
+pub fn list_letters(db: &MysqlConnection, pending_only: bool, offset: i64, limit: i64) -> Result<(i64, Vec<QueuedLetter>), DatabaseError> {
+    use crate::schema::RegistrationLetters::{citizen_id, created, downloaded, id};
+
+    let query = || {
+        let mut query: RegistrationLetters::BoxedQuery<'static, diesel::mysql::Mysql> = RegistrationLetters::table.into_boxed();
+        if pending_only {
+            query = query.filter(downloaded.is_null());
+        }
+        query
+    };
+
+    let total = query().count().get_result(db)?;
+    let letters = query()
+        .select((id, citizen_id, created, downloaded))
+        .order(id.asc())
+        .offset(offset)
+        .limit(limit)
+        .load(db)?;
+    Ok((total, letters))
+}
+
+/// The PDF of the letter, marks it as downloaded
+pub fn download_letter(db: &MysqlConnection, letter_id: u64) -> Result<Option<(QueuedLetter, Vec<u8>)>, DatabaseError> {
+    use crate::schema::RegistrationLetters::{citizen_id, created, downloaded, id, pdf};
+
+    let letter: Option<(QueuedLetter, Vec<u8>)> = RegistrationLetters::table
+        .find(letter_id)
+        .select(((id, citizen_id, created, downloaded), pdf))
+        .first(db)
+        .optional()?;
+
+    if letter.as_ref().map_or(false, |(l, _)| l.downloaded.is_none()) {
+        diesel::update(RegistrationLetters::table.find(letter_id))
+            .set(downloaded.eq(Some(Utc::now().naive_utc())))
+            .execute(db)?;
+    }
+    Ok(letter)
+}
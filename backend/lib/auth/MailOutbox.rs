@@ -0,0 +1,243 @@
+use std::str::FromStr;
+use diesel::{insert_into, AsChangeset, Connection, ExpressionMethods, Insertable, MysqlConnection, OptionalExtension, QueryDsl, Queryable, RunQueryDsl};
+use moon::{chrono, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use crate::auth::Errors::DatabaseError;
+use crate::auth::Mailer::{Mail, Mailer};
+use crate::schema::MailOutbox;
+use crate::server::MailQueueConfig;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MailStatus {
+    /// Waiting for the first or another attempt
+    Pending,
+    Sent,
+    /// Sending failed too often, the mail is only sent again on request
+    Failed,
+}
+
+impl MailStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MailStatus::Pending => "pending",
+            MailStatus::Sent => "sent",
+            MailStatus::Failed => "failed",
+        }
+    }
+}
+
+impl FromStr for MailStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(MailStatus::Pending),
+            "sent" => Ok(MailStatus::Sent),
+            "failed" => Ok(MailStatus::Failed),
+            _ => Err(())
+        }
+    }
+}
+
+#[derive(Queryable, Clone, Debug)]
+pub struct QueuedMail {
+    pub id: u64,
+    pub created: NaiveDateTime,
+    pub recipient: String,
+    pub subject: String,
+    /// The serialized [Mail], removed once it was sent
+    pub mail: Option<String>,
+    pub status: String,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub next_attempt: NaiveDateTime,
+    pub sent: Option<NaiveDateTime>,
+    /// The code or link in the mail is invalid afterwards, the mail is no longer sent
+    pub expires: NaiveDateTime,
+}
+
+impl QueuedMail {
+    pub fn status(&self) -> Option<MailStatus> {
+        self.status.parse().ok()
+    }
+
+    pub fn mail(&self) -> Option<Mail> {
+        serde_json::from_str(self.mail.as_deref()?).ok()
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires <= Utc::now().naive_utc()
+    }
+
+    /// Sends the mail and returns the new state of the queue entry
+    pub fn attempt(&self, mailer: &dyn Mailer, config: &MailQueueConfig) -> MailUpdate {
+        let now = Utc::now().naive_utc();
+        let failed = |error: String, retry_in: Option<chrono::Duration>| MailUpdate {
+            status: match retry_in {
+                Some(_) => MailStatus::Pending,
+                None => MailStatus::Failed
+            }.as_str().to_string(),
+            attempts: self.attempts + 1,
+            last_error: Some(error),
+            next_attempt: now + retry_in.unwrap_or_else(chrono::Duration::zero),
+            sent: None,
+            mail: self.mail.clone(),
+        };
+
+        if self.is_expired() {
+            return MailUpdate {
+                attempts: self.attempts,
+                mail: None,
+                ..failed(String::from("The mail expired before it could be sent"), None)
+            };
+        }
+        let mail = match self.mail() {
+            Some(mail) => mail,
+            None => return failed(String::from("The stored mail is unreadable"), None)
+        };
+        match mailer.send(&mail) {
+            Ok(()) => MailUpdate {
+                status: MailStatus::Sent.as_str().to_string(),
+                attempts: self.attempts + 1,
+                last_error: None,
+                next_attempt: self.next_attempt,
+                sent: Some(now),
+                mail: None,
+            },
+            Err(e) => failed(e.to_string(), config.retry_delay(self.attempts + 1))
+        }
+    }
+}
+
+/// State of a queued mail after an attempt to send it
+#[derive(AsChangeset, Clone, Debug)]
+#[table_name="MailOutbox"]
+#[changeset_options(treat_none_as_null="true")]
+pub struct MailUpdate {
+    pub status: String,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub next_attempt: NaiveDateTime,
+    pub sent: Option<NaiveDateTime>,
+    pub mail: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name="MailOutbox"]
+struct NewQueuedMail {
+    created: NaiveDateTime,
+    recipient: String,
+    subject: String,
+    mail: Option<String>,
+    next_attempt: NaiveDateTime,
+    expires: NaiveDateTime,
+}
+
+/// Stores the mail, it is sent by the mail queue of the server until it expires
+pub fn queue_mail(db: &MysqlConnection, mail: &Mail, expires: NaiveDateTime) -> Result<(), DatabaseError> {
+    let now = Utc::now().naive_utc();
+    insert_into(MailOutbox::table)
+        .values(&NewQueuedMail {
+            created: now,
+            recipient: mail.to.clone(),
+            subject: mail.subject.clone(),
+            mail: Some(serde_json::to_string(mail).expect("Mails are always serializable")),
+            next_attempt: now,
+            expires,
+        })
+        .execute(db)?;
+    Ok(())
+}
+
+/// Oldest pending mails that are due to be sent. They are not due again before the `lease` ran out,
+/// so other instances of the server skip them while they are being sent
+pub fn claim_due_mails(db: &MysqlConnection, limit: i64, lease: chrono::Duration) -> Result<Vec<QueuedMail>, DatabaseError> {
+    use crate::schema::MailOutbox::{id, next_attempt, status};
+
+    db.transaction::<_, DatabaseError, _>(|| {
+        let now = Utc::now().naive_utc();
+        let mails: Vec<QueuedMail> = MailOutbox::table
+            .filter(status.eq(MailStatus::Pending.as_str()))
+            .filter(next_attempt.le(now))
+            .order(id.asc())
+            .limit(limit)
+            .for_update()
+            .skip_locked()
+            .load(db)?;
+
+        let ids: Vec<u64> = mails.iter().map(|m| m.id).collect();
+        diesel::update(MailOutbox::table.filter(id.eq_any(&ids)))
+            .set(next_attempt.eq(now + lease))
+            .execute(db)?;
+        Ok(mails)
+    })
+}
+
+pub fn record_attempt(db: &MysqlConnection, mail_id: u64, update: &MailUpdate) -> Result<(), DatabaseError> {
+    diesel::update(MailOutbox::table.find(mail_id))
+        .set(update)
+        .execute(db)?;
+    Ok(())
+}
+
+/// Removes the content of expired mails, pending ones are marked as failed. Returns the number of purged mails
+pub fn purge_expired_mails(db: &MysqlConnection) -> Result<usize, DatabaseError> {
+    use crate::schema::MailOutbox::{expires, last_error, mail, status};
+
+    let now = Utc::now().naive_utc();
+    db.transaction::<_, DatabaseError, _>(|| {
+        diesel::update(MailOutbox::table
+            .filter(status.eq(MailStatus::Pending.as_str()))
+            .filter(expires.le(now)))
+            .set((status.eq(MailStatus::Failed.as_str()),
+                  last_error.eq(Some("The mail expired before it could be sent"))))
+            .execute(db)?;
+        Ok(diesel::update(MailOutbox::table
+            .filter(mail.is_not_null())
+            .filter(expires.le(now)))
+            .set(mail.eq(None::<String>))
+            .execute(db)?)
+    })
+}
+
+pub fn find_mail(db: &MysqlConnection, mail_id: u64) -> Result<Option<QueuedMail>, DatabaseError> {
+    Ok(MailOutbox::table
+        .find(mail_id)
+        .first(db)
+        .optional()?)
+}
+
+/// Newest mails first, optionally only those with the given status. Returns the total number of matching mails as well
+pub fn list_mails(db: &MysqlConnection, mail_status: Option<MailStatus>, offset: i64, limit: i64) -> Result<(i64, Vec<QueuedMail>), DatabaseError> {
+    use crate::schema::MailOutbox::{id, status};
+
+    let query = || {
+        let mut query: MailOutbox::BoxedQuery<'static, diesel::mysql::Mysql> = MailOutbox::table.into_boxed();
+        if let Some(s) = mail_status {
+            query = query.filter(status.eq(s.as_str()));
+        }
+        query
+    };
+
+    let total = query().count().get_result(db)?;
+    let mails = query()
+        .order(id.desc())
+        .offset(offset)
+        .limit(limit)
+        .load(db)?;
+    Ok((total, mails))
+}
+
+/// Puts a mail back into the queue, starting over with the attempts
+pub fn requeue_mail(db: &MysqlConnection, mail_id: u64) -> Result<(), DatabaseError> {
+    use crate::schema::MailOutbox::{attempts, next_attempt, status};
+
+    diesel::update(MailOutbox::table.find(mail_id))
+        .set((status.eq(MailStatus::Pending.as_str()),
+              attempts.eq(0u32),
+              next_attempt.eq(Utc::now().naive_utc())))
+        .execute(db)?;
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use log::debug;
+use crate::auth::Errors::MailSenderError;
+use crate::auth::Mailer::Mail;
+
+/// Templates every locale has to provide
+pub const REQUIRED_TEMPLATES: [&str; 3] = ["citizen_code", "employee_invitation", "password_reset"];
+
+#[derive(Clone, Debug)]
+struct MailTemplate {
+    subject: String,
+    text: String,
+    html: Option<String>,
+}
+
+/// Mail templates for all locales, loaded from `<dir>/<locale>/<name>.subject.txt`, `<name>.txt` and optionally `<name>.html`.
+/// `{{variable}}` in a template is replaced with the value of the variable, values are escaped in HTML templates
+#[derive(Clone, Debug)]
+pub struct MailTemplates {
+    templates: HashMap<(String, String), MailTemplate>,
+    default_locale: String,
+    sender: String,
+    reply_to: Option<String>,
+    /// Variables available in every template
+    globals: Vec<(String, String)>,
+}
+
+impl MailTemplates {
+    pub fn new(default_locale: &str, sender: &str, reply_to: Option<&str>) -> Self {
+        MailTemplates {
+            templates: HashMap::new(),
+            default_locale: default_locale.to_string(),
+            sender: sender.to_string(),
+            reply_to: reply_to.map(String::from),
+            globals: Vec::new(),
+        }
+    }
+
+    pub fn global(mut self, name: &str, value: &str) -> Self {
+        self.globals.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn template(mut self, locale: &str, name: &str, subject: &str, text: &str, html: Option<&str>) -> Self {
+        self.templates.insert((locale.to_string(), name.to_string()), MailTemplate {
+            subject: subject.trim().to_string(),
+            text: text.to_string(),
+            html: html.map(String::from),
+        });
+        self
+    }
+
+    /// Loads the templates of every locale directory, fails if the default locale lacks one of the [REQUIRED_TEMPLATES]
+    pub fn load(mut self, dir: &Path) -> Result<Self> {
+        let locales = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read mail templates from {}", dir.display()))?;
+
+        for locale in locales {
+            let locale = locale?;
+            if !locale.file_type()?.is_dir() {
+                continue;
+            }
+            let locale_name = locale.file_name().to_string_lossy().to_string();
+
+            for entry in std::fs::read_dir(locale.path())? {
+                let path = entry?.path();
+                let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+                let name = match file_name.strip_suffix(".subject.txt") {
+                    Some(n) => n,
+                    None => continue
+                };
+
+                let read = |extension: &str| std::fs::read_to_string(locale.path().join(format!("{}.{}", name, extension)));
+                let subject = read("subject.txt")?;
+                let text = read("txt")
+                    .with_context(|| format!("Mail template {}/{} has no text variant", locale_name, name))?;
+                let html = read("html").ok();
+                debug!("Loaded mail template {}/{}", locale_name, name);
+                self = self.template(&locale_name, name, &subject, &text, html.as_deref());
+            }
+        }
+
+        for name in REQUIRED_TEMPLATES {
+            if !self.templates.contains_key(&(self.default_locale.clone(), name.to_string())) {
+                bail!("Mail template {} is missing for the default locale {}", name, self.default_locale);
+            }
+        }
+        Ok(self)
+    }
+
+    /// The template in the requested locale, its language without region (`en` for `en-GB`) or the default locale
+    fn find(&self, name: &str, locale: Option<&str>) -> Result<&MailTemplate, MailSenderError> {
+        let requested = locale.into_iter().flat_map(|l| [l, l.split(|c: char| c == '-' || c == '_').next().unwrap_or(l)]);
+        requested
+            .chain([self.default_locale.as_str()])
+            .find_map(|l| self.templates.get(&(l.to_string(), name.to_string())))
+            .ok_or_else(|| MailSenderError::MissingTemplate(name.to_string()))
+    }
+
+    pub fn render(&self, name: &str, locale: Option<&str>, to: &str, variables: &[(&str, &str)]) -> Result<Mail, MailSenderError> {
+        let template = self.find(name, locale)?;
+        let lookup = |variable: &str| variables
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .chain(self.globals.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+            .find(|(k, _)| *k == variable)
+            .map(|(_, v)| v.to_string());
+
+        Ok(Mail {
+            from: self.sender.clone(),
+            reply_to: self.reply_to.clone(),
+            to: to.to_string(),
+            subject: substitute(name, &template.subject, &lookup)?,
+            text: substitute(name, &template.text, &lookup)?,
+            html: template.html
+                .as_deref()
+                .map(|html| substitute(name, html, &|v: &str| lookup(v).map(|value| escape_html(&value))))
+                .transpose()?,
+        })
+    }
+}
+
+fn substitute(name: &str, template: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String, MailSenderError> {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..]
+            .find("}}")
+            .ok_or_else(|| MailSenderError::InvalidTemplate(name.to_string()))?;
+        let variable = rest[start + 2..start + end].trim();
+        let value = lookup(variable)
+            .ok_or_else(|| MailSenderError::UnknownVariable(name.to_string(), variable.to_string()))?;
+
+        result.push_str(&rest[..start]);
+        result.push_str(&value);
+        rest = &rest[start + end + 2..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
@@ -0,0 +1,145 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use lettre::{SendableEmail, SmtpClient, Transport};
+use lettre_email::{Email, EmailBuilder};
+use log::{debug, info};
+use moon::Utc;
+use serde::{Deserialize, Serialize};
+use crate::auth::Errors::MailSenderError;
+
+/// A rendered mail, ready to be sent
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Mail {
+    pub from: String,
+    pub reply_to: Option<String>,
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    /// HTML variant, the text is sent as alternative
+    pub html: Option<String>,
+}
+
+impl Mail {
+    pub fn to_email(&self) -> Result<Email, MailSenderError> {
+        let mut builder = EmailBuilder::new()
+            .to(self.to.as_str())
+            .from(self.from.as_str())
+            .subject(self.subject.as_str());
+        if let Some(reply_to) = &self.reply_to {
+            builder = builder.reply_to(reply_to.as_str());
+        }
+        builder = match &self.html {
+            Some(html) => builder.alternative(html.as_str(), self.text.as_str()),
+            None => builder.text(self.text.as_str())
+        };
+        Ok(builder.build()?)
+    }
+}
+
+/// Delivers mails. Sending blocks, call it outside of the async executor
+pub trait Mailer: Send + Sync {
+    fn send(&self, mail: &Mail) -> Result<(), MailSenderError>;
+}
+
+/// Sends mails through an SMTP server
+pub struct SmtpMailer {
+    client: SmtpClient,
+}
+
+impl SmtpMailer {
+    pub fn new(client: SmtpClient) -> Self {
+        SmtpMailer { client }
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, mail: &Mail) -> Result<(), MailSenderError> {
+        debug!("Sending mail '{}' to {}", mail.subject, mail.to);
+        let mut transport = self.client.clone().transport();
+        transport.send(mail.to_email()?.into())?;
+        Ok(())
+    }
+}
+
+/// Writes every mail as `.eml` file into a directory instead of sending it
+pub struct FileMailer {
+    directory: PathBuf,
+    counter: AtomicU64,
+}
+
+impl FileMailer {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        FileMailer {
+            directory: directory.into(),
+            counter: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, mail: &Mail) -> Result<(), MailSenderError> {
+        std::fs::create_dir_all(&self.directory)?;
+        let recipient: String = mail.to
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '@' || c == '.' { c } else { '_' })
+            .collect();
+        let file_name = format!("{}-{}-{}.eml",
+                                Utc::now().format("%Y%m%d-%H%M%S"),
+                                self.counter.fetch_add(1, Ordering::Relaxed),
+                                recipient);
+        let path = self.directory.join(file_name);
+
+        let email: SendableEmail = mail.to_email()?.into();
+        std::fs::write(&path, email.message_to_string()?)?;
+        info!("Wrote mail '{}' to {}", mail.subject, path.display());
+        Ok(())
+    }
+}
+
+/// Only logs mails
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, mail: &Mail) -> Result<(), MailSenderError> {
+        info!("Mail to {}: {}\n{}", mail.to, mail.subject, mail.text);
+        Ok(())
+    }
+}
+
+/// Keeps sent mails in memory, so tests can look at them
+#[derive(Default)]
+pub struct MemoryMailer {
+    mails: Mutex<Vec<Mail>>,
+}
+
+impl MemoryMailer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// All mails sent so far, oldest first
+    pub fn mails(&self) -> Vec<Mail> {
+        self.mails.lock().unwrap().clone()
+    }
+
+    pub fn mails_to(&self, address: &str) -> Vec<Mail> {
+        self.mails.lock().unwrap()
+            .iter()
+            .filter(|m| m.to == address)
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.mails.lock().unwrap().clear();
+    }
+}
+
+impl Mailer for MemoryMailer {
+    fn send(&self, mail: &Mail) -> Result<(), MailSenderError> {
+        debug!("Keeping mail '{}' to {} in memory", mail.subject, mail.to);
+        self.mails.lock().unwrap().push(mail.clone());
+        Ok(())
+    }
+}
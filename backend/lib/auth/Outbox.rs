@@ -0,0 +1,126 @@
+use diesel::{insert_into, ExpressionMethods, Insertable, MysqlConnection, QueryDsl, Queryable, RunQueryDsl};
+use log::error;
+use moon::{chrono, NaiveDateTime, Utc};
+use serde::Serialize;
+use crate::auth::Errors::DatabaseError;
+use crate::schema::OutboxEvents;
+
+/// Prefix of the routing keys the events are published with
+const ROUTING_KEY_PREFIX: &str = "smartauth";
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountType {
+    Citizen,
+    Employee,
+}
+
+/// Events SmartAuth publishes for the other services
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DomainEvent {
+    /// A citizen redeemed the registration code and created an account
+    CitizenAccountActivated { citizen_id: u64 },
+    PasswordChanged { citizen_id: u64 },
+    /// An employee redeemed an invitation
+    EmployeeCreated { employee_id: u64 },
+    /// A citizen account was locked or an employee was disabled
+    AccountLocked { account_type: AccountType, account_id: u64 },
+    UserLoggedIn { citizen_id: u64 },
+}
+
+impl DomainEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DomainEvent::CitizenAccountActivated { .. } => "citizen_account_activated",
+            DomainEvent::PasswordChanged { .. } => "password_changed",
+            DomainEvent::EmployeeCreated { .. } => "employee_created",
+            DomainEvent::AccountLocked { .. } => "account_locked",
+            DomainEvent::UserLoggedIn { .. } => "user_logged_in",
+        }
+    }
+
+    pub fn routing_key(&self) -> String {
+        format!("{}.{}", ROUTING_KEY_PREFIX, self.name())
+    }
+}
+
+/// Message body of a published event
+#[derive(Serialize)]
+struct EventPayload<'a> {
+    occurred: NaiveDateTime,
+    #[serde(flatten)]
+    event: &'a DomainEvent,
+}
+
+/// An event waiting to be published
+#[derive(Queryable, Clone, Debug)]
+pub struct OutboxEvent {
+    pub id: u64,
+    pub created: NaiveDateTime,
+    pub routing_key: String,
+    pub payload: String,
+    pub attempts: u32,
+    pub next_attempt: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="OutboxEvents"]
+struct NewOutboxEvent {
+    created: NaiveDateTime,
+    routing_key: String,
+    payload: String,
+    next_attempt: NaiveDateTime,
+}
+
+/// Stores the event in the outbox, it is published by the relay of the server.
+/// Call this inside the transaction of the change the event is about, so that either both or neither are stored
+pub fn enqueue_event(db: &MysqlConnection, event: &DomainEvent) -> Result<(), DatabaseError> {
+    let now = NaiveDateTime::from_timestamp(Utc::now().timestamp(), 0);
+    let payload = serde_json::to_string(&EventPayload { occurred: now, event })
+        .expect("Domain events are always serializable");
+
+    insert_into(OutboxEvents::table)
+        .values(&NewOutboxEvent {
+            created: now,
+            routing_key: event.routing_key(),
+            payload,
+            next_attempt: now,
+        })
+        .execute(db)?;
+    Ok(())
+}
+
+/// Like [enqueue_event], but only logs a failure. For events that are not part of a change
+pub fn try_enqueue_event(db: &MysqlConnection, event: &DomainEvent) {
+    if let Err(e) = enqueue_event(db, event) {
+        error!("Unable to store event {:?}: {}", event, e);
+    }
+}
+
+/// Oldest events that are due to be published
+pub fn load_due_events(db: &MysqlConnection, limit: i64) -> Result<Vec<OutboxEvent>, DatabaseError> {
+    use crate::schema::OutboxEvents::{id, next_attempt};
+
+    Ok(OutboxEvents::table
+        .filter(next_attempt.le(Utc::now().naive_utc()))
+        .order(id.asc())
+        .limit(limit)
+        .load(db)?)
+}
+
+pub fn mark_published(db: &MysqlConnection, event_id: u64) -> Result<(), DatabaseError> {
+    diesel::delete(OutboxEvents::table.find(event_id))
+        .execute(db)?;
+    Ok(())
+}
+
+/// Postpones the next attempt to publish the event by `delay`
+pub fn mark_failed(db: &MysqlConnection, event: &OutboxEvent, delay: chrono::Duration) -> Result<(), DatabaseError> {
+    use crate::schema::OutboxEvents::{attempts, next_attempt};
+
+    diesel::update(OutboxEvents::table.find(event.id))
+        .set((attempts.eq(event.attempts + 1), next_attempt.eq(Utc::now().naive_utc() + delay)))
+        .execute(db)?;
+    Ok(())
+}
@@ -0,0 +1,169 @@
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::ACCEPT_LANGUAGE;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Error body following RFC 7807. `code` is stable and meant for clients, `title` is a message for humans
+/// in the language of the request and `detail` the technical description of the error
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub title: String,
+    pub status: u16,
+    pub code: String,
+    pub detail: String,
+}
+
+/// Errors that are sent to clients as [Problem]
+pub trait ProblemCode {
+    /// Stable identifier of the error, e.g. `invalid_session`
+    fn code(&self) -> &'static str;
+}
+
+impl Problem {
+    pub fn new(status: StatusCode, code: &str, detail: &str) -> Self {
+        Problem {
+            kind: format!("urn:smartauth:problem:{}", code),
+            title: title(code, "en").unwrap_or(detail).to_string(),
+            status: status.as_u16(),
+            code: code.to_string(),
+            detail: detail.to_string(),
+        }
+    }
+
+    pub fn localize(mut self, locale: &str) -> Self {
+        if let Some(title) = title(&self.code, locale) {
+            self.title = title.to_string();
+        }
+        self
+    }
+
+    /// The problem as response. It is kept in the extensions of the response, so it can be localized later
+    pub fn response(self) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let mut response = HttpResponse::build(status)
+            .content_type(PROBLEM_CONTENT_TYPE)
+            .body(serde_json::to_string(&self).unwrap_or_default());
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+/// First language of the `Accept-Language` header that has messages, `default_locale` otherwise
+pub fn request_locale(req: &HttpRequest, default_locale: &str) -> String {
+    req.headers()
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value
+            .split(',')
+            .map(|language| language.split(';').next().unwrap_or("").trim())
+            .map(|language| language.split('-').next().unwrap_or("").to_lowercase())
+            .find(|language| title("internal_error", language).is_some()))
+        .unwrap_or_else(|| default_locale.to_string())
+}
+
+/// Replaces the title of a problem response with the one in `locale`
+pub fn localize_response(response: ServiceResponse, locale: &str) -> ServiceResponse {
+    let problem = response.response().extensions().get::<Problem>().cloned();
+    match problem {
+        Some(problem) => {
+            let (req, _) = response.into_parts();
+            ServiceResponse::new(req, problem.localize(locale).response())
+        }
+        None => response
+    }
+}
+
+fn title(code: &str, locale: &str) -> Option<&'static str> {
+    Some(match (locale, code) {
+        ("en", "invalid_request") => "The request is malformed",
+        ("en", "internal_error") => "An internal error occurred",
+        ("en", "database_unavailable") => "The database is currently unavailable",
+        ("en", "database_error") => "An error occurred in the database",
+        ("en", "duplicate") => "The entry already exists",
+        ("en", "not_found") => "The entry could not be found",
+        ("en", "constraint_violation") => "The entry is still in use",
+        ("en", "citizen_service_unavailable") => "The citizen service is currently unavailable",
+        ("en", "citizen_service_error") => "The citizen service sent an invalid response",
+        ("en", "citizen_not_found") => "The citizen could not be found",
+        ("en", "invalid_credentials") => "Username or password is wrong",
+        ("en", "second_factor_required") => "Please enter the code of your authenticator app",
+        ("en", "invalid_second_factor") => "The code of your authenticator app is wrong",
+        ("en", "account_disabled") => "The account is disabled",
+        ("en", "account_locked") => "The account is locked",
+        ("en", "invalid_session") => "Your session has expired, please log in again",
+        ("en", "missing_token") => "Please log in",
+        ("en", "missing_permission") => "You are not allowed to do this",
+        ("en", "invalid_registration_code") => "The registration code is invalid",
+        ("en", "expired_registration_code") => "The registration code has expired, please request a new one",
+        ("en", "mail_mismatch") => "The email address does not match the registration code",
+        ("en", "resend_throttled") => "A new code was sent recently, please try again later",
+        ("en", "invalid_invitation") => "The invitation is invalid or has expired",
+        ("en", "missing_email") => "An email address is required",
+        ("en", "invalid_reset_token") => "The password reset link is invalid or has expired",
+        ("en", "mail_error") => "The mail could not be sent",
+        ("en", "employee_not_found") => "The employee could not be found",
+        ("en", "own_account") => "You can not disable or delete your own account",
+        ("en", "audit_chain_error") => "The audit trail could not be verified",
+        ("en", "mail_not_found") => "The mail could not be found",
+        ("en", "mail_not_failed") => "Only failed mails can be sent again",
+        ("en", "mail_expired") => "The mail expired, a new code or link has to be requested",
+        ("en", "export_too_large") => "Too many events to export, please narrow the filters",
+        ("en", "already_registered") => "The citizen is already registered",
+        ("en", "missing_identifier") => "A citizen id, username or email address is required",
+        ("en", "missing_address") => "No postal address is known for the citizen",
+        ("en", "citizen_without_email") => "No email address is known for the citizen",
+        ("en", "letter_error") => "The letter could not be created",
+        ("en", "letter_not_found") => "The letter could not be found",
+        ("en", "invalid_redirect") => "The redirect target is invalid",
+        ("en", "redirect_not_allowed") => "The redirect target is not allowed",
+
+        ("de", "invalid_request") => "Die Anfrage ist fehlerhaft",
+        ("de", "internal_error") => "Es ist ein interner Fehler aufgetreten",
+        ("de", "database_unavailable") => "Die Datenbank ist zurzeit nicht erreichbar",
+        ("de", "database_error") => "In der Datenbank ist ein Fehler aufgetreten",
+        ("de", "duplicate") => "Der Eintrag existiert bereits",
+        ("de", "not_found") => "Der Eintrag wurde nicht gefunden",
+        ("de", "constraint_violation") => "Der Eintrag wird noch verwendet",
+        ("de", "citizen_service_unavailable") => "Das Bürgeramt ist zurzeit nicht erreichbar",
+        ("de", "citizen_service_error") => "Das Bürgeramt hat eine ungültige Antwort geschickt",
+        ("de", "citizen_not_found") => "Der Bürger wurde nicht gefunden",
+        ("de", "invalid_credentials") => "Benutzername oder Passwort ist falsch",
+        ("de", "second_factor_required") => "Bitte geben Sie den Code Ihrer Authenticator-App ein",
+        ("de", "invalid_second_factor") => "Der Code Ihrer Authenticator-App ist falsch",
+        ("de", "account_disabled") => "Das Konto ist deaktiviert",
+        ("de", "account_locked") => "Das Konto ist gesperrt",
+        ("de", "invalid_session") => "Ihre Sitzung ist abgelaufen, bitte melden Sie sich erneut an",
+        ("de", "missing_token") => "Bitte melden Sie sich an",
+        ("de", "missing_permission") => "Sie sind dazu nicht berechtigt",
+        ("de", "invalid_registration_code") => "Der Registrierungscode ist ungültig",
+        ("de", "expired_registration_code") => "Der Registrierungscode ist abgelaufen, bitte fordern Sie einen neuen an",
+        ("de", "mail_mismatch") => "Die E-Mail-Adresse passt nicht zum Registrierungscode",
+        ("de", "resend_throttled") => "Es wurde gerade erst ein neuer Code verschickt, bitte versuchen Sie es später erneut",
+        ("de", "invalid_invitation") => "Die Einladung ist ungültig oder abgelaufen",
+        ("de", "missing_email") => "Eine E-Mail-Adresse wird benötigt",
+        ("de", "invalid_reset_token") => "Der Link zum Zurücksetzen des Passworts ist ungültig oder abgelaufen",
+        ("de", "mail_error") => "Die E-Mail konnte nicht verschickt werden",
+        ("de", "employee_not_found") => "Der Mitarbeiter wurde nicht gefunden",
+        ("de", "own_account") => "Sie können Ihr eigenes Konto nicht deaktivieren oder löschen",
+        ("de", "audit_chain_error") => "Das Audit-Protokoll konnte nicht geprüft werden",
+        ("de", "mail_not_found") => "Die E-Mail wurde nicht gefunden",
+        ("de", "mail_not_failed") => "Nur fehlgeschlagene E-Mails können erneut verschickt werden",
+        ("de", "mail_expired") => "Die E-Mail ist abgelaufen, ein neuer Code oder Link muss angefordert werden",
+        ("de", "export_too_large") => "Zu viele Ereignisse für den Export, bitte schränken Sie die Filter ein",
+        ("de", "already_registered") => "Der Bürger ist bereits registriert",
+        ("de", "missing_identifier") => "Bürger-ID, Benutzername oder E-Mail Adresse wird benötigt",
+        ("de", "missing_address") => "Für den Bürger ist keine Postanschrift bekannt",
+        ("de", "citizen_without_email") => "Für den Bürger ist keine E-Mail-Adresse bekannt",
+        ("de", "letter_error") => "Der Brief konnte nicht erstellt werden",
+        ("de", "letter_not_found") => "Der Brief wurde nicht gefunden",
+        ("de", "invalid_redirect") => "Das Weiterleitungsziel ist ungültig",
+        ("de", "redirect_not_allowed") => "Das Weiterleitungsziel ist nicht erlaubt",
+        _ => return None
+    })
+}
@@ -0,0 +1,63 @@
+use actix_web::http::header::{HeaderValue, LOCATION};
+use actix_web::HttpResponse;
+use url::{Origin, Url};
+use crate::auth::Errors::RedirectError;
+
+/// Checks the `redirect_success` and `redirect_error` targets sent by clients. Relative targets and targets on
+/// the own origin are always allowed, other targets only if their origin is in the allowlist
+#[derive(Clone, Debug)]
+pub struct Redirects {
+    base: Url,
+    allowed: Vec<Origin>,
+}
+
+impl Redirects {
+    pub fn new(base: &str, allowed_origins: &[String]) -> Result<Self, url::ParseError> {
+        let base = Url::parse(base)?;
+        let allowed = allowed_origins
+            .iter()
+            .map(|origin| Url::parse(origin).map(|url| url.origin()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Redirects { base, allowed })
+    }
+
+    /// The target as absolute URL, if it may be redirected to
+    pub fn target(&self, target: &str) -> Result<Url, RedirectError> {
+        let url = self.base.join(target).map_err(|_| RedirectError::Invalid)?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(RedirectError::NotAllowed);
+        }
+        let origin = url.origin();
+        if origin != self.base.origin() && !self.allowed.contains(&origin) {
+            return Err(RedirectError::NotAllowed);
+        }
+        Ok(url)
+    }
+
+    /// Checks an optional target, so requests with forbidden targets can be rejected before anything happens
+    pub fn optional_target(&self, target: Option<&str>) -> Result<Option<Url>, RedirectError> {
+        target.map(|target| self.target(target)).transpose()
+    }
+}
+
+/// `302 Found` to `target` with `parameters` and the opaque `state` of the client merged into its query.
+/// Parameters of the same name that are already part of the target are replaced
+pub fn redirect_to(mut target: Url, parameters: &[(&str, &str)], state: Option<&str>) -> Result<HttpResponse, RedirectError> {
+    let state = state.map(|state| ("state", state));
+    let parameters: Vec<(&str, &str)> = parameters.iter().copied().chain(state).collect();
+
+    let kept: Vec<(String, String)> = target
+        .query_pairs()
+        .filter(|(name, _)| !parameters.iter().any(|(replaced, _)| replaced == name))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    target.set_query(None);
+    if !kept.is_empty() || !parameters.is_empty() {
+        target.query_pairs_mut()
+            .extend_pairs(kept)
+            .extend_pairs(parameters);
+    }
+
+    let location = HeaderValue::try_from(target.as_str()).map_err(|_| RedirectError::Invalid)?;
+    Ok(HttpResponse::Found().append_header((LOCATION, location)).finish())
+}
@@ -0,0 +1,93 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use crate::auth::Session::Token;
+
+/// Crockford base32, without the letters that are easily confused with digits
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const SEPARATOR: char = '-';
+
+/// Format of the registration codes, e.g. `7KQ2-M9XD-4HTR-B6W3` for 4 groups of 4 characters.
+/// With `check_digit` the last character is a checksum of the others, so typos are noticed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct RegistrationCodeFormat {
+    pub groups: usize,
+    pub group_length: usize,
+    pub check_digit: bool,
+}
+
+impl Default for RegistrationCodeFormat {
+    fn default() -> Self {
+        RegistrationCodeFormat {
+            groups: 4,
+            group_length: 4,
+            check_digit: true,
+        }
+    }
+}
+
+impl RegistrationCodeFormat {
+    fn length(&self) -> usize {
+        (self.groups * self.group_length).max(2)
+    }
+
+    pub fn generate(&self) -> Token {
+        let mut rng = rand::thread_rng();
+        let random_length = self.length() - self.check_digit as usize;
+        let mut symbols: Vec<u8> = (0..random_length)
+            .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())])
+            .collect();
+        if self.check_digit {
+            symbols.push(check_symbol(&symbols));
+        }
+        self.group(&symbols)
+    }
+
+    /// The code as it is stored, if `input` has the format of a code. Case, dashes and spaces are ignored,
+    /// `O` is read as `0` and `I` and `L` as `1`
+    pub fn normalize(&self, input: &str) -> Option<Token> {
+        let symbols = input
+            .chars()
+            .filter(|c| *c != SEPARATOR && !c.is_whitespace())
+            .map(|c| match c.to_ascii_uppercase() {
+                'O' => Some(b'0'),
+                'I' | 'L' => Some(b'1'),
+                c if c.is_ascii() && ALPHABET.contains(&(c as u8)) => Some(c as u8),
+                _ => None
+            })
+            .collect::<Option<Vec<u8>>>()?;
+
+        if symbols.len() != self.length() {
+            return None;
+        }
+        if self.check_digit {
+            let (code, check) = symbols.split_at(symbols.len() - 1);
+            if check_symbol(code) != check[0] {
+                return None;
+            }
+        }
+        Some(self.group(&symbols))
+    }
+
+    fn group(&self, symbols: &[u8]) -> Token {
+        symbols
+            .chunks(self.group_length.max(1))
+            .map(|chunk| String::from_utf8_lossy(chunk).to_string())
+            .collect::<Vec<_>>()
+            .join(&SEPARATOR.to_string())
+    }
+}
+
+/// Weighted sum of the symbol values. Odd weights catch every single typo, the changing weights most swapped neighbours
+fn check_symbol(symbols: &[u8]) -> u8 {
+    let sum: usize = symbols
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (2 * i + 1) * value(*s))
+        .sum();
+    ALPHABET[sum % ALPHABET.len()]
+}
+
+fn value(symbol: u8) -> usize {
+    ALPHABET.iter().position(|s| *s == symbol).unwrap_or(0)
+}
@@ -1,11 +1,60 @@
+use std::future::Future;
+use std::ops::Deref;
+use std::pin::Pin;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest};
+use actix_web::dev::Payload;
+use serde::de::DeserializeOwned;
+use schemars::JsonSchema;
 use serde::{Serialize, Deserialize};
+use moon::NaiveDateTime;
+use crate::auth::Audit::{AuditEvent, AuditEventType, AuditOutcome};
 use crate::auth::Citizen::CitizenInfo;
 use crate::auth::Credentials::CredentialsPair;
 use crate::auth::Session::Token;
 use crate::auth::User::User;
-use crate::auth::Employee::{EmployeeLogin, NewEmployeeInfo};
+use crate::auth::Employee::{EmployeeLogin, EmployeeStatus, NewEmployeeInfo, Role};
+use crate::auth::LetterOutbox::QueuedLetter;
+use crate::auth::MailOutbox::{MailStatus, QueuedMail};
 
-#[derive(Deserialize, Debug)]
+/// Request body sent either as JSON (`application/json`) or url-encoded form, depending on the content type
+pub struct FormOrJson<T>(pub T);
+
+impl<T> FormOrJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for FormOrJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// Whether [FormOrJson] reads the body of the request as JSON
+pub fn is_json_request(req: &HttpRequest) -> bool {
+    let content_type = req.content_type();
+    content_type == "application/json" || content_type.ends_with("+json")
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for FormOrJson<T> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        if is_json_request(req) {
+            let json = web::Json::<T>::from_request(req, payload);
+            Box::pin(async move { Ok(FormOrJson(json.await?.into_inner())) })
+        } else {
+            let form = web::Form::<T>::from_request(req, payload);
+            Box::pin(async move { Ok(FormOrJson(form.await?.into_inner())) })
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
 pub struct UserRegistrationRequest {
     #[serde(flatten)]
     pub credentials: CredentialsPair,
@@ -13,60 +62,332 @@ pub struct UserRegistrationRequest {
     pub code: Token,
 
     pub redirect_success: Option<String>,
-    pub redirect_error: Option<String>
+    pub redirect_error: Option<String>,
+    /// Passed back unchanged to the redirect target
+    pub state: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+/// The citizen id, the address the last code was sent to or both
+#[derive(Deserialize, Debug, JsonSchema)]
+pub struct RegistrationResendRequest {
+    pub citizen_id: Option<u64>,
+    pub email: Option<String>,
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
 pub struct UserLoginRequest {
     #[serde(flatten)]
     pub credentials: CredentialsPair,
 
     pub redirect_success: Option<String>,
     pub redirect_error: Option<String>,
+    /// Passed back unchanged to the redirect target
+    pub state: Option<String>,
 }
 
 pub struct UserLoginRequestResponse {
     pub user: User,
-    pub new_session_token: Token
+    pub new_session_token: Token,
+    pub session_expires: NaiveDateTime
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CitizenInfoStatus {
+    Current,
+    /// The citizen service is unavailable, the info is the last one known
+    Stale,
+    /// The citizen service is unavailable and no info is known
+    Unavailable,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
 pub struct UserInfoRequestResponse {
     pub(crate) citizen_id: u64,
     pub(crate) username: String,
     pub(crate) user_session_token: String,
-    pub(crate) info: CitizenInfo
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) info: Option<CitizenInfo>,
+    pub(crate) info_status: CitizenInfoStatus
 }
-#[derive(Deserialize, Serialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
 pub struct TokenValidateRequest {
     pub code: Token
 }
 
-#[derive(Deserialize)]
-pub struct EmployeeRegisterRequest {
+#[derive(Deserialize, JsonSchema)]
+pub struct EmployeeInviteRequest {
     pub code: Token,
     #[serde(flatten)]
     pub info: NewEmployeeInfo,
+    /// Language of the invitation mail, e.g. `de` or `en`
+    #[serde(default)]
+    pub locale: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+pub struct EmployeeInvitationResponse {
+    pub info_id: u64,
+    pub email: String,
+    pub expires: NaiveDateTime
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
+pub struct EmployeeInvitationQuery {
+    pub token: Token
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+pub struct TotpEnrollmentResponse {
+    pub totp_secret: String,
+    pub provisioning_uri: String
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct EmployeeRegisterRequest {
+    pub invitation: Token,
 
     #[serde(flatten)]
     pub credentials: CredentialsPair,
+
+    /// Current code for the secret from the invitation page
+    pub totp_code: String,
+}
+
+/// Answer to JSON requests, form posts are redirected to the login page
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+pub struct EmployeeRegisterResponse {
+    pub employee_id: u64,
+    pub username: String
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
+pub struct EmployeeLoginRequest {
+    #[serde(flatten)]
+    pub credentials: CredentialsPair,
+    pub otp: Option<String>,
+
+    pub redirect_success: Option<String>,
+    pub redirect_error: Option<String>,
+    /// Passed back unchanged to the redirect target
+    pub state: Option<String>,
 }
 
 pub struct EmployeeLoginRequestResponse {
     pub employee: EmployeeLogin,
-    pub new_employee_token: Token
+    pub new_employee_token: Token,
+    pub session_expires: NaiveDateTime
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, JsonSchema)]
 pub struct ExternalUserLoginRequest {
     pub redirect_success: Option<String>,
     pub redirect_error: Option<String>,
+    pub state: Option<String>,
 }
 
-#[derive(Deserialize, Serialize, Clone)]
+#[derive(Deserialize, Serialize, Clone, JsonSchema)]
 pub struct EmployeeInfoRequestResponse {
     pub(crate) id: u64,
     pub(crate) username: String,
     pub employee_session_token: Token,
     pub info: NewEmployeeInfo
-}
\ No newline at end of file
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
+pub struct EmployeeAdministrationRequest {
+    pub code: Token,
+    pub employee_id: u64
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
+pub struct EmployeeRoleRequest {
+    pub code: Token,
+    pub employee_id: u64,
+    pub role: Role
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
+pub struct EmployeeListRequest {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+
+    /// Matches first name, last name and username
+    pub search: Option<String>,
+    pub department: Option<String>,
+    pub status: Option<EmployeeStatus>,
+    pub role: Option<Role>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+pub struct EmployeeListResponse<T> {
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+    pub employees: Vec<T>
+}
+
+#[derive(Deserialize, Serialize, Clone, JsonSchema)]
+pub struct EmployeeListEntry {
+    pub info_id: u64,
+    /// Not set for employees that did not redeem their invitation yet
+    pub employee_id: Option<u64>,
+    pub username: Option<String>,
+    pub roles: Vec<Role>,
+    pub status: EmployeeStatus,
+    pub last_login: Option<NaiveDateTime>,
+    pub active_sessions: i64,
+    pub info: NewEmployeeInfo
+}
+
+#[derive(Deserialize, Serialize, Clone, JsonSchema)]
+pub struct EmployeeDirectoryEntry {
+    pub info_id: u64,
+    pub info: NewEmployeeInfo
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
+pub struct CitizenLookupRequest {
+    pub citizen_id: Option<u64>,
+    pub username: Option<String>
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
+pub struct CitizenAdministrationRequest {
+    pub code: Token,
+    pub citizen_id: u64
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+pub struct CitizenSessionEntry {
+    pub id: u64,
+    pub expires: NaiveDateTime,
+    pub valid: bool
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+pub struct CitizenAccountResponse {
+    pub citizen_id: u64,
+    /// Not set for citizens that did not register yet
+    pub username: Option<String>,
+    pub locked: bool,
+    pub pending_registration: bool,
+    pub sessions: Vec<CitizenSessionEntry>
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
+pub struct PasswordResetQuery {
+    pub token: Token
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
+pub struct PasswordResetRequest {
+    pub token: Token,
+    pub password: String
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditExportFormat {
+    Json,
+    Csv,
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
+pub struct AuditQueryRequest {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+
+    pub event_type: Option<AuditEventType>,
+    pub outcome: Option<AuditOutcome>,
+    pub actor_type: Option<String>,
+    pub actor_id: Option<u64>,
+    pub subject_type: Option<String>,
+    pub subject_id: Option<u64>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+
+    /// `csv` exports all matching events at once instead of a single page
+    pub format: Option<AuditExportFormat>,
+}
+
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct AuditQueryResponse {
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+    pub events: Vec<AuditEvent>
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
+pub struct MailListRequest {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+    pub status: Option<MailStatus>,
+}
+
+/// A mail in the outbox, without its content
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct MailListEntry {
+    pub id: u64,
+    pub created: NaiveDateTime,
+    pub recipient: String,
+    pub subject: String,
+    pub status: String,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub next_attempt: NaiveDateTime,
+    pub sent: Option<NaiveDateTime>,
+}
+
+impl From<QueuedMail> for MailListEntry {
+    fn from(mail: QueuedMail) -> Self {
+        MailListEntry {
+            id: mail.id,
+            created: mail.created,
+            recipient: mail.recipient,
+            subject: mail.subject,
+            status: mail.status,
+            attempts: mail.attempts,
+            last_error: mail.last_error,
+            next_attempt: mail.next_attempt,
+            sent: mail.sent,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct MailListResponse {
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+    pub mails: Vec<MailListEntry>
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
+pub struct MailResendRequest {
+    pub code: Token,
+    pub mail_id: u64,
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
+pub struct LetterListRequest {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+    /// Include letters that were already downloaded
+    #[serde(default)]
+    pub all: bool,
+}
+
+#[derive(Serialize, Debug, JsonSchema)]
+pub struct LetterListResponse {
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+    pub letters: Vec<QueuedLetter>,
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
+pub struct LetterDownloadRequest {
+    pub letter_id: u64,
+}
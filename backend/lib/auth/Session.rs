@@ -17,7 +17,7 @@ use diesel::deserialize::FromSql;
 use crate::auth::Errors::SessionCreationError;
 use crate::auth::User::User;
 pub type Token = String;
-fn create_token() -> Token {
+pub fn create_token() -> Token {
         let mut rng = rand::thread_rng();
         const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
                             abcdefghijklmnopqrstuvwxyz\
@@ -44,11 +44,11 @@ pub struct NewSession {
     pub expires: NaiveDateTime
 }
 impl NewSession {
-    pub fn new() -> Result<Self, SessionCreationError> {
+    pub fn new(lifetime: chrono::Duration) -> Result<Self, SessionCreationError> {
         let token = create_token();
         Utc::now()
             .naive_utc()
-            .checked_add_signed(chrono::Duration::days(1))
+            .checked_add_signed(lifetime)
             .ok_or(SessionCreationError::Overflow)
             .map(|e| Self {token, expires: e})
     }
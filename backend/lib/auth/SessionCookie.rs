@@ -0,0 +1,42 @@
+use actix_web::cookie::{Cookie, SameSite};
+use moon::{NaiveDateTime, Utc};
+use crate::auth::Session::Token;
+
+/// Builds the session cookies according to the configured policy. Without a domain and with `Secure`
+/// the names get the `__Host-` prefix, so the cookies can not be set or overwritten by other hosts
+#[derive(Clone, Debug)]
+pub struct SessionCookies {
+    domain: Option<String>,
+    secure: bool,
+    same_site: SameSite,
+}
+
+impl SessionCookies {
+    pub fn new(domain: Option<String>, secure: bool, same_site: SameSite) -> Self {
+        SessionCookies { domain, secure, same_site }
+    }
+
+    /// Name of the cookie, e.g. `__Host-user_session_token` for `user_session_token`
+    pub fn name(&self, name: &str) -> String {
+        match (&self.domain, self.secure) {
+            (None, true) => format!("__Host-{}", name),
+            _ => name.to_string()
+        }
+    }
+
+    /// Cookie with the session token that expires together with the session
+    pub fn build(&self, name: &str, token: &Token, expires: &NaiveDateTime) -> Cookie<'static> {
+        let max_age = (*expires - Utc::now().naive_utc()).num_seconds().max(0);
+        let mut cookie = Cookie::build(self.name(name), token.clone())
+            .path("/")
+            .secure(self.secure)
+            .http_only(true)
+            .same_site(self.same_site)
+            .max_age(time::Duration::seconds(max_age))
+            .finish();
+        if let Some(domain) = &self.domain {
+            cookie.set_domain(domain.clone());
+        }
+        cookie
+    }
+}
@@ -0,0 +1,54 @@
+use base32::Alphabet;
+use hmac::{Hmac, Mac};
+use moon::Utc;
+use rand::RngCore;
+use sha1::Sha1;
+
+type HmacSha1 = Hmac<Sha1>;
+
+const SECRET_ALPHABET: Alphabet = Alphabet::RFC4648 { padding: false };
+const STEP_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+//Accept codes from one step before and after the current one to allow for clock drift
+const ALLOWED_DRIFT: i64 = 1;
+
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    base32::encode(SECRET_ALPHABET, &secret)
+}
+
+pub fn provisioning_uri(secret: &str, account: &str, issuer: &str) -> String {
+    format!("otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&digits={DIGITS}&period={STEP_SECONDS}")
+}
+
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = HmacSha1::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = ((digest[offset] as u32 & 0x7f) << 24)
+        | ((digest[offset + 1] as u32) << 16)
+        | ((digest[offset + 2] as u32) << 8)
+        | (digest[offset + 3] as u32);
+
+    binary % 10u32.pow(DIGITS)
+}
+
+pub fn verify_code(secret: &str, code: &str) -> bool {
+    let key = match base32::decode(SECRET_ALPHABET, secret) {
+        Some(k) => k,
+        None => return false
+    };
+    let code: u32 = match code.trim().parse() {
+        Ok(c) => c,
+        Err(_) => return false
+    };
+
+    let current_step = Utc::now().timestamp() / STEP_SECONDS;
+    (-ALLOWED_DRIFT..=ALLOWED_DRIFT)
+        .map(|drift| current_step + drift)
+        .filter(|step| *step >= 0)
+        .any(|step| hotp(&key, step as u64) == code)
+}
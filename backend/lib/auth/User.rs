@@ -1,35 +1,20 @@
 use crate::auth::Credentials::{CredentialsHolder, IdentityHolder};
 use diesel::dsl::*;
-use moon::{chrono, Utc};
-use rand::Rng;
+use moon::{chrono, NaiveDateTime, Utc};
 use crate::auth::Citizen::IsCitizen;
 use crate::auth::Session::Token;
 use crate::schema::Sessions::dsl::Sessions;
 use crate::schema::Users;
 use crate::schema::PendingUsers;
+use crate::schema::PasswordResets;
 
 #[derive(Queryable, Identifiable, Clone)]
 #[table_name = "Users"]
 pub struct User {
     pub id: u64,
     pub username: String,
-    pub hash: String
-}
-impl User {
-    pub fn generate_pending_code() -> Token {
-        let mut rng = rand::thread_rng();
-
-        const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
-                            abcdefghijklmnopqrstuvwxyz\
-                            0123456789)(*&^%$#@!~";
-        //Read max len of code from somewhere maybe
-        (0..10)
-            .map(|_| {
-                let idx = rng.gen_range(0..CHARSET.len());
-                CHARSET[idx] as char
-            })
-            .collect()
-    }
+    pub hash: String,
+    pub locked: bool
 }
 impl IdentityHolder for User {
     fn get_hash(&self) -> &str {
@@ -49,9 +34,38 @@ impl IsCitizen for User {
 #[derive(Queryable, Identifiable, PartialEq)]
 #[table_name="PendingUsers"]
 pub struct PendingUser {
-    id: u64,
+    pub id: u64,
     pub citizen: i64,
-    code: String
+    pub code: String,
+    /// Address the code was sent to
+    pub email: Option<String>,
+    pub issued: NaiveDateTime,
+    pub expires: NaiveDateTime,
 }
 
+impl PendingUser {
+    pub fn is_valid(&self) -> bool {
+        self.expires >= Utc::now().naive_utc()
+    }
 
+    /// Whether the code was issued at least `interval` ago, so a new one may be sent
+    pub fn can_be_resent(&self, interval: chrono::Duration) -> bool {
+        self.issued + interval <= Utc::now().naive_utc()
+    }
+}
+
+#[derive(Queryable, Identifiable, PartialEq, Associations)]
+#[belongs_to(User, foreign_key = "user_id")]
+#[table_name="PasswordResets"]
+pub struct PasswordReset {
+    pub id: u64,
+    pub user_id: u64,
+    pub token: Token,
+    pub expires: NaiveDateTime
+}
+
+impl PasswordReset {
+    pub fn is_valid(&self) -> bool {
+        self.expires >= Utc::now().naive_utc()
+    }
+}
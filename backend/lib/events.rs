@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use crate::auth::Errors::EventHandlingError;
+
+/// A citizen was added to the citizen registry and should receive a registration code
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct NewCitizenEvent {
+    pub citizen_id: u64,
+}
+
+impl NewCitizenEvent {
+    pub const EVENT_ID: u64 = 1001;
+}
+
+/// Personal information of a citizen changed, e.g. the email address
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CitizenUpdatedEvent {
+    pub citizen_id: u64,
+}
+
+impl CitizenUpdatedEvent {
+    pub const EVENT_ID: u64 = 1002;
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CitizenDeceasedEvent {
+    pub citizen_id: u64,
+}
+
+impl CitizenDeceasedEvent {
+    pub const EVENT_ID: u64 = 1003;
+}
+
+/// The citizen moved out of the city
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CitizenMovedOutEvent {
+    pub citizen_id: u64,
+}
+
+impl CitizenMovedOutEvent {
+    pub const EVENT_ID: u64 = 1004;
+}
+
+/// The citizen was removed from the citizen registry
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CitizenDeletedEvent {
+    pub citizen_id: u64,
+}
+
+impl CitizenDeletedEvent {
+    pub const EVENT_ID: u64 = 1005;
+}
+
+/// Envelope shared by all events, the remaining fields depend on the event id
+#[derive(Deserialize)]
+pub struct RawEvent {
+    event_id: u64,
+    #[serde(flatten)]
+    payload: Value,
+}
+
+/// Events SmartAuth consumes from the other services, tagged by their numeric `event_id`
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(try_from = "RawEvent")]
+pub enum IntegrationEvent {
+    NewCitizen(NewCitizenEvent),
+    CitizenUpdated(CitizenUpdatedEvent),
+    CitizenDeceased(CitizenDeceasedEvent),
+    CitizenMovedOut(CitizenMovedOutEvent),
+    CitizenDeleted(CitizenDeletedEvent),
+    /// An event SmartAuth is not interested in
+    Unknown { event_id: u64 },
+}
+
+impl TryFrom<RawEvent> for IntegrationEvent {
+    type Error = serde_json::Error;
+
+    fn try_from(raw: RawEvent) -> Result<Self, Self::Error> {
+        Ok(match raw.event_id {
+            NewCitizenEvent::EVENT_ID => IntegrationEvent::NewCitizen(serde_json::from_value(raw.payload)?),
+            CitizenUpdatedEvent::EVENT_ID => IntegrationEvent::CitizenUpdated(serde_json::from_value(raw.payload)?),
+            CitizenDeceasedEvent::EVENT_ID => IntegrationEvent::CitizenDeceased(serde_json::from_value(raw.payload)?),
+            CitizenMovedOutEvent::EVENT_ID => IntegrationEvent::CitizenMovedOut(serde_json::from_value(raw.payload)?),
+            CitizenDeletedEvent::EVENT_ID => IntegrationEvent::CitizenDeleted(serde_json::from_value(raw.payload)?),
+            event_id => IntegrationEvent::Unknown { event_id }
+        })
+    }
+}
+
+impl IntegrationEvent {
+    pub fn event_id(&self) -> u64 {
+        match self {
+            IntegrationEvent::NewCitizen(_) => NewCitizenEvent::EVENT_ID,
+            IntegrationEvent::CitizenUpdated(_) => CitizenUpdatedEvent::EVENT_ID,
+            IntegrationEvent::CitizenDeceased(_) => CitizenDeceasedEvent::EVENT_ID,
+            IntegrationEvent::CitizenMovedOut(_) => CitizenMovedOutEvent::EVENT_ID,
+            IntegrationEvent::CitizenDeleted(_) => CitizenDeletedEvent::EVENT_ID,
+            IntegrationEvent::Unknown { event_id } => *event_id,
+        }
+    }
+
+    /// The citizen the event is about
+    pub fn citizen_id(&self) -> Option<u64> {
+        match self {
+            IntegrationEvent::NewCitizen(e) => Some(e.citizen_id),
+            IntegrationEvent::CitizenUpdated(e) => Some(e.citizen_id),
+            IntegrationEvent::CitizenDeceased(e) => Some(e.citizen_id),
+            IntegrationEvent::CitizenMovedOut(e) => Some(e.citizen_id),
+            IntegrationEvent::CitizenDeleted(e) => Some(e.citizen_id),
+            IntegrationEvent::Unknown { .. } => None,
+        }
+    }
+
+    /// Parses the body of an AMQP message, malformed messages can never be handled
+    pub fn from_payload(data: &[u8]) -> Result<Self, EventHandlingError> {
+        serde_json::from_slice(data)
+            .map_err(|e| EventHandlingError::Permanent(format!("Malformed event: {}", e)))
+    }
+}
+
+/// Handlers have to be idempotent, events are delivered again after a failure and possibly more than once anyway
+#[async_trait]
+pub trait EventHandler: Send + Sync {
+    async fn handle(&self, event: &IntegrationEvent) -> Result<(), EventHandlingError>;
+}
+
+/// Routes incoming events to the handlers registered for their event id.
+/// Events without a handler are ignored
+#[derive(Default, Clone)]
+pub struct EventDispatcher {
+    handlers: HashMap<u64, Vec<Arc<dyn EventHandler>>>,
+}
+
+impl EventDispatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(mut self, event_id: u64, handler: impl EventHandler + 'static) -> Self {
+        self.handlers
+            .entry(event_id)
+            .or_default()
+            .push(Arc::new(handler));
+        self
+    }
+
+    pub fn handles(&self, event_id: u64) -> bool {
+        self.handlers.contains_key(&event_id)
+    }
+
+    /// Parses the payload and runs all handlers for it, stopping at the first failing one
+    pub async fn dispatch(&self, data: &[u8]) -> Result<IntegrationEvent, EventHandlingError> {
+        let event = IntegrationEvent::from_payload(data)?;
+        for handler in self.handlers.get(&event.event_id()).into_iter().flatten() {
+            handler.handle(&event).await?;
+        }
+        Ok(event)
+    }
+}
@@ -7,6 +7,7 @@ extern crate diesel;
 extern crate diesel_migrations;
 
 pub mod auth;
+pub mod events;
 pub mod server;
 pub mod schema;
 
@@ -1,8 +1,47 @@
+table! {
+    AuditChainLock (id) {
+        id -> Unsigned<Tinyint>,
+    }
+}
+
+table! {
+    AuditEvents (id) {
+        id -> Unsigned<Bigint>,
+        created -> Datetime,
+        actor_type -> Varchar,
+        actor_id -> Nullable<Unsigned<Bigint>>,
+        subject_type -> Nullable<Varchar>,
+        subject_id -> Nullable<Unsigned<Bigint>>,
+        event_type -> Varchar,
+        outcome -> Varchar,
+        details -> Nullable<Text>,
+        ip -> Nullable<Varchar>,
+        user_agent -> Nullable<Varchar>,
+        prev_hash -> Nullable<Varchar>,
+        hash -> Nullable<Varchar>,
+    }
+}
+
 table! {
     EmployeeInfo (id) {
         id -> Unsigned<Bigint>,
         firstname -> Varchar,
         lastname -> Varchar,
+        department -> Nullable<Varchar>,
+        email -> Nullable<Varchar>,
+        phone -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    EmployeeInvitations (id) {
+        id -> Unsigned<Bigint>,
+        info_id -> Unsigned<Bigint>,
+        token -> Varchar,
+        email -> Varchar,
+        invited_by -> Nullable<Unsigned<Bigint>>,
+        expires -> Datetime,
+        totp_secret -> Nullable<Varchar>,
     }
 }
 
@@ -12,6 +51,17 @@ table! {
         info_id -> Unsigned<Bigint>,
         username -> Varchar,
         hash -> Varchar,
+        totp_secret -> Nullable<Varchar>,
+        disabled -> Bool,
+        last_login -> Nullable<Datetime>,
+    }
+}
+
+table! {
+    EmployeeRoles (id) {
+        id -> Unsigned<Bigint>,
+        e_id -> Unsigned<Bigint>,
+        role -> Varchar,
     }
 }
 
@@ -24,11 +74,60 @@ table! {
     }
 }
 
+table! {
+    MailOutbox (id) {
+        id -> Unsigned<Bigint>,
+        created -> Datetime,
+        recipient -> Varchar,
+        subject -> Varchar,
+        mail -> Nullable<Text>,
+        status -> Varchar,
+        attempts -> Unsigned<Integer>,
+        last_error -> Nullable<Text>,
+        next_attempt -> Datetime,
+        sent -> Nullable<Datetime>,
+        expires -> Datetime,
+    }
+}
+
+table! {
+    OutboxEvents (id) {
+        id -> Unsigned<Bigint>,
+        created -> Datetime,
+        routing_key -> Varchar,
+        payload -> Text,
+        attempts -> Unsigned<Integer>,
+        next_attempt -> Datetime,
+    }
+}
+
+table! {
+    PasswordResets (id) {
+        id -> Unsigned<Bigint>,
+        user_id -> Unsigned<Bigint>,
+        token -> Varchar,
+        expires -> Datetime,
+    }
+}
+
 table! {
     PendingUsers (id) {
         id -> Unsigned<Bigint>,
         citizen -> Bigint,
         code -> Varchar,
+        email -> Nullable<Varchar>,
+        issued -> Datetime,
+        expires -> Datetime,
+    }
+}
+
+table! {
+    RegistrationLetters (id) {
+        id -> Unsigned<Bigint>,
+        citizen_id -> Unsigned<Bigint>,
+        created -> Datetime,
+        pdf -> Blob,
+        downloaded -> Nullable<Datetime>,
     }
 }
 
@@ -46,18 +145,30 @@ table! {
         id -> Unsigned<Bigint>,
         username -> Varchar,
         hash -> Varchar,
+        locked -> Bool,
     }
 }
 
+joinable!(EmployeeInvitations -> EmployeeInfo (info_id));
 joinable!(EmployeeLogins -> EmployeeInfo (info_id));
+joinable!(EmployeeRoles -> EmployeeLogins (e_id));
 joinable!(EmployeeSessions -> EmployeeLogins (e_id));
+joinable!(PasswordResets -> Users (user_id));
 joinable!(Sessions -> Users (user_id));
 
 allow_tables_to_appear_in_same_query!(
+    AuditChainLock,
+    AuditEvents,
     EmployeeInfo,
+    EmployeeInvitations,
     EmployeeLogins,
+    EmployeeRoles,
     EmployeeSessions,
+    MailOutbox,
+    OutboxEvents,
+    PasswordResets,
     PendingUsers,
+    RegistrationLetters,
     Sessions,
     Users,
 );
@@ -1,43 +1,74 @@
+mod api;
+mod events;
+mod mail;
+mod outbox;
 mod routes;
 
 use std::fmt;
 use actix_web::{App, web};
+use actix_web::cookie::SameSite;
+use actix_web::dev::Service;
 use actix_web::http::StatusCode;
+use actix_web::http::header::{HeaderName, HeaderValue, LINK};
 use actix_web::middleware::{Compat, Condition, ErrorHandlers, Logger};
 use either::Either;
-use anyhow::{Context, ensure, Result};
+use anyhow::{ensure, Context, Result};
 use config::Config;
 use diesel::MysqlConnection;
 use diesel::r2d2::ConnectionManager;
-use lapin::message::Delivery;
-use lapin::options::{BasicAckOptions, BasicConsumeOptions};
-use lapin::types::FieldTable;
 use lettre::smtp::authentication::Credentials;
 use lettre::SmtpClient;
-use log::{debug, info};
+use log::{debug, error, info};
 use moon::actix_cors::Cors;
 use moon::config::{CONFIG};
-use moon::{error_handler, Frontend, Redirect};
+use moon::{chrono, error_handler, Frontend, Redirect};
 use std::future::join;
 use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use moon::start_with_app;
-use moon::futures::StreamExt;
-use std::str;
 pub type DBPool = diesel::r2d2::Pool<ConnectionManager<MysqlConnection>>;
 pub type RMQPool = deadpool_lapin::Pool;
 
+use schemars::JsonSchema;
 use serde::{Serialize, Deserialize};
-use serde_json::Value;
-use crate::auth::Actions::{insert_new_pending_user, login_employee, register_employee, send_citizen_code};
-use crate::auth::Citizen::{Citizen, IsCitizen};
-use crate::auth::Endpoints::{employee_login, employee_login_external, employee_register, employee_verify, login_external, login_page, user_login, user_register, user_verify};
-use crate::server::routes::{ping};
+use crate::auth::AuditChain::{verify_audit_trail, write_checkpoint, ChainVerification};
+use crate::auth::CitizenDirectory::{citizen_directory, CitizenDirectory};
+use crate::auth::Errors::{form_error_handler, json_error_handler, query_error_handler, DatabaseError};
+use crate::auth::Letter::LetterRenderer;
+use crate::auth::Problem::{localize_response, request_locale};
+use crate::auth::Redirect::Redirects;
+use crate::auth::SessionCookie::SessionCookies;
+use crate::auth::RegistrationCode::RegistrationCodeFormat;
+use crate::auth::Mailer::{FileMailer, LogMailer, Mailer, MemoryMailer, SmtpMailer};
+use crate::auth::MailTemplate::MailTemplates;
+use crate::auth::Endpoints::{employee_invitation_page, employee_login_external, login_external, login_page, password_reset_page};
+use crate::server::api::{api_routes, openapi, successor_path, swagger_ui, swagger_ui_asset, API_PREFIX};
+
+pub use crate::server::api::openapi_document;
 
 #[derive(Clone)]
 pub struct MailServer {
-    pub info: ServerCredentials,
-    pub transport: SmtpClient
+    pub mailer: Arc<dyn Mailer>,
+    /// Set for the memory transport, tests read the sent mails from it
+    pub memory: Option<Arc<MemoryMailer>>,
+    pub templates: Arc<MailTemplates>,
+    /// Registration letters for citizens without email address
+    pub letters: Arc<LetterRenderer>,
+}
+
+impl MailServer {
+    /// Keeps all mails in the given outbox instead of sending them
+    pub fn in_memory(outbox: Arc<MemoryMailer>, templates: MailTemplates, letters: LetterRenderer) -> Self {
+        MailServer {
+            mailer: outbox.clone(),
+            memory: Some(outbox),
+            templates: Arc::new(templates),
+            letters: Arc::new(letters),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -47,8 +78,40 @@ pub struct BackendServerInfo {
     db: Either<ServerCredentials, String>,
     #[serde(with = "either::serde_untagged")]
     rmq: Either<ServerCredentials, String>,
-    mail: ServerCredentials,
+    #[serde(default)]
+    mail: MailConfig,
+    /// Public address of this server, used to build links sent to citizens and employees
+    #[serde(default = "default_public_url")]
+    pub(crate) public_url: String,
+    #[serde(default)]
+    pub(crate) invitation: InvitationConfig,
+    #[serde(default)]
+    pub(crate) audit: AuditConfig,
+    #[serde(default)]
+    pub(crate) citizen_directory: CitizenDirectoryConfig,
+    #[serde(default)]
+    pub(crate) events: EventsConfig,
+    #[serde(default)]
+    pub(crate) publisher: PublisherConfig,
+    #[serde(default)]
+    pub(crate) mail_queue: MailQueueConfig,
+    #[serde(default)]
+    pub(crate) letter: LetterConfig,
+    #[serde(default)]
+    pub(crate) registration_code: RegistrationCodeFormat,
+    #[serde(default)]
+    pub(crate) redirect: RedirectConfig,
+    #[serde(default)]
+    pub(crate) session: SessionConfig,
 }
+
+/// Sessions that are valid for longer than a year are most likely a misconfiguration
+const MAX_SESSION_LIFETIME_HOURS: i64 = 24 * 365;
+
+fn default_public_url() -> String {
+    String::from("http://auth.smartcityproject.net:8080")
+}
+
 impl BackendServerInfo {
     fn try_from_file(path: &str) -> Result<Self> {
         debug!("Trying to read config file from {}", path);
@@ -70,11 +133,72 @@ impl BackendServerInfo {
             info: AuthServerInfo::default(),
             db: Either::Right(std::env::var("DATABASE_URL")?),
             rmq: Either::Right(std::env::var("AMQP_ADDR")?),
-            mail: ServerCredentials {
-                host: std::env::var("MAIL_HOST")?,
-                username: std::env::var("MAIL_USERNAME")?,
-                password: std::env::var("MAIL_PASSWORD")?
-            }
+            mail: MailConfig {
+                credentials: match std::env::var("MAIL_HOST") {
+                    Ok(host) => Some(ServerCredentials {
+                        host,
+                        username: std::env::var("MAIL_USERNAME")?,
+                        password: std::env::var("MAIL_PASSWORD")?
+                    }),
+                    Err(_) => None
+                },
+                transport: match std::env::var("MAIL_TRANSPORT") {
+                    Ok(t) => serde_json::from_value(serde_json::Value::String(t)).context("Invalid MAIL_TRANSPORT")?,
+                    Err(_) => MailTransport::Smtp
+                },
+                directory: std::env::var("MAIL_DIRECTORY").map(PathBuf::from).unwrap_or_else(|_| default_mail_directory()),
+                sender: std::env::var("MAIL_SENDER").unwrap_or_else(|_| default_mail_sender()),
+                reply_to: std::env::var("MAIL_REPLY_TO").ok(),
+                portal_url: std::env::var("PORTAL_URL").unwrap_or_else(|_| default_portal_url()),
+                ..MailConfig::default()
+            },
+            public_url: std::env::var("PUBLIC_URL").unwrap_or_else(|_| default_public_url()),
+            invitation: InvitationConfig {
+                bootstrap_token: std::env::var("BOOTSTRAP_TOKEN").ok(),
+                ..InvitationConfig::default()
+            },
+            audit: AuditConfig {
+                checkpoint_file: std::env::var("AUDIT_CHECKPOINT_FILE").ok().map(PathBuf::from),
+                checkpoint_key: std::env::var("AUDIT_CHECKPOINT_KEY").ok(),
+                trusted_proxies: match std::env::var("TRUSTED_PROXIES") {
+                    Ok(proxies) => proxies.split(',').map(|p| p.trim().parse()).collect::<Result<_, _>>().context("Invalid TRUSTED_PROXIES")?,
+                    Err(_) => vec![]
+                },
+                ..AuditConfig::default()
+            },
+            events: EventsConfig::default(),
+            publisher: PublisherConfig::default(),
+            mail_queue: MailQueueConfig::default(),
+            letter: LetterConfig::default(),
+            registration_code: RegistrationCodeFormat::default(),
+            redirect: RedirectConfig {
+                allowed_origins: std::env::var("REDIRECT_ORIGINS")
+                    .map(|origins| origins.split(',').map(|o| o.trim().to_string()).filter(|o| !o.is_empty()).collect())
+                    .unwrap_or_default(),
+            },
+            session: SessionConfig {
+                lifetime_hours: match std::env::var("SESSION_LIFETIME_HOURS") {
+                    Ok(hours) => hours.parse().context("Invalid SESSION_LIFETIME_HOURS")?,
+                    Err(_) => SessionConfig::default().lifetime_hours
+                },
+                cookie_domain: match std::env::var("COOKIE_DOMAIN") {
+                    Ok(domain) => Some(domain),
+                    Err(_) => SessionConfig::default().cookie_domain
+                },
+                secure_cookies: match std::env::var("COOKIE_SECURE") {
+                    Ok(secure) => Some(secure.parse().context("Invalid COOKIE_SECURE")?),
+                    Err(_) => None
+                },
+                same_site: match std::env::var("COOKIE_SAME_SITE") {
+                    Ok(s) => serde_json::from_value(serde_json::Value::String(s)).context("Invalid COOKIE_SAME_SITE")?,
+                    Err(_) => CookieSameSite::default()
+                },
+            },
+            citizen_directory: CitizenDirectoryConfig {
+                url: std::env::var("CITIZEN_SERVICE_URL").unwrap_or_else(|_| default_citizen_service_url()),
+                file: std::env::var("CITIZEN_FILE").ok().map(PathBuf::from),
+                ..CitizenDirectoryConfig::default()
+            },
         })
     }
 }
@@ -84,11 +208,14 @@ pub struct BackendServer {
     info: BackendServerInfo,
     db_pool: DBPool,
     rmq_pool: RMQPool,
-    mail_sender: MailServer
+    mail_sender: MailServer,
+    citizen_directory: Arc<dyn CitizenDirectory>,
+    redirects: Arc<Redirects>,
+    session_cookies: Arc<SessionCookies>,
 }
 
 impl BackendServer {
-    pub fn new(config_path: Option<&str>) -> Result<Self> {
+    fn read_config(config_path: Option<&str>) -> Result<BackendServerInfo> {
         println!("Reading config file...");
 
         let info =
@@ -97,6 +224,11 @@ impl BackendServer {
                 Some(p) => {BackendServerInfo::try_from_file(p).or_else(|_|BackendServerInfo::try_from_env())?}
             };
         println!("... done");
+        Ok(info)
+    }
+
+    pub fn new(config_path: Option<&str>) -> Result<Self> {
+        let info = Self::read_config(config_path)?;
 
         println!("Connecting to database...");
         let db_pool = Self::connect_to_database(&info)?;
@@ -110,38 +242,36 @@ impl BackendServer {
         let mail_sender = Self::create_mail_sender(&info)?;
         println!("...done!");
 
+        let citizen_directory = citizen_directory(&info.citizen_directory)?;
+        let redirects = Arc::new(Self::create_redirects(&info)?);
+        let session_cookies = Arc::new(Self::create_session_cookies(&info)?);
+
         Ok(Self{
             info,
             db_pool,
             rmq_pool,
-            mail_sender
+            mail_sender,
+            citizen_directory,
+            redirects,
+            session_cookies
         })
     }
 
+    /// The mail setup of the server, with the memory transport the sent mails can be read from it
+    pub fn mail_server(&self) -> &MailServer {
+        &self.mail_sender
+    }
+
     pub async fn start(config_path: Option<&str>) -> Result<()> {
         let server = BackendServer::new(config_path)?;
         let rmq_server = server.clone();
         let rmq_thread = rmq_server.events_listen(5);
-
-        let query_cfg = web::QueryConfig::default()
-            .error_handler(|err, req| {
-                actix_web::error::InternalError::from_response(
-                    "",
-                    actix_web::HttpResponse::BadRequest()
-                        .content_type("application/json")
-                        .body(format!(r#"{{"error": "{:?}"}}"#, err)),
-                ).into()
-            });
-
-        let form_cfg= web::FormConfig::default()
-            .error_handler(|err, req| {
-                actix_web::error::InternalError::from_response(
-                    "",
-                    actix_web::HttpResponse::BadRequest()
-                        .content_type("application/json")
-                        .body(format!(r#"{{"error": "{:?}"}}"#, err)),
-                ).into()
-            });
+        let checkpoint_server = server.clone();
+        let checkpoint_thread = checkpoint_server.audit_checkpoints();
+        let outbox_server = server.clone();
+        let outbox_thread = outbox_server.outbox_relay();
+        let mail_server = server.clone();
+        let mail_thread = mail_server.mail_queue();
 
         info!("Starting the server");
 
@@ -150,7 +280,16 @@ impl BackendServer {
                 .http_to_https(CONFIG.https)
                 .port(CONFIG.redirect.port, CONFIG.port);
 
+            let default_locale = server.info.mail.default_locale.clone();
+
             App::new()
+                .wrap_fn(move |req, srv| {
+                    let locale = request_locale(req.request(), &default_locale);
+                    let response = srv.call(req);
+                    async move {
+                        Ok(localize_response(response.await?, &locale))
+                    }
+                })
                 .wrap(Condition::new(CONFIG.redirect.enabled, Compat::new(redirect)))
                 .wrap(Logger::new("%r %s %D ms %a"))
                 .wrap(Cors::default().allowed_origin_fn(move |origin, _| {
@@ -163,22 +302,36 @@ impl BackendServer {
                     };
                     CONFIG.cors.origins.contains(origin)
                 }))
+                .wrap_fn(|req, srv| {
+                    let successor = successor_path(req.method(), req.path());
+                    let response = srv.call(req);
+                    async move {
+                        let mut response = response.await?;
+                        if let Some(successor) = successor {
+                            let headers = response.headers_mut();
+                            headers.insert(HeaderName::from_static("deprecation"), HeaderValue::from_static("true"));
+                            if let Ok(link) = HeaderValue::try_from(format!("<{}>; rel=\"successor-version\"", successor)) {
+                                headers.insert(LINK, link);
+                            }
+                        }
+                        Ok(response)
+                    }
+                })
                 .wrap(ErrorHandlers::new().handler(StatusCode::INTERNAL_SERVER_ERROR, error_handler::internal_server_error)
                     .handler(StatusCode::NOT_FOUND, error_handler::not_found))
 
-                .app_data(query_cfg)
-                .app_data(web::FormConfig::default().error_handler(|err, req| {
-                    actix_web::error::InternalError::from_response(
-                        "",
-                        actix_web::HttpResponse::BadRequest()
-                            .content_type("application/json")
-                            .body(format!(r#"{{"error": "{:?}"}}"#, err)),
-                    ).into()
-                }))
+                .app_data(web::QueryConfig::default().error_handler(query_error_handler))
+                .app_data(web::FormConfig::default().error_handler(form_error_handler))
+                .app_data(web::JsonConfig::default().error_handler(json_error_handler))
                 .app_data(web::Data::new(server.db_pool.clone()))
+                .app_data(web::Data::new(server.mail_sender.clone()))
+                .app_data(web::Data::new(server.info.clone()))
+                .app_data(web::Data::from(server.citizen_directory.clone()))
+                .app_data(web::Data::from(server.redirects.clone()))
+                .app_data(web::Data::from(server.session_cookies.clone()))
         };
         let server_thread = async {start_with_app(Self::frontend, Self::up_msg_handler, app, Self::set_routes).await.unwrap() };
-        join!(server_thread, rmq_thread).await;
+        join!(server_thread, rmq_thread, checkpoint_thread, outbox_thread, mail_thread).await;
         info!("Server done!");
         Ok(())
     }
@@ -195,14 +348,15 @@ impl BackendServer {
             .service(on_login_test);
 
          */
-        cfg.route("/ping", web::get().to(ping))
-            .route("/login", web::post().to(user_login))
-            .route("/verify", web::post().to(user_verify))
-            .route("/register", web::post().to(user_register))
+        cfg.service(web::scope(API_PREFIX)
+                .configure(api_routes)
+                .route("/openapi.json", web::get().to(openapi))
+                .route("/docs", web::get().to(swagger_ui))
+                .route("/docs/{file}", web::get().to(swagger_ui_asset)))
+            .configure(api_routes)
             .route("/external", web::get().to(login_external))
-            .route("/employee/login", web::post().to(employee_login))
-            .route("/employee/register", web::post().to(employee_register))
-            .route("/employee/verify", web::post().to(employee_verify))
+            .route("/employee/invitation", web::get().to(employee_invitation_page))
+            .route("/password/reset", web::get().to(password_reset_page))
             .route("/page/login", web::get().to(login_page))
             .route("/employee/external", web::get().to(employee_login_external));
 
@@ -210,57 +364,53 @@ impl BackendServer {
 
     async fn up_msg_handler(_: moon::UpMsgRequest<()>) {}
 
-    async fn events_handle(&self) -> Result<()> {
-        info!("Listening to events");
-        let connection = self.rmq_pool.get().await?;
-
-        let channel = connection.create_channel().await?;
-        let mut consumer = channel.basic_consume("smartauth", "new_citizen_consumer", BasicConsumeOptions::default(), FieldTable::default()).await?;
-
-        while let Some(message) = consumer.next().await {
-            info!("Got an event!");
-            let message: Delivery = message?;
-
-            message
-                .ack(BasicAckOptions::default())
-                .await?;
-            debug!("Message body {:?}", str::from_utf8(&message.data)?);
-
-            let new_citizen_event_id = 1001;
-            let json_data: Value = serde_json::from_slice(&message.data)?;
-            if json_data["event_id"] == 1001 {
-                println!("We got an event");
-                let id = json_data.get("citizen_id");
-                ensure!(id.is_some());
-                let id = id.unwrap().as_i64();
-                ensure!(id.is_some());
-                let db = self.db_pool.get()?;
-
-                let code = insert_new_pending_user(&db, id.unwrap())?;
-                let citizen = Citizen {
-                    citizen_id: id.unwrap() as u64
-                };
-
-                let info = citizen.get_citizen_info().await?;
-                println!("Got citizen information");
-                send_citizen_code(&self.mail_sender.transport, &info, &code).await?;
+    /// Periodically exports a signed checkpoint of the audit trail, if checkpoints are configured
+    async fn audit_checkpoints(&self) -> Result<()> {
+        let (path, key) = match self.info.audit.checkpoints() {
+            Some((path, key)) => (path.to_path_buf(), key.to_string()),
+            None => {
+                info!("Audit checkpoints are disabled");
+                return Ok(());
             }
-        }
-        Ok(())
-    }
-
-    async fn events_listen(&self, poll_interval_secs: u64) -> Result<()> {
-        let mut retry = tokio::time::interval(std::time::Duration::from_secs(poll_interval_secs));
+        };
+        let minutes = self.info.audit.checkpoint_interval_minutes.max(1);
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(minutes * 60));
 
         loop {
-            retry.tick().await;
-            match self.events_handle().await {
-                Ok(_) => debug!("Got a message and handled it without error"),
-                Err(e) => debug!("Got a message but failed with error: {:?}", e)
+            interval.tick().await;
+            let db_pool = self.db_pool.clone();
+            let (path, key) = (path.clone(), key.clone());
+            let checkpoint = tokio::task::spawn_blocking(move || -> Result<_> {
+                let db = db_pool.get()?;
+                Ok(write_checkpoint(&db, &path, &key)?)
+            }).await?;
+
+            match checkpoint {
+                Ok(Some(c)) => info!("Wrote audit checkpoint for event {}", c.event_id),
+                Ok(None) => debug!("No new audit events since the last checkpoint"),
+                Err(e) => error!("Unable to write audit checkpoint: {:?}", e)
             };
         }
     }
 
+    /// Runs blocking database work outside of the async executor
+    async fn blocking_db<T, F>(&self, action: F) -> Result<T>
+        where F: FnOnce(&MysqlConnection) -> Result<T, DatabaseError> + Send + 'static,
+              T: Send + 'static {
+        let db_pool = self.db_pool.clone();
+        tokio::task::spawn_blocking(move || -> Result<T> {
+            let db = db_pool.get()?;
+            Ok(action(&db)?)
+        }).await?
+    }
+
+    /// Walks the audit trail once and compares it against the exported checkpoints
+    pub fn verify_audit_trail(config_path: Option<&str>) -> Result<ChainVerification> {
+        let info = Self::read_config(config_path)?;
+        let db = Self::connect_to_database(&info)?.get()?;
+        Ok(verify_audit_trail(&db, &info.audit)?)
+    }
+
     fn connect_to_database(config: &BackendServerInfo) -> Result<DBPool> {
         let db_url = &config.db.as_ref().either(|l| format!("mysql://{}:{}@{}/{}", l.username, l.password, l.host, "SmartAuth"), |r| r.clone());
         info!("Got a database url: {}", db_url);
@@ -281,13 +431,57 @@ impl BackendServer {
     }
 
     fn create_mail_sender(config: &BackendServerInfo) -> Result<MailServer> {
-        Ok(MailServer {
-            transport: SmtpClient::new_simple(config.mail.host.as_str())?
-                .credentials(Credentials::new(config.mail.username.clone(), config.mail.password.clone())),
+        let mail = &config.mail;
+        let mut memory = None;
+        let mailer: Arc<dyn Mailer> = match mail.transport {
+            MailTransport::Smtp => {
+                let credentials = mail.credentials.as_ref().context("The smtp mail transport needs host, username and password")?;
+                let transport = SmtpClient::new_simple(credentials.host.as_str())?
+                    .credentials(Credentials::new(credentials.username.clone(), credentials.password.clone()));
+                Arc::new(SmtpMailer::new(transport))
+            }
+            MailTransport::File => Arc::new(FileMailer::new(mail.directory.clone())),
+            MailTransport::Log => Arc::new(LogMailer),
+            MailTransport::Memory => {
+                let outbox = Arc::new(MemoryMailer::new());
+                memory = Some(outbox.clone());
+                outbox
+            }
+        };
+        info!("Sending mails using {:?}", mail.transport);
+
+        let templates = MailTemplates::new(&mail.default_locale, &mail.sender, mail.reply_to.as_deref())
+            .global("public_url", &config.public_url)
+            .global("portal_url", &mail.portal_url)
+            .load(&mail.templates)?;
 
-            info: config.mail.clone(),
+        let mut letters = LetterRenderer::new(config.letter.sender.clone(), &mail.portal_url);
+        if let Some(font) = &config.letter.font {
+            letters = letters.font(std::fs::read(font).with_context(|| format!("Failed to read the letter font {}", font.display()))?);
+        }
+
+        Ok(MailServer {
+            mailer,
+            memory,
+            templates: Arc::new(templates),
+            letters: Arc::new(letters),
         })
     }
+    /// Redirects to the own server and the citizen portal are always allowed
+    fn create_redirects(config: &BackendServerInfo) -> Result<Redirects> {
+        let mut origins = config.redirect.allowed_origins.clone();
+        origins.push(config.mail.portal_url.clone());
+        Redirects::new(&config.public_url, &origins).context("Invalid redirect origin")
+    }
+    /// Cookies are only marked `Secure` by default if the server is reached over HTTPS
+    fn create_session_cookies(config: &BackendServerInfo) -> Result<SessionCookies> {
+        let secure = config.session.secure_cookies.unwrap_or_else(|| config.public_url.starts_with("https://"));
+        ensure!(secure || config.session.same_site != CookieSameSite::None, "Cookies with SameSite=None have to be secure");
+        ensure!((1..=MAX_SESSION_LIFETIME_HOURS).contains(&config.session.lifetime_hours), "The session lifetime has to be between 1 and {} hours", MAX_SESSION_LIFETIME_HOURS);
+        let domain = config.session.cookie_domain.clone().filter(|d| !d.trim().is_empty());
+        Ok(SessionCookies::new(domain, secure, config.session.same_site.into()))
+    }
+
     async fn frontend() -> Frontend {
         Frontend::new()
             .title("SmartAuth")
@@ -306,6 +500,349 @@ pub struct ServerCredentials {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MailConfig {
+    /// SMTP server, only needed for the smtp transport
+    #[serde(flatten)]
+    credentials: Option<ServerCredentials>,
+    #[serde(default)]
+    pub(crate) transport: MailTransport,
+    /// Directory the file transport writes `.eml` files to
+    #[serde(default = "default_mail_directory")]
+    pub(crate) directory: PathBuf,
+    #[serde(default = "default_mail_sender")]
+    pub(crate) sender: String,
+    #[serde(default)]
+    pub(crate) reply_to: Option<String>,
+    /// Public address of the citizen portal, available as `{{portal_url}}` in the templates
+    #[serde(default = "default_portal_url")]
+    pub(crate) portal_url: String,
+    /// Directory with one subdirectory of templates per locale
+    #[serde(default = "default_mail_templates")]
+    pub(crate) templates: PathBuf,
+    /// Locale used if a template does not exist in the locale of the recipient
+    #[serde(default = "default_mail_locale")]
+    pub(crate) default_locale: String,
+}
+/// How mails are delivered. Everything except smtp is meant for development and tests
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MailTransport {
+    Smtp,
+    /// Write each mail as `.eml` file into `directory`
+    File,
+    /// Only log the mails
+    Log,
+    /// Keep the mails in memory
+    Memory,
+}
+impl Default for MailTransport {
+    fn default() -> Self {
+        MailTransport::Smtp
+    }
+}
+fn default_mail_directory() -> PathBuf {
+    PathBuf::from("mails")
+}
+fn default_mail_sender() -> String {
+    String::from("support@mail.smartcityproject.net")
+}
+fn default_portal_url() -> String {
+    String::from("http://www.supersmartcity.de:9760")
+}
+fn default_mail_templates() -> PathBuf {
+    PathBuf::from("templates/mail")
+}
+fn default_mail_locale() -> String {
+    String::from("de")
+}
+impl Default for MailConfig {
+    fn default() -> Self {
+        MailConfig {
+            credentials: None,
+            transport: MailTransport::default(),
+            directory: default_mail_directory(),
+            sender: default_mail_sender(),
+            reply_to: None,
+            portal_url: default_portal_url(),
+            templates: default_mail_templates(),
+            default_locale: default_mail_locale(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InvitationConfig {
+    pub(crate) validity_hours: i64,
+    /// Secret to invite the first administrator with, it is only accepted while no employee exists
+    #[serde(default)]
+    pub(crate) bootstrap_token: Option<String>,
+}
+impl Default for InvitationConfig {
+    fn default() -> Self {
+        InvitationConfig {
+            validity_hours: 72,
+            bootstrap_token: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// File the signed checkpoints are appended to
+    pub(crate) checkpoint_file: Option<PathBuf>,
+    /// Key the checkpoints are signed with, checkpoints are disabled without file and key
+    pub(crate) checkpoint_key: Option<String>,
+    pub(crate) checkpoint_interval_minutes: u64,
+    /// Addresses of reverse proxies, only their `X-Forwarded-For` and `Forwarded` headers are used for the client address
+    pub(crate) trusted_proxies: Vec<IpAddr>,
+}
+impl AuditConfig {
+    pub(crate) fn checkpoints(&self) -> Option<(&Path, &str)> {
+        Some((self.checkpoint_file.as_deref()?, self.checkpoint_key.as_deref()?))
+    }
+
+    pub(crate) fn trusts_proxy(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.contains(&ip)
+    }
+}
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            checkpoint_file: None,
+            checkpoint_key: None,
+            checkpoint_interval_minutes: 60,
+            trusted_proxies: vec![],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct EventsConfig {
+    /// Queue the events of the other services are consumed from
+    pub(crate) queue: String,
+    /// Prefix of the queues failed events wait in before they are delivered again, there is one queue per delay,
+    /// e.g. `smartauth.retry.10s`
+    pub(crate) retry_queue: String,
+    /// Queue for events that can not be handled or failed `max_retries` times
+    pub(crate) dead_letter_queue: String,
+    pub(crate) max_retries: u32,
+    /// Delay before the first retry, doubled with every further attempt
+    pub(crate) retry_delay_seconds: u64,
+    pub(crate) max_retry_delay_seconds: u64,
+    /// Number of unacknowledged events delivered at once
+    pub(crate) prefetch: u16,
+    /// Whether the queues and exchanges survive a restart of RabbitMQ
+    pub(crate) durable: bool,
+    /// Exchanges the event queue is bound to, the exchanges are declared if they do not exist
+    pub(crate) bindings: Vec<EventBinding>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EventBinding {
+    pub(crate) exchange: String,
+    #[serde(default = "default_exchange_type")]
+    pub(crate) exchange_type: String,
+    #[serde(default)]
+    pub(crate) routing_key: String,
+}
+fn default_exchange_type() -> String {
+    String::from("topic")
+}
+impl Default for EventsConfig {
+    fn default() -> Self {
+        EventsConfig {
+            queue: String::from("smartauth"),
+            retry_queue: String::from("smartauth.retry"),
+            dead_letter_queue: String::from("smartauth.dead"),
+            max_retries: 5,
+            retry_delay_seconds: 10,
+            max_retry_delay_seconds: 600,
+            prefetch: 10,
+            durable: true,
+            bindings: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PublisherConfig {
+    /// Topic exchange the events of SmartAuth are published to
+    pub(crate) exchange: String,
+    /// How often the outbox is checked for new events
+    pub(crate) poll_interval_seconds: u64,
+    /// Maximum number of events published per check
+    pub(crate) batch_size: i64,
+    /// Delay before publishing a failed event again, doubled with every further attempt
+    pub(crate) retry_delay_seconds: u64,
+    pub(crate) max_retry_delay_seconds: u64,
+}
+impl Default for PublisherConfig {
+    fn default() -> Self {
+        PublisherConfig {
+            exchange: String::from("smartauth.events"),
+            poll_interval_seconds: 5,
+            batch_size: 100,
+            retry_delay_seconds: 10,
+            max_retry_delay_seconds: 600,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LetterConfig {
+    /// Return address printed above the recipient, one line each
+    pub(crate) sender: Vec<String>,
+    /// TrueType font for the letters, without it the builtin Helvetica is used and umlauts can not be printed
+    pub(crate) font: Option<PathBuf>,
+}
+impl Default for LetterConfig {
+    fn default() -> Self {
+        LetterConfig {
+            sender: vec![String::from("Bürgeramt Super Smart City"), String::from("Rathausplatz 1"), String::from("12345 Super Smart City")],
+            font: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RedirectConfig {
+    /// Origins besides `public_url` and `mail.portal_url` that clients may be redirected to after login or registration,
+    /// e.g. `https://service.supersmartcity.de`
+    pub(crate) allowed_origins: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// How long sessions of citizens and employees and their cookies are valid
+    pub(crate) lifetime_hours: i64,
+    /// Domain the session cookies are sent to besides this server, the other services of `supersmartcity.de` by default.
+    /// Empty for cookies that are only sent to this server
+    pub(crate) cookie_domain: Option<String>,
+    /// Only send the session cookies over HTTPS, by default if `public_url` uses HTTPS
+    pub(crate) secure_cookies: Option<bool>,
+    pub(crate) same_site: CookieSameSite,
+}
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            lifetime_hours: 24,
+            cookie_domain: Some(String::from("supersmartcity.de")),
+            secure_cookies: None,
+            same_site: CookieSameSite::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CookieSameSite {
+    Strict,
+    /// Cookies are sent when following links from other sites, needed for the redirects after login
+    Lax,
+    None,
+}
+impl Default for CookieSameSite {
+    fn default() -> Self {
+        CookieSameSite::Lax
+    }
+}
+impl From<CookieSameSite> for SameSite {
+    fn from(same_site: CookieSameSite) -> Self {
+        match same_site {
+            CookieSameSite::Strict => SameSite::Strict,
+            CookieSameSite::Lax => SameSite::Lax,
+            CookieSameSite::None => SameSite::None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MailQueueConfig {
+    /// How often the mail outbox is checked for mails to send
+    pub(crate) poll_interval_seconds: u64,
+    /// Maximum number of mails sent per check
+    pub(crate) batch_size: i64,
+    /// Failed attempts after which a mail is marked as failed and only sent again on request
+    pub(crate) max_attempts: u32,
+    /// Delay before the next attempt, doubled with every further attempt
+    pub(crate) retry_delay_seconds: u64,
+    pub(crate) max_retry_delay_seconds: u64,
+    /// Time a server instance has to send the mails it took from the outbox, afterwards another instance may send them
+    pub(crate) lease_seconds: u64,
+}
+impl Default for MailQueueConfig {
+    fn default() -> Self {
+        MailQueueConfig {
+            poll_interval_seconds: 5,
+            batch_size: 20,
+            max_attempts: 8,
+            retry_delay_seconds: 30,
+            max_retry_delay_seconds: 3600,
+            lease_seconds: 300,
+        }
+    }
+}
+impl MailQueueConfig {
+    /// Delay after the given number of failed attempts, None once a mail failed `max_attempts` times
+    pub fn retry_delay(&self, failed_attempts: u32) -> Option<chrono::Duration> {
+        if failed_attempts >= self.max_attempts {
+            return None;
+        }
+        let seconds = self.retry_delay_seconds
+            .saturating_mul(1 << failed_attempts.saturating_sub(1).min(16))
+            .min(self.max_retry_delay_seconds);
+        Some(chrono::Duration::seconds(seconds as i64))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CitizenDirectoryConfig {
+    /// Address of the citizen service, `{id}` is replaced with the citizen id
+    pub(crate) url: String,
+    pub(crate) timeout_ms: u64,
+    /// Additional attempts after a failed request
+    pub(crate) retries: u32,
+    pub(crate) retry_delay_ms: u64,
+    pub(crate) cache_ttl_seconds: u64,
+    /// How long unknown citizens are remembered
+    pub(crate) negative_cache_ttl_seconds: u64,
+    /// JSON file with citizens to use instead of the citizen service
+    pub(crate) file: Option<PathBuf>,
+    /// Consecutive failures after which the citizen service is left alone for `breaker_open_seconds`
+    pub(crate) breaker_failure_threshold: u32,
+    pub(crate) breaker_open_seconds: u64,
+    /// Let login and verify succeed with outdated or without citizen info while the citizen service is unavailable
+    pub(crate) degraded_responses: bool,
+}
+fn default_citizen_service_url() -> String {
+    String::from("http://www.smartcityproject.net:9710/api/citizen/{id}")
+}
+impl Default for CitizenDirectoryConfig {
+    fn default() -> Self {
+        CitizenDirectoryConfig {
+            url: default_citizen_service_url(),
+            timeout_ms: 5000,
+            retries: 2,
+            retry_delay_ms: 200,
+            cache_ttl_seconds: 300,
+            negative_cache_ttl_seconds: 60,
+            file: None,
+            breaker_failure_threshold: 5,
+            breaker_open_seconds: 30,
+            degraded_responses: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct AuthServerInfo {
     api_version: String,
     server_version: String,
@@ -0,0 +1,247 @@
+use actix_web::{web, HttpResponse, Responder, Route};
+use actix_web::http::Method;
+use moon::actix_files::NamedFile;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::{Schema, SchemaObject};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+use crate::auth::AuditChain::ChainVerification;
+use crate::auth::Problem::{Problem, PROBLEM_CONTENT_TYPE};
+use crate::auth::Endpoints::{audit_events, audit_verify, citizen_code_resend, citizen_lock, citizen_logout, citizen_lookup, citizen_password_reset, citizen_registration_code, citizen_unlock, employee_delete, employee_directory, employee_disable, employee_enable, employee_grant_role, employee_invitation_totp, employee_invite, employee_letter_download, employee_letters, employee_list, employee_login, employee_mail_resend, employee_mails, employee_register, employee_revoke_role, employee_verify, password_reset, user_login, user_register, user_verify};
+use crate::auth::Request::{AuditQueryRequest, AuditQueryResponse, CitizenAccountResponse, CitizenAdministrationRequest, CitizenLookupRequest, EmployeeAdministrationRequest, EmployeeDirectoryEntry, EmployeeInfoRequestResponse, EmployeeInvitationQuery, EmployeeInvitationResponse, EmployeeInviteRequest, EmployeeListEntry, EmployeeListRequest, EmployeeListResponse, EmployeeLoginRequest, EmployeeRegisterRequest, EmployeeRegisterResponse, EmployeeRoleRequest, LetterDownloadRequest, LetterListRequest, LetterListResponse, MailListRequest, MailListResponse, MailResendRequest, PasswordResetRequest, RegistrationResendRequest, TokenValidateRequest, TotpEnrollmentResponse, UserInfoRequestResponse, UserLoginRequest, UserRegistrationRequest};
+use crate::server::AuthServerInfo;
+use crate::server::routes::ping;
+
+/// Prefix of the current version of the API. The same routes without prefix are deprecated aliases
+pub const API_PREFIX: &str = "/api/v1";
+
+const SWAGGER_UI_ASSETS: [&str; 2] = ["swagger-ui.css", "swagger-ui-bundle.js"];
+
+type SchemaFn = fn(&mut SchemaGenerator) -> Schema;
+type ParametersFn = fn(&mut SchemaGenerator) -> SchemaObject;
+
+enum Input {
+    None,
+    /// Form or JSON body
+    Body(SchemaFn),
+    Query(ParametersFn),
+}
+
+enum Output {
+    Empty,
+    Json(SchemaFn),
+    Pdf,
+    Redirect,
+    /// 201 with a JSON body for JSON requests, form posts are redirected
+    CreatedOrRedirect(SchemaFn),
+}
+
+struct ApiRoute {
+    method: Method,
+    path: &'static str,
+    summary: &'static str,
+    route: fn() -> Route,
+    input: Input,
+    output: Output,
+}
+
+fn schema<T: JsonSchema>(gen: &mut SchemaGenerator) -> Schema {
+    gen.subschema_for::<T>()
+}
+
+fn parameters<T: JsonSchema>(gen: &mut SchemaGenerator) -> SchemaObject {
+    T::json_schema(gen).into_object()
+}
+
+fn api() -> Vec<ApiRoute> {
+    vec![
+        ApiRoute { method: Method::GET, path: "/ping", summary: "Version of the server",
+            route: || web::route().to(ping), input: Input::None, output: Output::Json(schema::<AuthServerInfo>) },
+        ApiRoute { method: Method::POST, path: "/login", summary: "Log a citizen in, redirects if `redirect_success` is set",
+            route: || web::route().to(user_login), input: Input::Body(schema::<UserLoginRequest>), output: Output::Json(schema::<UserInfoRequestResponse>) },
+        ApiRoute { method: Method::POST, path: "/verify", summary: "Check the session of a citizen",
+            route: || web::route().to(user_verify), input: Input::Body(schema::<TokenValidateRequest>), output: Output::Json(schema::<UserInfoRequestResponse>) },
+        ApiRoute { method: Method::POST, path: "/register", summary: "Register a citizen with a registration code",
+            route: || web::route().to(user_register), input: Input::Body(schema::<UserRegistrationRequest>), output: Output::Redirect },
+        ApiRoute { method: Method::POST, path: "/register/resend", summary: "Send a new registration code",
+            route: || web::route().to(citizen_code_resend), input: Input::Body(schema::<RegistrationResendRequest>), output: Output::Empty },
+        ApiRoute { method: Method::POST, path: "/password/reset", summary: "Set a new password with a reset token",
+            route: || web::route().to(password_reset), input: Input::Body(schema::<PasswordResetRequest>), output: Output::Redirect },
+        ApiRoute { method: Method::POST, path: "/employee/login", summary: "Log an employee in, redirects if `redirect_success` is set",
+            route: || web::route().to(employee_login), input: Input::Body(schema::<EmployeeLoginRequest>), output: Output::Json(schema::<EmployeeInfoRequestResponse>) },
+        ApiRoute { method: Method::POST, path: "/employee/verify", summary: "Check the session of an employee",
+            route: || web::route().to(employee_verify), input: Input::Body(schema::<TokenValidateRequest>), output: Output::Json(schema::<EmployeeInfoRequestResponse>) },
+        ApiRoute { method: Method::POST, path: "/employee/invite", summary: "Invite a new employee",
+            route: || web::route().to(employee_invite), input: Input::Body(schema::<EmployeeInviteRequest>), output: Output::Json(schema::<EmployeeInvitationResponse>) },
+        ApiRoute { method: Method::GET, path: "/employee/invitation/totp", summary: "TOTP secret for an invitation",
+            route: || web::route().to(employee_invitation_totp), input: Input::Query(parameters::<EmployeeInvitationQuery>), output: Output::Json(schema::<TotpEnrollmentResponse>) },
+        ApiRoute { method: Method::POST, path: "/employee/register", summary: "Redeem an invitation",
+            route: || web::route().to(employee_register), input: Input::Body(schema::<EmployeeRegisterRequest>), output: Output::CreatedOrRedirect(schema::<EmployeeRegisterResponse>) },
+        ApiRoute { method: Method::GET, path: "/employee/directory", summary: "Contact details of all employees",
+            route: || web::route().to(employee_directory), input: Input::Query(parameters::<EmployeeListRequest>), output: Output::Json(schema::<EmployeeListResponse<EmployeeDirectoryEntry>>) },
+        ApiRoute { method: Method::GET, path: "/employee/admin/list", summary: "Employees with roles and status, for admins",
+            route: || web::route().to(employee_list), input: Input::Query(parameters::<EmployeeListRequest>), output: Output::Json(schema::<EmployeeListResponse<EmployeeListEntry>>) },
+        ApiRoute { method: Method::POST, path: "/employee/admin/disable", summary: "Disable an employee",
+            route: || web::route().to(employee_disable), input: Input::Body(schema::<EmployeeAdministrationRequest>), output: Output::Empty },
+        ApiRoute { method: Method::POST, path: "/employee/admin/enable", summary: "Enable a disabled employee",
+            route: || web::route().to(employee_enable), input: Input::Body(schema::<EmployeeAdministrationRequest>), output: Output::Empty },
+        ApiRoute { method: Method::POST, path: "/employee/admin/delete", summary: "Delete an employee",
+            route: || web::route().to(employee_delete), input: Input::Body(schema::<EmployeeAdministrationRequest>), output: Output::Empty },
+        ApiRoute { method: Method::POST, path: "/employee/admin/role/grant", summary: "Grant a role to an employee",
+            route: || web::route().to(employee_grant_role), input: Input::Body(schema::<EmployeeRoleRequest>), output: Output::Empty },
+        ApiRoute { method: Method::POST, path: "/employee/admin/role/revoke", summary: "Revoke a role of an employee",
+            route: || web::route().to(employee_revoke_role), input: Input::Body(schema::<EmployeeRoleRequest>), output: Output::Empty },
+        ApiRoute { method: Method::GET, path: "/employee/audit", summary: "Audit events, as CSV with `format=csv`",
+            route: || web::route().to(audit_events), input: Input::Query(parameters::<AuditQueryRequest>), output: Output::Json(schema::<AuditQueryResponse>) },
+        ApiRoute { method: Method::GET, path: "/employee/audit/verify", summary: "Verify the hash chain of the audit trail",
+            route: || web::route().to(audit_verify), input: Input::None, output: Output::Json(schema::<ChainVerification>) },
+        ApiRoute { method: Method::GET, path: "/employee/mail", summary: "Mails in the outbox",
+            route: || web::route().to(employee_mails), input: Input::Query(parameters::<MailListRequest>), output: Output::Json(schema::<MailListResponse>) },
+        ApiRoute { method: Method::POST, path: "/employee/mail/resend", summary: "Send a failed mail again",
+            route: || web::route().to(employee_mail_resend), input: Input::Body(schema::<MailResendRequest>), output: Output::Empty },
+        ApiRoute { method: Method::GET, path: "/employee/letters", summary: "Registration letters for the print office",
+            route: || web::route().to(employee_letters), input: Input::Query(parameters::<LetterListRequest>), output: Output::Json(schema::<LetterListResponse>) },
+        ApiRoute { method: Method::GET, path: "/employee/letters/download", summary: "PDF of a registration letter",
+            route: || web::route().to(employee_letter_download), input: Input::Query(parameters::<LetterDownloadRequest>), output: Output::Pdf },
+        ApiRoute { method: Method::GET, path: "/employee/citizen", summary: "Account of a citizen",
+            route: || web::route().to(citizen_lookup), input: Input::Query(parameters::<CitizenLookupRequest>), output: Output::Json(schema::<CitizenAccountResponse>) },
+        ApiRoute { method: Method::POST, path: "/employee/citizen/logout", summary: "End all sessions of a citizen",
+            route: || web::route().to(citizen_logout), input: Input::Body(schema::<CitizenAdministrationRequest>), output: Output::Empty },
+        ApiRoute { method: Method::POST, path: "/employee/citizen/lock", summary: "Lock the account of a citizen",
+            route: || web::route().to(citizen_lock), input: Input::Body(schema::<CitizenAdministrationRequest>), output: Output::Empty },
+        ApiRoute { method: Method::POST, path: "/employee/citizen/unlock", summary: "Unlock the account of a citizen",
+            route: || web::route().to(citizen_unlock), input: Input::Body(schema::<CitizenAdministrationRequest>), output: Output::Empty },
+        ApiRoute { method: Method::POST, path: "/employee/citizen/password-reset", summary: "Mail a password reset link to a citizen",
+            route: || web::route().to(citizen_password_reset), input: Input::Body(schema::<CitizenAdministrationRequest>), output: Output::Empty },
+        ApiRoute { method: Method::POST, path: "/employee/citizen/registration-code", summary: "Issue a new registration code",
+            route: || web::route().to(citizen_registration_code), input: Input::Body(schema::<CitizenAdministrationRequest>), output: Output::Empty },
+    ]
+}
+
+/// Registers the JSON API, used for [API_PREFIX] and the deprecated aliases
+pub(crate) fn api_routes(cfg: &mut web::ServiceConfig) {
+    for route in api() {
+        cfg.route(route.path, (route.route)().method(route.method));
+    }
+}
+
+/// Path of the route under [API_PREFIX], if `path` is a deprecated alias of it
+pub(crate) fn successor_path(method: &Method, path: &str) -> Option<String> {
+    api()
+        .iter()
+        .any(|route| &route.method == method && route.path == path)
+        .then(|| format!("{}{}", API_PREFIX, path))
+}
+
+pub(crate) async fn openapi() -> impl Responder {
+    HttpResponse::Ok().json(openapi_document())
+}
+
+pub(crate) async fn swagger_ui() -> impl Responder {
+    NamedFile::open(std::path::PathBuf::from(r"static_content/swagger_ui.html")).unwrap()
+}
+
+/// Files of the Swagger UI, served from `static_content/swagger-ui` instead of a CDN
+pub(crate) async fn swagger_ui_asset(file: web::Path<String>) -> actix_web::Result<NamedFile> {
+    let file = file.into_inner();
+    if !SWAGGER_UI_ASSETS.contains(&file.as_str()) {
+        return Err(actix_web::error::ErrorNotFound("Unknown Swagger UI file"));
+    }
+    Ok(NamedFile::open(std::path::PathBuf::from(r"static_content/swagger-ui").join(file))?)
+}
+
+/// OpenAPI 3 document of the API, generated from the request and response types
+pub fn openapi_document() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let problem = schema::<Problem>(&mut gen);
+    let mut paths = Map::new();
+
+    for route in api() {
+        let mut operation = json!({
+            "summary": route.summary,
+            "responses": {
+                "default": {
+                    "description": "Error",
+                    "content": { (PROBLEM_CONTENT_TYPE): { "schema": problem } }
+                }
+            }
+        });
+
+        match route.input {
+            Input::None => {}
+            Input::Body(body) => {
+                let body = body(&mut gen);
+                operation["requestBody"] = json!({
+                    "required": true,
+                    "content": {
+                        "application/json": { "schema": body },
+                        "application/x-www-form-urlencoded": { "schema": body }
+                    }
+                });
+            }
+            Input::Query(query) => {
+                operation["parameters"] = query_parameters(&query(&mut gen));
+            }
+        }
+
+        let status = response_status(&route.output);
+        let response = match route.output {
+            Output::Empty => json!({ "description": "OK" }),
+            Output::Json(response) => json!({
+                "description": "OK",
+                "content": { "application/json": { "schema": response(&mut gen) } }
+            }),
+            Output::Pdf => json!({
+                "description": "OK",
+                "content": { "application/pdf": { "schema": { "type": "string", "format": "binary" } } }
+            }),
+            Output::Redirect => json!({ "description": "Redirect to the next page" }),
+            Output::CreatedOrRedirect(response) => {
+                operation["responses"]["302"] = json!({ "description": "Redirect to the next page, for form posts" });
+                json!({
+                    "description": "Created, for JSON requests",
+                    "content": { "application/json": { "schema": response(&mut gen) } }
+                })
+            }
+        };
+        operation["responses"][status] = response;
+
+        paths.entry(route.path)
+            .or_insert_with(|| json!({}))[route.method.as_str().to_lowercase()] = operation;
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "SmartAuth",
+            "version": env!("CARGO_PKG_VERSION")
+        },
+        "servers": [{ "url": API_PREFIX }],
+        "paths": paths,
+        "components": { "schemas": gen.take_definitions() }
+    })
+}
+
+fn response_status(output: &Output) -> &'static str {
+    match output {
+        Output::Redirect => "302",
+        Output::CreatedOrRedirect(_) => "201",
+        _ => "200"
+    }
+}
+
+fn query_parameters(query: &SchemaObject) -> Value {
+    let object = match &query.object {
+        Some(object) => object,
+        None => return json!([])
+    };
+    object.properties
+        .iter()
+        .map(|(name, schema)| json!({
+            "name": name,
+            "in": "query",
+            "required": object.required.contains(name),
+            "schema": schema
+        }))
+        .collect()
+}
@@ -0,0 +1,320 @@
+use std::str;
+use std::sync::Arc;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use lapin::message::Delivery;
+use lapin::options::{BasicAckOptions, BasicConsumeOptions, BasicPublishOptions, BasicQosOptions, ConfirmSelectOptions, ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions};
+use lapin::types::{AMQPValue, FieldTable, LongString, ShortString};
+use lapin::{BasicProperties, Channel, ExchangeKind};
+use log::{debug, error, info, warn};
+use moon::futures::StreamExt;
+use diesel::MysqlConnection;
+use crate::auth::Actions::{deactivate_citizen, delete_citizen, issue_citizen_code, rebind_pending_user};
+use crate::auth::Citizen::{Citizen, IsCitizen};
+use crate::auth::CitizenDirectory::CitizenDirectory;
+use crate::auth::Errors::EventHandlingError;
+use crate::events::{CitizenDeceasedEvent, CitizenDeletedEvent, CitizenMovedOutEvent, CitizenUpdatedEvent, EventDispatcher, EventHandler, IntegrationEvent, NewCitizenEvent};
+use crate::auth::Letter::LetterRenderer;
+use crate::auth::MailTemplate::MailTemplates;
+use crate::auth::RegistrationCode::RegistrationCodeFormat;
+use crate::server::{BackendServer, DBPool, EventsConfig};
+
+/// Number of failed deliveries of a message so far
+const ATTEMPT_HEADER: &str = "x-smartauth-attempt";
+/// Reason a message was dead-lettered
+const ERROR_HEADER: &str = "x-smartauth-error";
+
+fn header_value(delivery: &Delivery, name: &str) -> Option<AMQPValue> {
+    delivery.properties
+        .headers()
+        .as_ref()?
+        .inner()
+        .iter()
+        .find(|(k, _)| k.as_str() == name)
+        .map(|(_, v)| v.clone())
+}
+
+fn delivery_attempt(delivery: &Delivery) -> u32 {
+    match header_value(delivery, ATTEMPT_HEADER) {
+        Some(AMQPValue::LongUInt(n)) => n,
+        Some(AMQPValue::LongInt(n)) => n.max(0) as u32,
+        Some(AMQPValue::LongLongInt(n)) => n.clamp(0, u32::MAX as i64) as u32,
+        _ => 0
+    }
+}
+
+/// Delay before the given retry, doubled with every attempt up to `max_retry_delay_seconds`
+fn retry_delay(config: &EventsConfig, attempt: u32) -> u64 {
+    config.retry_delay_seconds
+        .saturating_mul(1 << attempt.min(16))
+        .min(config.max_retry_delay_seconds)
+}
+
+/// Every delay has its own retry queue with a fixed TTL, a message with a long delay would otherwise hold back
+/// the messages behind it, because RabbitMQ only expires messages at the head of a queue
+fn retry_queue(config: &EventsConfig, delay: u64) -> String {
+    format!("{}.{}s", config.retry_queue, delay)
+}
+
+fn with_header(properties: &BasicProperties, name: &str, value: AMQPValue) -> BasicProperties {
+    let mut headers = properties.headers().clone().unwrap_or_default();
+    headers.insert(ShortString::from(name), value);
+    properties.clone()
+        .with_headers(headers)
+        .with_delivery_mode(2)
+}
+
+impl BackendServer {
+    /// Declares the event queue with its bindings as well as the queues used for delayed retries and for messages
+    /// that could not be handled. Messages in a retry queue return to the event queue once its TTL has passed
+    async fn declare_event_queues(&self, channel: &Channel) -> Result<()> {
+        let config = &self.info.events;
+        let durable = QueueDeclareOptions { durable: config.durable, ..QueueDeclareOptions::default() };
+
+        channel.queue_declare(&config.queue, durable, FieldTable::default()).await?;
+        for binding in &config.bindings {
+            let exchange_options = ExchangeDeclareOptions { durable: config.durable, ..ExchangeDeclareOptions::default() };
+            channel.exchange_declare(&binding.exchange, ExchangeKind::Custom(binding.exchange_type.clone()), exchange_options, FieldTable::default()).await?;
+            channel.queue_bind(&config.queue, &binding.exchange, &binding.routing_key, QueueBindOptions::default(), FieldTable::default()).await?;
+        }
+
+        let mut delays: Vec<u64> = (0..config.max_retries).map(|attempt| retry_delay(config, attempt)).collect();
+        delays.dedup();
+        for delay in delays {
+            let mut retry_arguments = FieldTable::default();
+            retry_arguments.insert("x-dead-letter-exchange".into(), AMQPValue::LongString(LongString::from("")));
+            retry_arguments.insert("x-dead-letter-routing-key".into(), AMQPValue::LongString(LongString::from(config.queue.as_str())));
+            retry_arguments.insert("x-message-ttl".into(), AMQPValue::LongLongInt(delay.saturating_mul(1000).min(i64::MAX as u64) as i64));
+            channel.queue_declare(&retry_queue(config, delay), durable, retry_arguments).await?;
+        }
+        channel.queue_declare(&config.dead_letter_queue, durable, FieldTable::default()).await?;
+        Ok(())
+    }
+
+    pub(crate) async fn events_handle(&self) -> Result<()> {
+        info!("Listening to events");
+        let config = &self.info.events;
+        let connection = self.rmq_pool.get().await?;
+
+        let dispatcher = self.event_dispatcher();
+        let channel = connection.create_channel().await?;
+        channel.confirm_select(ConfirmSelectOptions::default()).await?;
+        self.declare_event_queues(&channel).await?;
+        channel.basic_qos(config.prefetch, BasicQosOptions::default()).await?;
+        let mut consumer = channel.basic_consume(&config.queue, "new_citizen_consumer", BasicConsumeOptions::default(), FieldTable::default()).await?;
+
+        while let Some(message) = consumer.next().await {
+            let message: Delivery = message?;
+            debug!("Got an event: {:?}", str::from_utf8(&message.data));
+
+            let result = dispatcher.dispatch(&message.data).await;
+            if let Ok(IntegrationEvent::Unknown { event_id }) = &result {
+                debug!("Ignoring event {}", event_id);
+            }
+            self.settle_event(&channel, &message, result.map(|_| ())).await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn events_listen(&self, poll_interval_secs: u64) -> Result<()> {
+        let mut retry = tokio::time::interval(std::time::Duration::from_secs(poll_interval_secs));
+
+        loop {
+            retry.tick().await;
+            match self.events_handle().await {
+                Ok(_) => debug!("Event consumer stopped"),
+                Err(e) => error!("Event consumer failed, reconnecting: {:?}", e)
+            };
+        }
+    }
+
+    /// Acknowledges a handled message. Failed messages are acknowledged as well, after RabbitMQ confirmed a copy
+    /// in a retry queue or, if it can not be handled or has failed too often, in the dead letter queue.
+    /// Without the confirm the message is not acknowledged and delivered again once the channel is closed
+    async fn settle_event(&self, channel: &Channel, delivery: &Delivery, result: Result<(), EventHandlingError>) -> Result<()> {
+        let config = &self.info.events;
+        let attempt = delivery_attempt(delivery);
+
+        match result {
+            Ok(()) => {}
+            Err(EventHandlingError::Transient(e)) if attempt < config.max_retries => {
+                let delay = retry_delay(config, attempt);
+                warn!("Handling event failed (attempt {}), retrying in {}s: {:?}", attempt + 1, delay, e);
+
+                let properties = with_header(&delivery.properties, ATTEMPT_HEADER, AMQPValue::LongUInt(attempt + 1));
+                let confirmation = channel.basic_publish("", &retry_queue(config, delay), BasicPublishOptions::default(), &delivery.data, properties)
+                    .await?
+                    .await?;
+                if !confirmation.is_ack() {
+                    return Err(anyhow!("Retry of the event was not confirmed"));
+                }
+            }
+            Err(e) => {
+                error!("Moving event to {} after {} attempts: {:?}", config.dead_letter_queue, attempt + 1, e);
+
+                let properties = with_header(&delivery.properties, ERROR_HEADER, AMQPValue::LongString(LongString::from(e.to_string())));
+                let confirmation = channel.basic_publish("", &config.dead_letter_queue, BasicPublishOptions::default(), &delivery.data, properties)
+                    .await?
+                    .await?;
+                if !confirmation.is_ack() {
+                    return Err(anyhow!("Dead-lettering of the event was not confirmed"));
+                }
+            }
+        }
+
+        delivery.ack(BasicAckOptions::default()).await?;
+        Ok(())
+    }
+
+    pub(crate) fn event_dispatcher(&self) -> EventDispatcher {
+        let deactivation = CitizenDeactivationHandler {
+            db_pool: self.db_pool.clone(),
+            citizen_directory: self.citizen_directory.clone(),
+        };
+
+        EventDispatcher::new()
+            .register(NewCitizenEvent::EVENT_ID, NewCitizenHandler {
+                db_pool: self.db_pool.clone(),
+                mail_templates: self.mail_sender.templates.clone(),
+                letters: self.mail_sender.letters.clone(),
+                code_format: self.info.registration_code.clone(),
+                citizen_directory: self.citizen_directory.clone(),
+            })
+            .register(CitizenUpdatedEvent::EVENT_ID, CitizenUpdateHandler {
+                db_pool: self.db_pool.clone(),
+                mail_templates: self.mail_sender.templates.clone(),
+                letters: self.mail_sender.letters.clone(),
+                code_format: self.info.registration_code.clone(),
+                citizen_directory: self.citizen_directory.clone(),
+            })
+            .register(CitizenDeceasedEvent::EVENT_ID, deactivation.clone())
+            .register(CitizenMovedOutEvent::EVENT_ID, deactivation)
+            .register(CitizenDeletedEvent::EVENT_ID, CitizenDeletionHandler {
+                db_pool: self.db_pool.clone(),
+                citizen_directory: self.citizen_directory.clone(),
+            })
+    }
+}
+
+/// Runs blocking database work outside of the async executor
+async fn with_db<T, E, F>(db_pool: &DBPool, action: F) -> Result<T, EventHandlingError>
+    where F: FnOnce(&MysqlConnection) -> Result<T, E> + Send + 'static,
+          E: Into<EventHandlingError>,
+          T: Send + 'static {
+    let db_pool = db_pool.clone();
+    tokio::task::spawn_blocking(move || -> Result<T, EventHandlingError> {
+        let db = db_pool.get().map_err(|e| anyhow!(e))?;
+        action(&db).map_err(Into::into)
+    }).await.map_err(|e| anyhow!(e))?
+}
+
+/// Queues a mail, or a letter if no email address is known, with the registration code for a new citizen.
+/// Duplicate deliveries of the event keep the existing code and queue nothing
+struct NewCitizenHandler {
+    db_pool: DBPool,
+    mail_templates: Arc<MailTemplates>,
+    letters: Arc<LetterRenderer>,
+    code_format: RegistrationCodeFormat,
+    citizen_directory: Arc<dyn CitizenDirectory>,
+}
+
+#[async_trait]
+impl EventHandler for NewCitizenHandler {
+    async fn handle(&self, event: &IntegrationEvent) -> Result<(), EventHandlingError> {
+        let citizen_id = match event {
+            IntegrationEvent::NewCitizen(e) => e.citizen_id,
+            _ => return Ok(())
+        };
+
+        let info = Citizen { citizen_id }
+            .get_citizen_info(self.citizen_directory.as_ref())
+            .await?;
+
+        let format = self.code_format.clone();
+        let templates = self.mail_templates.clone();
+        let letters = self.letters.clone();
+        let code = with_db(&self.db_pool, move |db| issue_citizen_code(db, &format, &templates, &letters, &info)).await?;
+        if code.is_none() {
+            info!("Citizen {} is already registered or has a valid code, ignoring the event", citizen_id);
+        }
+        Ok(())
+    }
+}
+
+/// Drops the cached info of a citizen whose data changed. An open registration code that was sent to an
+/// address the citizen no longer has is replaced by a new one for the new address
+struct CitizenUpdateHandler {
+    db_pool: DBPool,
+    mail_templates: Arc<MailTemplates>,
+    letters: Arc<LetterRenderer>,
+    code_format: RegistrationCodeFormat,
+    citizen_directory: Arc<dyn CitizenDirectory>,
+}
+
+#[async_trait]
+impl EventHandler for CitizenUpdateHandler {
+    async fn handle(&self, event: &IntegrationEvent) -> Result<(), EventHandlingError> {
+        let citizen_id = match event {
+            IntegrationEvent::CitizenUpdated(e) => e.citizen_id,
+            _ => return Ok(())
+        };
+
+        self.citizen_directory.invalidate(citizen_id);
+        let info = Citizen { citizen_id }
+            .get_citizen_info(self.citizen_directory.as_ref())
+            .await?;
+
+        let format = self.code_format.clone();
+        let templates = self.mail_templates.clone();
+        let letters = self.letters.clone();
+        let code = with_db(&self.db_pool, move |db| rebind_pending_user(db, &format, &templates, &letters, &info)).await?;
+        if code.is_some() {
+            info!("Sent a new registration code to the changed address of citizen {}", citizen_id);
+        }
+        Ok(())
+    }
+}
+
+/// Locks the account of a citizen who died or moved away, revokes the sessions and discards open registration codes
+#[derive(Clone)]
+struct CitizenDeactivationHandler {
+    db_pool: DBPool,
+    citizen_directory: Arc<dyn CitizenDirectory>,
+}
+
+#[async_trait]
+impl EventHandler for CitizenDeactivationHandler {
+    async fn handle(&self, event: &IntegrationEvent) -> Result<(), EventHandlingError> {
+        let (citizen_id, reason) = match event {
+            IntegrationEvent::CitizenDeceased(e) => (e.citizen_id, "deceased"),
+            IntegrationEvent::CitizenMovedOut(e) => (e.citizen_id, "moved out"),
+            _ => return Ok(())
+        };
+
+        with_db(&self.db_pool, move |db| deactivate_citizen(db, citizen_id, reason)).await?;
+        self.citizen_directory.invalidate(citizen_id);
+        info!("Deactivated citizen {} ({})", citizen_id, reason);
+        Ok(())
+    }
+}
+
+/// Deletes the account and everything belonging to it when a citizen is removed from the registry
+struct CitizenDeletionHandler {
+    db_pool: DBPool,
+    citizen_directory: Arc<dyn CitizenDirectory>,
+}
+
+#[async_trait]
+impl EventHandler for CitizenDeletionHandler {
+    async fn handle(&self, event: &IntegrationEvent) -> Result<(), EventHandlingError> {
+        let citizen_id = match event {
+            IntegrationEvent::CitizenDeleted(e) => e.citizen_id,
+            _ => return Ok(())
+        };
+
+        with_db(&self.db_pool, move |db| delete_citizen(db, citizen_id)).await?;
+        self.citizen_directory.invalidate(citizen_id);
+        info!("Deleted citizen {}", citizen_id);
+        Ok(())
+    }
+}
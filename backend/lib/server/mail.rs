@@ -0,0 +1,55 @@
+use std::time::Duration;
+use anyhow::Result;
+use log::{debug, error, info, warn};
+use moon::chrono;
+use crate::auth::MailOutbox::{claim_due_mails, purge_expired_mails, record_attempt, MailStatus};
+use crate::server::BackendServer;
+
+impl BackendServer {
+    /// Periodically sends the mails waiting in the mail outbox
+    pub(crate) async fn mail_queue(&self) -> Result<()> {
+        let config = &self.info.mail_queue;
+        let mut interval = tokio::time::interval(Duration::from_secs(config.poll_interval_seconds.max(1)));
+
+        loop {
+            interval.tick().await;
+            match self.blocking_db(purge_expired_mails).await {
+                Ok(0) => {}
+                Ok(n) => info!("Removed the content of {} expired mails", n),
+                Err(e) => error!("Purging expired mails failed: {:?}", e)
+            };
+            match self.send_queued_mails().await {
+                Ok(0) => {}
+                Ok(n) => debug!("Sent {} mails", n),
+                Err(e) => error!("Sending queued mails failed: {:?}", e)
+            };
+        }
+    }
+
+    /// Sends one batch of due mails and returns the number of sent mails
+    async fn send_queued_mails(&self) -> Result<usize> {
+        let config = self.info.mail_queue.clone();
+        let batch_size = config.batch_size;
+        let lease = chrono::Duration::seconds(config.lease_seconds as i64);
+        let mails = self.blocking_db(move |db| claim_due_mails(db, batch_size, lease)).await?;
+
+        let mut sent = 0;
+        for queued in mails {
+            let mailer = self.mail_sender.mailer.clone();
+            let config = config.clone();
+            let (queued, update) = tokio::task::spawn_blocking(move || {
+                let update = queued.attempt(mailer.as_ref(), &config);
+                (queued, update)
+            }).await?;
+
+            match update.status.parse() {
+                Ok(MailStatus::Sent) => sent += 1,
+                Ok(MailStatus::Pending) => warn!("Sending mail {} failed (attempt {}), retrying at {}: {}", queued.id, update.attempts, update.next_attempt, update.last_error.as_deref().unwrap_or_default()),
+                _ => error!("Sending mail {} failed after {} attempts, giving up: {}", queued.id, update.attempts, update.last_error.as_deref().unwrap_or_default())
+            };
+            let id = queued.id;
+            self.blocking_db(move |db| record_attempt(db, id, &update)).await?;
+        }
+        Ok(sent)
+    }
+}
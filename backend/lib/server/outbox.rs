@@ -0,0 +1,81 @@
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use lapin::options::{BasicPublishOptions, ConfirmSelectOptions, ExchangeDeclareOptions};
+use lapin::types::{FieldTable, ShortString};
+use lapin::{BasicProperties, ExchangeKind};
+use log::{debug, error, warn};
+use moon::chrono;
+use crate::auth::Outbox::{load_due_events, mark_failed, mark_published, OutboxEvent};
+use crate::server::BackendServer;
+
+impl BackendServer {
+    /// Periodically publishes the events stored in the outbox. Events stay in the outbox until
+    /// RabbitMQ confirmed them, so they may be published more than once but are never lost
+    pub(crate) async fn outbox_relay(&self) -> Result<()> {
+        let config = &self.info.publisher;
+        let mut interval = tokio::time::interval(Duration::from_secs(config.poll_interval_seconds.max(1)));
+
+        loop {
+            interval.tick().await;
+            match self.publish_outbox().await {
+                Ok(0) => {}
+                Ok(n) => debug!("Published {} events", n),
+                Err(e) => error!("Publishing events failed: {:?}", e)
+            };
+        }
+    }
+
+    /// Publishes one batch of due events and returns the number of published events
+    async fn publish_outbox(&self) -> Result<usize> {
+        let config = &self.info.publisher;
+        let batch_size = config.batch_size;
+        let events = self.blocking_db(move |db| load_due_events(db, batch_size)).await?;
+        if events.is_empty() {
+            return Ok(0);
+        }
+
+        let connection = self.rmq_pool.get().await?;
+        let channel = connection.create_channel().await?;
+        channel.confirm_select(ConfirmSelectOptions::default()).await?;
+        let exchange_options = ExchangeDeclareOptions { durable: true, ..ExchangeDeclareOptions::default() };
+        channel.exchange_declare(&config.exchange, ExchangeKind::Topic, exchange_options, FieldTable::default()).await?;
+
+        let mut published = 0;
+        for event in events {
+            let properties = BasicProperties::default()
+                .with_content_type(ShortString::from("application/json"))
+                .with_message_id(ShortString::from(format!("smartauth-outbox-{}", event.id)))
+                .with_timestamp(event.created.timestamp().max(0) as u64)
+                .with_delivery_mode(2);
+
+            let confirmation = match channel.basic_publish(&config.exchange, &event.routing_key, BasicPublishOptions::default(), event.payload.as_bytes(), properties).await {
+                Ok(confirm) => confirm.await.map_err(|e| anyhow!(e)),
+                Err(e) => Err(anyhow!(e))
+            };
+
+            match confirmation {
+                Ok(c) if c.is_ack() => {
+                    let id = event.id;
+                    self.blocking_db(move |db| mark_published(db, id)).await?;
+                    published += 1;
+                }
+                result => {
+                    let delay = self.outbox_retry_delay(&event);
+                    warn!("Publishing event {} failed (attempt {}), retrying in {}s: {:?}", event.id, event.attempts + 1, delay.num_seconds(), result);
+                    self.blocking_db(move |db| mark_failed(db, &event, delay)).await?;
+                    //The channel is most likely unusable, the remaining events are published with the next batch
+                    break;
+                }
+            }
+        }
+        Ok(published)
+    }
+
+    fn outbox_retry_delay(&self, event: &OutboxEvent) -> chrono::Duration {
+        let config = &self.info.publisher;
+        let seconds = config.retry_delay_seconds
+            .saturating_mul(1 << event.attempts.min(16))
+            .min(config.max_retry_delay_seconds);
+        chrono::Duration::seconds(seconds as i64)
+    }
+}
@@ -0,0 +1 @@
+DROP TABLE EmployeeInvitations;
\ No newline at end of file
@@ -0,0 +1,12 @@
+CREATE TABLE EmployeeInvitations (
+    id SERIAL PRIMARY KEY,
+    info_id BIGINT UNSIGNED NOT NULL,
+    token VARCHAR(255) NOT NULL UNIQUE,
+    email VARCHAR(255) NOT NULL,
+    invited_by BIGINT UNSIGNED,
+    expires DATETIME NOT NULL,
+
+    FOREIGN KEY (info_id)
+                            REFERENCES EmployeeInfo(id)
+                            ON DELETE CASCADE
+);
@@ -0,0 +1 @@
+ALTER TABLE EmployeeLogins DROP COLUMN totp_secret;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE EmployeeLogins
+    ADD COLUMN totp_secret VARCHAR(255) NULL;
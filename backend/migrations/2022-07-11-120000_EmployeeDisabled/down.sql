@@ -0,0 +1 @@
+ALTER TABLE EmployeeLogins DROP COLUMN disabled;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE EmployeeLogins
+    ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT FALSE;
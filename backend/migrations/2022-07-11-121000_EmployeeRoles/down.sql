@@ -0,0 +1 @@
+DROP TABLE EmployeeRoles;
\ No newline at end of file
@@ -0,0 +1,10 @@
+CREATE TABLE EmployeeRoles (
+    id SERIAL PRIMARY KEY,
+    e_id BIGINT UNSIGNED NOT NULL,
+    role VARCHAR(64) NOT NULL,
+
+    UNIQUE (e_id, role),
+    FOREIGN KEY (e_id)
+                            REFERENCES EmployeeLogins(id)
+                            ON DELETE CASCADE
+);
@@ -0,0 +1,4 @@
+ALTER TABLE EmployeeInfo
+    DROP COLUMN department,
+    DROP COLUMN email,
+    DROP COLUMN phone;
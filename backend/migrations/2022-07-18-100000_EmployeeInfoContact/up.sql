@@ -0,0 +1,4 @@
+ALTER TABLE EmployeeInfo
+    ADD COLUMN department VARCHAR(255) NULL,
+    ADD COLUMN email VARCHAR(255) NULL,
+    ADD COLUMN phone VARCHAR(255) NULL;
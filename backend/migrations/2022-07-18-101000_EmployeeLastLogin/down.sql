@@ -0,0 +1 @@
+ALTER TABLE EmployeeLogins DROP COLUMN last_login;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE EmployeeLogins
+    ADD COLUMN last_login DATETIME NULL;
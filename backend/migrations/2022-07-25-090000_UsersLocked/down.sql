@@ -0,0 +1 @@
+ALTER TABLE Users DROP COLUMN locked;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE Users
+    ADD COLUMN locked BOOLEAN NOT NULL DEFAULT FALSE;
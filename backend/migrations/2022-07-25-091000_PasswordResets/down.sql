@@ -0,0 +1 @@
+DROP TABLE PasswordResets;
\ No newline at end of file
@@ -0,0 +1,10 @@
+CREATE TABLE PasswordResets (
+    id SERIAL PRIMARY KEY,
+    user_id BIGINT UNSIGNED NOT NULL,
+    token VARCHAR(255) NOT NULL UNIQUE,
+    expires DATETIME NOT NULL,
+
+    FOREIGN KEY (user_id)
+                      REFERENCES Users(id)
+                      ON DELETE CASCADE
+);
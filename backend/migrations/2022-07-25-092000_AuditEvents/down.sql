@@ -0,0 +1 @@
+DROP TABLE AuditEvents;
\ No newline at end of file
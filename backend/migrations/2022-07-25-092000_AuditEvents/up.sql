@@ -0,0 +1,11 @@
+CREATE TABLE AuditEvents (
+    id SERIAL PRIMARY KEY,
+    created DATETIME NOT NULL,
+    actor_type VARCHAR(32) NOT NULL,
+    actor_id BIGINT UNSIGNED NULL,
+    subject_type VARCHAR(32) NULL,
+    subject_id BIGINT UNSIGNED NULL,
+    event_type VARCHAR(64) NOT NULL,
+    outcome VARCHAR(32) NOT NULL,
+    details TEXT NULL
+);
@@ -0,0 +1,4 @@
+DROP INDEX AuditEventsCreated ON AuditEvents;
+ALTER TABLE AuditEvents
+    DROP COLUMN ip,
+    DROP COLUMN user_agent;
@@ -0,0 +1,4 @@
+ALTER TABLE AuditEvents
+    ADD COLUMN ip VARCHAR(64) NULL,
+    ADD COLUMN user_agent VARCHAR(512) NULL;
+CREATE INDEX AuditEventsCreated ON AuditEvents (created);
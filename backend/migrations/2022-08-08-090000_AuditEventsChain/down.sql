@@ -0,0 +1,3 @@
+ALTER TABLE AuditEvents
+    DROP COLUMN prev_hash,
+    DROP COLUMN hash;
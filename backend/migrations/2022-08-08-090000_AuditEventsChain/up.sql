@@ -0,0 +1,3 @@
+ALTER TABLE AuditEvents
+    ADD COLUMN prev_hash VARCHAR(64) NULL,
+    ADD COLUMN hash VARCHAR(64) NULL;
@@ -0,0 +1,9 @@
+CREATE TABLE OutboxEvents (
+    id SERIAL PRIMARY KEY,
+    created DATETIME NOT NULL,
+    routing_key VARCHAR(255) NOT NULL,
+    payload TEXT NOT NULL,
+    attempts INT UNSIGNED NOT NULL DEFAULT 0,
+    next_attempt DATETIME NOT NULL
+);
+CREATE INDEX OutboxEventsNextAttempt ON OutboxEvents (next_attempt);
@@ -0,0 +1,14 @@
+CREATE TABLE MailOutbox (
+    id SERIAL PRIMARY KEY,
+    created DATETIME NOT NULL,
+    recipient VARCHAR(255) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    -- Removed once the mail was sent, it contains codes and links
+    mail TEXT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    attempts INT UNSIGNED NOT NULL DEFAULT 0,
+    last_error TEXT NULL,
+    next_attempt DATETIME NOT NULL,
+    sent DATETIME NULL
+);
+CREATE INDEX MailOutboxStatus ON MailOutbox (status, next_attempt);
@@ -0,0 +1,5 @@
+ALTER TABLE PendingUsers
+    DROP INDEX PendingUsersEmail,
+    DROP COLUMN email,
+    DROP COLUMN issued,
+    DROP COLUMN expires;
@@ -0,0 +1,9 @@
+ALTER TABLE PendingUsers
+    ADD COLUMN email VARCHAR(255) NULL,
+    ADD COLUMN issued DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ADD COLUMN expires DATETIME NULL,
+    ADD INDEX PendingUsersEmail (email);
+
+UPDATE PendingUsers SET expires = issued + INTERVAL 30 DAY;
+
+ALTER TABLE PendingUsers MODIFY expires DATETIME NOT NULL;
@@ -0,0 +1,9 @@
+CREATE TABLE RegistrationLetters (
+    id SERIAL PRIMARY KEY,
+    citizen_id BIGINT UNSIGNED NOT NULL,
+    created DATETIME NOT NULL,
+    pdf MEDIUMBLOB NOT NULL,
+    downloaded DATETIME NULL,
+
+    INDEX RegistrationLettersCitizen (citizen_id)
+);
@@ -0,0 +1,5 @@
+-- Single row that is locked while an event is appended, so the audit chain can not fork even while AuditEvents is empty
+CREATE TABLE AuditChainLock (
+    id TINYINT UNSIGNED PRIMARY KEY
+);
+INSERT INTO AuditChainLock (id) VALUES (1);
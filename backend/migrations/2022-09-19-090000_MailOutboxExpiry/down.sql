@@ -0,0 +1,2 @@
+ALTER TABLE MailOutbox
+    DROP COLUMN expires;
@@ -0,0 +1,8 @@
+-- Mails are only sent while the code or link in them is valid, afterwards their content is removed
+ALTER TABLE MailOutbox
+    ADD COLUMN expires DATETIME NULL;
+
+UPDATE MailOutbox SET expires = created + INTERVAL 1 DAY;
+
+ALTER TABLE MailOutbox MODIFY expires DATETIME NOT NULL;
+UPDATE MailOutbox SET mail = NULL WHERE expires <= CURRENT_TIMESTAMP;
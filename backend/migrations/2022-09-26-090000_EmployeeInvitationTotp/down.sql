@@ -0,0 +1,2 @@
+ALTER TABLE EmployeeInvitations
+    DROP COLUMN totp_secret;
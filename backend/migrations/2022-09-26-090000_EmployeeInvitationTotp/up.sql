@@ -0,0 +1,3 @@
+-- The TOTP secret handed out on the invitation page, the employee has to confirm it on registration
+ALTER TABLE EmployeeInvitations
+    ADD COLUMN totp_secret VARCHAR(255) NULL;
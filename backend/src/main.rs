@@ -3,7 +3,7 @@ use config::Config;
 use backend::server;
 use backend::server::BackendServer;
 use std::thread;
-use anyhow::Result;
+use anyhow::{ensure, Result};
 
 #[moon::main]
 async fn main() -> Result<()>{
@@ -22,6 +22,13 @@ async fn main() -> Result<()>{
     */
     //let server: BackendServer = BackendServer::new(Some("config/server.toml"))?;
 
+    if std::env::args().nth(1).as_deref() == Some("verify-audit") {
+        let verification = BackendServer::verify_audit_trail(Some("config/server.toml"))?;
+        println!("{}", serde_json::to_string_pretty(&verification)?);
+        ensure!(verification.broken.is_none(), "The audit trail is broken");
+        return Ok(());
+    }
+
     BackendServer::start(Some("config/server.toml")).await?;
     Ok(())
 }
@@ -0,0 +1,14 @@
+#!/bin/sh
+# Downloads the files of the Swagger UI from the npm package swagger-ui-dist into this directory
+set -eu
+
+VERSION="${1:-4.15.5}"
+DIR="$(cd "$(dirname "$0")" && pwd)"
+TMP="$(mktemp -d)"
+trap 'rm -rf "$TMP"' EXIT
+
+curl -fsSL "https://registry.npmjs.org/swagger-ui-dist/-/swagger-ui-dist-$VERSION.tgz" | tar -xz -C "$TMP"
+for file in swagger-ui.css swagger-ui-bundle.js LICENSE; do
+    cp "$TMP/package/$file" "$DIR/$file"
+done
+echo "$VERSION" > "$DIR/VERSION"
@@ -0,0 +1,33 @@
+use backend::auth::Audit::{events_to_csv, AuditEvent};
+use moon::Utc;
+
+fn event(details: &str) -> AuditEvent {
+    AuditEvent {
+        id: 1,
+        created: Utc::now().naive_utc(),
+        actor_type: String::from("anonymous"),
+        actor_id: None,
+        subject_type: None,
+        subject_id: None,
+        event_type: String::from("citizen_login"),
+        outcome: String::from("failure"),
+        details: Some(details.to_string()),
+        ip: None,
+        user_agent: None,
+        prev_hash: None,
+        hash: None,
+    }
+}
+
+fn details_field(csv: &str) -> String {
+    csv.lines().nth(1).unwrap().split(',').nth(8).unwrap().to_string()
+}
+
+#[test]
+fn formulas_are_not_exported_as_formulas() {
+    assert_eq!(details_field(&events_to_csv(&[event("=HYPERLINK(\"http://evil.example\")")])), "\"'=HYPERLINK(\"\"http://evil.example\"\")\"");
+    assert_eq!(details_field(&events_to_csv(&[event("@SUM(A1)")])), "'@SUM(A1)");
+    assert_eq!(details_field(&events_to_csv(&[event("+1")])), "'+1");
+    assert_eq!(details_field(&events_to_csv(&[event("-1")])), "'-1");
+    assert_eq!(details_field(&events_to_csv(&[event("user not found")])), "user not found");
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use async_trait::async_trait;
+use backend::auth::Citizen::CitizenInfo;
+use backend::auth::CitizenDirectory::{CachedCitizenDirectory, CircuitBreakerDirectory, CitizenDirectory, StaticCitizenDirectory};
+use backend::auth::Errors::{CitizenInfoRetrievalError, CitizenInfoRetrievalResult};
+use common::citizen;
+
+/// Counts how often the cache asks the underlying directory
+struct CountingDirectory {
+    inner: StaticCitizenDirectory,
+    requests: AtomicUsize,
+}
+
+#[async_trait]
+impl CitizenDirectory for CountingDirectory {
+    async fn citizen_info(&self, citizen_id: u64) -> CitizenInfoRetrievalResult<CitizenInfo> {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        self.inner.citizen_info(citizen_id).await
+    }
+}
+
+fn counting_cache(ttl: Duration) -> CachedCitizenDirectory<CountingDirectory> {
+    let inner = CountingDirectory {
+        inner: StaticCitizenDirectory::new(vec![citizen(1)]),
+        requests: AtomicUsize::new(0),
+    };
+    CachedCitizenDirectory::new(inner, ttl, ttl)
+}
+
+#[tokio::test]
+async fn static_directory_knows_only_its_citizens() {
+    let directory = StaticCitizenDirectory::new(vec![citizen(1)]);
+
+    assert_eq!(directory.citizen_info(1).await.unwrap().citizen_id, 1);
+    assert!(matches!(directory.citizen_info(2).await, Err(CitizenInfoRetrievalError::NotFound(2))));
+}
+
+#[tokio::test]
+async fn cache_answers_known_and_unknown_citizens() {
+    let cache = counting_cache(Duration::from_secs(60));
+
+    for _ in 0..3 {
+        assert!(cache.citizen_info(1).await.is_ok());
+        assert!(matches!(cache.citizen_info(2).await, Err(CitizenInfoRetrievalError::NotFound(2))));
+    }
+    assert_eq!(cache.inner().requests.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn cache_asks_again_after_expiry() {
+    let cache = counting_cache(Duration::ZERO);
+
+    cache.citizen_info(1).await.unwrap();
+    cache.citizen_info(1).await.unwrap();
+    assert_eq!(cache.inner().requests.load(Ordering::SeqCst), 2);
+}
+
+/// Always fails as if the citizen service was down
+struct FailingDirectory {
+    requests: AtomicUsize,
+}
+
+#[async_trait]
+impl CitizenDirectory for FailingDirectory {
+    async fn citizen_info(&self, _: u64) -> CitizenInfoRetrievalResult<CitizenInfo> {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        Err(CitizenInfoRetrievalError::Unavailable)
+    }
+}
+
+#[tokio::test]
+async fn breaker_stops_requests_after_failures() {
+    let breaker = CircuitBreakerDirectory::new(FailingDirectory { requests: AtomicUsize::new(0) }, 3, Duration::from_secs(60));
+
+    for _ in 0..10 {
+        assert!(breaker.citizen_info(1).await.is_err());
+    }
+    assert_eq!(breaker.inner().requests.load(Ordering::SeqCst), 3);
+}
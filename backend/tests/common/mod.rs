@@ -0,0 +1,24 @@
+use backend::auth::Citizen::{CitizenAddress, CitizenInfo};
+
+/// A citizen with email and postal address, tests remove what they do not need
+pub fn citizen(citizen_id: u64) -> CitizenInfo {
+    CitizenInfo {
+        citizen_id,
+        firstname: String::from("Erika"),
+        lastname: String::from("Mustermann"),
+        gender: None,
+        birthdate: None,
+        place_of_birth: None,
+        birthname: None,
+        email: Some(String::from("erika@example.org")),
+        spouse_id: None,
+        child_ids: None,
+        language: None,
+        address: CitizenAddress {
+            street: Some(String::from("Hauptstraße")),
+            housenumber: Some(String::from("12a")),
+            city_code: Some(1067),
+            city: Some(String::from("Dresden"))
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use backend::auth::Actions::{employee_invitation_totp_secret, invite_employee, register_employee};
+use backend::auth::Audit::RequestOrigin;
+use backend::auth::Employee::NewEmployeeInfo;
+use backend::auth::Errors::UserRegistrationError;
+use backend::auth::Request::EmployeeRegisterRequest;
+use diesel::{Connection, MysqlConnection};
+use moon::chrono;
+
+/// Like the registration flow, the writes of the test are never committed
+fn database() -> MysqlConnection {
+    let db = MysqlConnection::establish(&std::env::var("DATABASE_URL").expect("DATABASE_URL is not set")).unwrap();
+    db.begin_test_transaction().unwrap();
+    db
+}
+
+#[test]
+#[ignore = "needs a migrated database in DATABASE_URL"]
+fn the_second_factor_is_the_secret_stored_with_the_invitation() {
+    let db = database();
+    let info = NewEmployeeInfo {
+        firstname: String::from("Max"),
+        lastname: String::from("Mustermann"),
+        department: None,
+        email: Some(String::from("max@example.org")),
+        phone: None
+    };
+    let invitation = invite_employee(&db, &info, "max@example.org", None, chrono::Duration::hours(1), &RequestOrigin::default()).unwrap();
+
+    let (_, secret) = employee_invitation_totp_secret(&db, &invitation.token).unwrap();
+    let (_, again) = employee_invitation_totp_secret(&db, &invitation.token).unwrap();
+    let request: EmployeeRegisterRequest = serde_json::from_value(serde_json::json!({
+        "invitation": invitation.token,
+        "username": "max.mustermann",
+        "password": "correct horse battery staple",
+        "totp_secret": "JBSWY3DPEHPK3PXP",
+        "totp_code": "not a code",
+    })).unwrap();
+    let result = register_employee(&db, &request, &RequestOrigin::default());
+
+    assert_eq!(secret, again);
+    assert!(matches!(result, Err(UserRegistrationError::InvalidSecondFactor)), "{:?}", result.map(|e| e.id));
+}
@@ -0,0 +1,8 @@
+use backend::auth::Actions::contains_pattern;
+
+#[test]
+fn search_terms_are_matched_literally() {
+    assert_eq!(contains_pattern("erika"), "%erika%");
+    assert_eq!(contains_pattern("100%_sicher"), "%100\\%\\_sicher%");
+    assert_eq!(contains_pattern("a\\b"), "%a\\\\b%");
+}
@@ -0,0 +1,101 @@
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+use backend::auth::Errors::EventHandlingError;
+use backend::events::{CitizenDeceasedEvent, CitizenDeletedEvent, CitizenMovedOutEvent, CitizenUpdatedEvent, EventDispatcher, EventHandler, IntegrationEvent, NewCitizenEvent};
+
+/// Remembers every event it was asked to handle
+#[derive(Clone, Default)]
+struct RecordingHandler {
+    events: Arc<Mutex<Vec<IntegrationEvent>>>,
+}
+
+#[async_trait]
+impl EventHandler for RecordingHandler {
+    async fn handle(&self, event: &IntegrationEvent) -> Result<(), EventHandlingError> {
+        self.events.lock().unwrap().push(event.clone());
+        Ok(())
+    }
+}
+
+struct FailingHandler;
+
+#[async_trait]
+impl EventHandler for FailingHandler {
+    async fn handle(&self, _: &IntegrationEvent) -> Result<(), EventHandlingError> {
+        Err(EventHandlingError::Transient(anyhow::anyhow!("Database is down")))
+    }
+}
+
+fn recording_dispatcher() -> (EventDispatcher, RecordingHandler) {
+    let handler = RecordingHandler::default();
+    let dispatcher = EventDispatcher::new().register(NewCitizenEvent::EVENT_ID, handler.clone());
+    (dispatcher, handler)
+}
+
+#[tokio::test]
+async fn new_citizen_event_reaches_its_handler() {
+    let (dispatcher, handler) = recording_dispatcher();
+
+    let event = dispatcher
+        .dispatch(br#"{"event_id": 1001, "citizen_id": 42, "firstname": "Erika"}"#)
+        .await
+        .unwrap();
+
+    let expected = IntegrationEvent::NewCitizen(NewCitizenEvent { citizen_id: 42 });
+    assert_eq!(event, expected);
+    assert_eq!(*handler.events.lock().unwrap(), vec![expected]);
+}
+
+#[tokio::test]
+async fn unknown_events_are_ignored() {
+    let (dispatcher, handler) = recording_dispatcher();
+
+    let event = dispatcher
+        .dispatch(br#"{"event_id": 4711, "something": "else"}"#)
+        .await
+        .unwrap();
+
+    assert_eq!(event, IntegrationEvent::Unknown { event_id: 4711 });
+    assert!(handler.events.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn malformed_payloads_are_permanent_failures() {
+    let (dispatcher, handler) = recording_dispatcher();
+
+    let payloads: [&[u8]; 4] = [
+        b"not json at all",
+        br#"{"citizen_id": 42}"#,
+        br#"{"event_id": 1001}"#,
+        br#"{"event_id": 1001, "citizen_id": "forty-two"}"#,
+    ];
+    for payload in payloads {
+        let result = dispatcher.dispatch(payload).await;
+        assert!(matches!(result, Err(EventHandlingError::Permanent(_))), "{:?}", String::from_utf8_lossy(payload));
+    }
+    assert!(handler.events.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn handler_failures_are_passed_on() {
+    let dispatcher = EventDispatcher::new().register(NewCitizenEvent::EVENT_ID, FailingHandler);
+
+    let result = dispatcher.dispatch(br#"{"event_id": 1001, "citizen_id": 42}"#).await;
+
+    assert!(matches!(result, Err(EventHandlingError::Transient(_))));
+}
+
+#[test]
+fn citizen_lifecycle_events_are_parsed() {
+    let payloads: [(&[u8], u64); 4] = [
+        (br#"{"event_id": 1002, "citizen_id": 7, "email": "new@example.org"}"#, CitizenUpdatedEvent::EVENT_ID),
+        (br#"{"event_id": 1003, "citizen_id": 7}"#, CitizenDeceasedEvent::EVENT_ID),
+        (br#"{"event_id": 1004, "citizen_id": 7}"#, CitizenMovedOutEvent::EVENT_ID),
+        (br#"{"event_id": 1005, "citizen_id": 7}"#, CitizenDeletedEvent::EVENT_ID),
+    ];
+    for (payload, event_id) in payloads {
+        let event = IntegrationEvent::from_payload(payload).unwrap();
+        assert_eq!(event.event_id(), event_id);
+        assert_eq!(event.citizen_id(), Some(7));
+    }
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use backend::auth::Actions::{citizen_code_delivery, CodeDelivery};
+use backend::auth::Citizen::{CitizenAddress, CitizenInfo};
+use backend::auth::Errors::LetterError;
+use backend::auth::Letter::LetterRenderer;
+use backend::auth::MailTemplate::MailTemplates;
+use common::citizen;
+use qrcode::QrCode;
+
+/// The citizen office has no email address of the citizen
+fn citizen_without_email() -> CitizenInfo {
+    CitizenInfo { email: None, ..citizen(42) }
+}
+
+fn letters() -> LetterRenderer {
+    LetterRenderer::new(vec![String::from("Bürgeramt")], "https://portal.example.org")
+}
+
+#[test]
+fn citizens_without_email_get_a_letter() {
+    let templates = MailTemplates::new("de", "support@example.org", None);
+    let delivery = citizen_code_delivery(&templates, &letters(), &citizen_without_email(), &String::from("ABC123")).unwrap();
+
+    match delivery {
+        CodeDelivery::Letter(pdf) => assert!(pdf.starts_with(b"%PDF")),
+        CodeDelivery::Mail(_) => panic!("Expected a letter")
+    }
+}
+
+#[test]
+fn letters_need_an_address() {
+    let citizen = CitizenInfo {
+        address: CitizenAddress { street: None, ..citizen_without_email().address },
+        ..citizen_without_email()
+    };
+    let result = letters().render(&citizen, &String::from("ABC123"));
+
+    assert!(matches!(result, Err(LetterError::MissingAddress)));
+}
+
+#[test]
+fn qr_codes_link_to_the_portal() {
+    let code = String::from("ABCD-EFGH-JKLM-NPQR");
+
+    let qr_code = letters().qr_code(&code).unwrap();
+
+    let expected = QrCode::new("https://portal.example.org?code=ABCD-EFGH-JKLM-NPQR").unwrap();
+    assert_eq!(qr_code.to_colors(), expected.to_colors());
+    assert_ne!(qr_code.to_colors(), QrCode::new("https://portal.example.org?code=ABCD-EFGH-JKLM-NPQS").unwrap().to_colors());
+}
+
+#[test]
+fn letters_are_rendered_without_a_font_file() {
+    let pdf = letters().render(&citizen_without_email(), &String::from("ABC123")).unwrap();
+
+    assert!(pdf.starts_with(b"%PDF"));
+    assert!(String::from_utf8_lossy(&pdf).contains("Helvetica"));
+}
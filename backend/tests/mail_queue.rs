@@ -0,0 +1,147 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use backend::auth::Errors::MailSenderError;
+use backend::auth::MailOutbox::{MailStatus, MailUpdate, QueuedMail};
+use backend::auth::Mailer::{Mail, Mailer, MemoryMailer};
+use backend::server::MailQueueConfig;
+use moon::{chrono, NaiveDateTime, Utc};
+
+/// Fails the given number of times before it hands the mails to the memory mailer
+struct FlakyMailer {
+    failures: AtomicUsize,
+    outbox: MemoryMailer,
+}
+
+impl FlakyMailer {
+    fn new(failures: usize) -> Self {
+        Self { failures: AtomicUsize::new(failures), outbox: MemoryMailer::new() }
+    }
+}
+
+impl Mailer for FlakyMailer {
+    fn send(&self, mail: &Mail) -> Result<(), MailSenderError> {
+        if self.failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |f| f.checked_sub(1)).is_ok() {
+            return Err(MailSenderError::Io(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "connection refused")));
+        }
+        self.outbox.send(mail)
+    }
+}
+
+/// The row `queue_mail` stores for the mail
+fn enqueue(mail: &Mail, expires: NaiveDateTime) -> QueuedMail {
+    let now = Utc::now().naive_utc();
+    QueuedMail {
+        id: 1,
+        created: now,
+        recipient: mail.to.clone(),
+        subject: mail.subject.clone(),
+        mail: Some(serde_json::to_string(mail).unwrap()),
+        status: String::from("pending"),
+        attempts: 0,
+        last_error: None,
+        next_attempt: now,
+        sent: None,
+        expires,
+    }
+}
+
+/// Applies the update like `record_attempt` does
+fn apply(queued: QueuedMail, update: MailUpdate) -> QueuedMail {
+    QueuedMail {
+        status: update.status,
+        attempts: update.attempts,
+        last_error: update.last_error,
+        next_attempt: update.next_attempt,
+        sent: update.sent,
+        mail: update.mail,
+        ..queued
+    }
+}
+
+fn mail() -> Mail {
+    Mail {
+        from: String::from("support@example.org"),
+        reply_to: None,
+        to: String::from("erika@example.org"),
+        subject: String::from("Ihr Code"),
+        text: String::from("Code: ABC123"),
+        html: None,
+    }
+}
+
+fn in_days(days: i64) -> NaiveDateTime {
+    Utc::now().naive_utc() + chrono::Duration::days(days)
+}
+
+#[test]
+fn queued_mails_are_delivered_and_their_content_is_removed() {
+    let mailer = FlakyMailer::new(0);
+    let queued = enqueue(&mail(), in_days(1));
+
+    let queued = apply(queued.clone(), queued.attempt(&mailer, &MailQueueConfig::default()));
+
+    assert_eq!(queued.status(), Some(MailStatus::Sent));
+    assert!(queued.sent.is_some());
+    assert!(queued.mail.is_none());
+    assert_eq!(mailer.outbox.mails_to("erika@example.org")[0].text, "Code: ABC123");
+}
+
+#[test]
+fn failed_attempts_are_retried_with_growing_delays() {
+    let config = MailQueueConfig::default();
+    let mailer = FlakyMailer::new(2);
+    let mut queued = enqueue(&mail(), in_days(1));
+
+    let mut delays = Vec::new();
+    while queued.status() == Some(MailStatus::Pending) {
+        let before = Utc::now().naive_utc();
+        queued = apply(queued.clone(), queued.attempt(&mailer, &config));
+        delays.push((queued.next_attempt - before).num_seconds());
+    }
+
+    assert_eq!(queued.status(), Some(MailStatus::Sent));
+    assert_eq!(queued.attempts, 3);
+    assert!(delays[0] >= 29 && delays[0] <= 30, "{:?}", delays);
+    assert!(delays[1] >= 59 && delays[1] <= 60, "{:?}", delays);
+    assert_eq!(mailer.outbox.mails().len(), 1);
+}
+
+#[test]
+fn retry_delays_are_capped_and_end_after_the_last_attempt() {
+    let config = MailQueueConfig::default();
+
+    assert_eq!(config.retry_delay(1), Some(chrono::Duration::seconds(30)));
+    assert_eq!(config.retry_delay(2), Some(chrono::Duration::seconds(60)));
+    assert_eq!(config.retry_delay(7), Some(chrono::Duration::seconds(1920)));
+    assert_eq!(config.retry_delay(8), None);
+    assert!((1..8).all(|attempt| config.retry_delay(attempt).unwrap() <= chrono::Duration::hours(1)));
+}
+
+#[test]
+fn mails_are_marked_as_failed_after_the_last_attempt() {
+    let config = MailQueueConfig::default();
+    let mailer = FlakyMailer::new(usize::MAX);
+    let mut queued = enqueue(&mail(), in_days(1));
+
+    while queued.status() == Some(MailStatus::Pending) {
+        queued = apply(queued.clone(), queued.attempt(&mailer, &config));
+    }
+
+    assert_eq!(queued.status(), Some(MailStatus::Failed));
+    assert_eq!(queued.attempts, 8);
+    assert!(queued.last_error.is_some());
+    // Kept until it expires so that it can be sent again on request
+    assert!(queued.mail().is_some());
+    assert!(mailer.outbox.mails().is_empty());
+}
+
+#[test]
+fn expired_mails_are_not_sent() {
+    let mailer = FlakyMailer::new(0);
+    let queued = enqueue(&mail(), in_days(-1));
+
+    let queued = apply(queued.clone(), queued.attempt(&mailer, &MailQueueConfig::default()));
+
+    assert_eq!(queued.status(), Some(MailStatus::Failed));
+    assert!(queued.mail.is_none());
+    assert!(mailer.outbox.mails().is_empty());
+}
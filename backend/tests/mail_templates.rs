@@ -0,0 +1,64 @@
+use std::path::Path;
+use backend::auth::Errors::MailSenderError;
+use backend::auth::MailTemplate::MailTemplates;
+
+fn templates() -> MailTemplates {
+    MailTemplates::new("de", "support@example.org", Some("help@example.org"))
+        .global("portal_url", "https://portal.example.org")
+        .template("de", "greeting", "Hallo {{ name }}", "Hallo {{name}}, siehe {{portal_url}}", Some("<p>Hallo {{name}}</p>"))
+        .template("en", "greeting", "Hello {{name}}", "Hello {{name}}", None)
+}
+
+#[test]
+fn variables_are_substituted() {
+    let mail = templates()
+        .render("greeting", None, "erika@example.org", &[("name", "Erika")])
+        .unwrap();
+
+    assert_eq!(mail.from, "support@example.org");
+    assert_eq!(mail.reply_to.as_deref(), Some("help@example.org"));
+    assert_eq!(mail.to, "erika@example.org");
+    assert_eq!(mail.subject, "Hallo Erika");
+    assert_eq!(mail.text, "Hallo Erika, siehe https://portal.example.org");
+    assert_eq!(mail.html.as_deref(), Some("<p>Hallo Erika</p>"));
+}
+
+#[test]
+fn values_are_escaped_in_html() {
+    let mail = templates()
+        .render("greeting", Some("de"), "erika@example.org", &[("name", "<b>Erika</b>")])
+        .unwrap();
+
+    assert_eq!(mail.text, "Hallo <b>Erika</b>, siehe https://portal.example.org");
+    assert_eq!(mail.html.as_deref(), Some("<p>Hallo &lt;b&gt;Erika&lt;/b&gt;</p>"));
+}
+
+#[test]
+fn locales_fall_back_to_language_and_default() {
+    let templates = templates();
+    let render = |locale| templates.render("greeting", locale, "x@example.org", &[("name", "Erika")]).unwrap().subject;
+
+    assert_eq!(render(Some("en")), "Hello Erika");
+    assert_eq!(render(Some("en-GB")), "Hello Erika");
+    assert_eq!(render(Some("fr")), "Hallo Erika");
+}
+
+#[test]
+fn missing_templates_and_variables_are_errors() {
+    let templates = templates();
+
+    assert!(matches!(templates.render("farewell", None, "x@example.org", &[]), Err(MailSenderError::MissingTemplate(_))));
+    assert!(matches!(templates.render("greeting", None, "x@example.org", &[]), Err(MailSenderError::UnknownVariable(_, v)) if v == "name"));
+}
+
+#[test]
+fn shipped_templates_are_complete() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../templates/mail");
+    for locale in ["de", "en"] {
+        let templates = MailTemplates::new(locale, "support@example.org", None)
+            .global("portal_url", "https://portal.example.org")
+            .load(&dir)
+            .unwrap();
+        templates.render("citizen_code", Some(locale), "x@example.org", &[("name", "Erika"), ("code", "ABC")]).unwrap();
+    }
+}
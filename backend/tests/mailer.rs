@@ -0,0 +1,37 @@
+mod common;
+
+use backend::auth::Actions::citizen_code_mail;
+use backend::auth::Citizen::CitizenInfo;
+use backend::auth::Errors::MailSenderError;
+use backend::auth::Mailer::{FileMailer, Mailer};
+use backend::auth::MailTemplate::MailTemplates;
+use common::citizen;
+
+fn templates() -> MailTemplates {
+    MailTemplates::new("de", "support@example.org", None)
+        .template("de", "citizen_code", "Ihr Code", "Code: {{code}}", None)
+        .template("en", "citizen_code", "Your code", "Code: {{code}}", Some("<p>{{code}}</p>"))
+}
+
+#[test]
+fn citizens_without_email_get_no_mail() {
+    let result = citizen_code_mail(&templates(), &CitizenInfo { email: None, ..citizen(42) }, &String::from("ABC123"));
+
+    assert!(matches!(result, Err(MailSenderError::MissingRecipient)));
+}
+
+#[test]
+fn file_mailer_writes_eml_files() {
+    let directory = std::env::temp_dir().join(format!("smartauth-mails-{}", std::process::id()));
+    let mail = citizen_code_mail(&templates(), &CitizenInfo { language: Some(String::from("en")), ..citizen(42) }, &String::from("ABC123")).unwrap();
+    FileMailer::new(directory.clone()).send(&mail).unwrap();
+
+    let files: Vec<_> = std::fs::read_dir(&directory).unwrap().map(|f| f.unwrap().path()).collect();
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].extension().unwrap(), "eml");
+    let content = std::fs::read_to_string(&files[0]).unwrap();
+    assert!(content.contains("Subject: Your code"));
+    assert!(content.contains("<p>ABC123</p>"));
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,24 @@
+use backend::server::openapi_document;
+
+#[test]
+fn openapi_document_describes_the_api() {
+    let document = openapi_document();
+
+    assert_eq!(document["openapi"], "3.0.3");
+    assert_eq!(document["servers"][0]["url"], "/api/v1");
+    assert!(document["paths"]["/login"]["post"]["requestBody"].is_object());
+    assert!(document["paths"]["/employee/register"]["post"]["responses"]["201"]["content"]["application/json"].is_object());
+    assert!(document["paths"]["/employee/register"]["post"]["responses"]["302"].is_object());
+    assert!(document["paths"]["/employee/audit"]["get"]["parameters"].as_array().map_or(false, |p| !p.is_empty()));
+    assert!(document["components"]["schemas"]["UserInfoRequestResponse"].is_object());
+    assert!(document["components"]["schemas"]["EmployeeInfoRequestResponse"].is_object());
+    assert!(document["components"]["schemas"]["Problem"].is_object());
+}
+
+#[test]
+fn pages_are_not_part_of_the_api() {
+    let document = openapi_document();
+
+    assert!(document["paths"]["/external"].is_null());
+    assert!(document["paths"]["/password/reset"]["get"].is_null());
+}
@@ -0,0 +1,62 @@
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use actix_web::ResponseError;
+use backend::auth::Actions::{check_user_session_token, verify_employee};
+use backend::auth::Errors::{AuthenticationError, DatabaseError, LoginError, SessionRetrievalError};
+use backend::auth::Problem::{request_locale, Problem, ProblemCode};
+use diesel::{Connection, MysqlConnection};
+
+#[test]
+fn errors_have_stable_codes_and_statuses() {
+    assert_eq!(DatabaseError::NotFound.status_code(), StatusCode::NOT_FOUND);
+    assert_eq!(DatabaseError::Duplicate.status_code(), StatusCode::CONFLICT);
+    assert_eq!(SessionRetrievalError::InvalidSession.status_code(), StatusCode::UNAUTHORIZED);
+    assert_eq!(SessionRetrievalError::InvalidSession.code(), "invalid_session");
+
+    let wrong_password = LoginError::Authentication(AuthenticationError::WrongPassword);
+    let unknown_user = LoginError::Authentication(AuthenticationError::UserNotFound);
+    assert_eq!(wrong_password.status_code(), StatusCode::UNAUTHORIZED);
+    assert_eq!(wrong_password.code(), unknown_user.code());
+    assert_eq!(LoginError::Authentication(AuthenticationError::Locked).code(), "account_locked");
+}
+
+#[test]
+fn missing_rows_are_not_reported_as_database_errors() {
+    assert!(matches!(DatabaseError::from(diesel::result::Error::NotFound), DatabaseError::NotFound));
+    assert!(matches!(DatabaseError::from(diesel::result::Error::RollbackTransaction), DatabaseError::Other(_)));
+
+    let unknown_session = SessionRetrievalError::from(diesel::result::Error::NotFound);
+    assert_eq!(unknown_session.status_code(), StatusCode::UNAUTHORIZED);
+    assert_eq!(unknown_session.code(), "invalid_session");
+}
+
+#[test]
+#[ignore = "needs a migrated database in DATABASE_URL"]
+fn unknown_session_tokens_are_unauthorized() {
+    let db = MysqlConnection::establish(&std::env::var("DATABASE_URL").unwrap()).unwrap();
+    let token = String::from("not-a-session-token");
+
+    let citizen = check_user_session_token(&db, &token).map(|_| ()).unwrap_err();
+    let employee = verify_employee(&db, &token).map(|_| ()).unwrap_err();
+
+    assert_eq!(citizen.status_code(), StatusCode::UNAUTHORIZED);
+    assert_eq!(employee.status_code(), StatusCode::UNAUTHORIZED);
+    assert_eq!(employee.code(), "invalid_session");
+}
+
+#[test]
+fn problems_are_localized() {
+    let problem = Problem::new(StatusCode::UNAUTHORIZED, "missing_token", "No session token was sent");
+    assert_eq!(problem.kind, "urn:smartauth:problem:missing_token");
+    assert_eq!(problem.title, "Please log in");
+    assert_eq!(problem.localize("de").title, "Bitte melden Sie sich an");
+}
+
+#[test]
+fn locale_is_taken_from_accept_language() {
+    let german = TestRequest::default().insert_header(("Accept-Language", "fr-CH, de-DE;q=0.8, en;q=0.5")).to_http_request();
+    let none = TestRequest::default().to_http_request();
+
+    assert_eq!(request_locale(&german, "en"), "de");
+    assert_eq!(request_locale(&none, "en"), "en");
+}
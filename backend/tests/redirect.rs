@@ -0,0 +1,26 @@
+use actix_web::http::header::LOCATION;
+use backend::auth::Redirect::{redirect_to, Redirects};
+
+fn redirects() -> Redirects {
+    Redirects::new("http://auth.supersmartcity.de:8080", &[String::from("https://www.supersmartcity.de")]).unwrap()
+}
+
+#[test]
+fn only_allowed_origins_are_accepted() {
+    let redirects = redirects();
+
+    assert!(redirects.target("/page/login").is_ok());
+    assert!(redirects.target("https://www.supersmartcity.de/app").is_ok());
+    assert!(redirects.target("https://evil.example/app").is_err());
+    assert!(redirects.target("//evil.example/app").is_err());
+    assert!(redirects.target("javascript:alert(1)").is_err());
+    assert!(redirects.target("https://www.supersmartcity.de.evil.example/").is_err());
+}
+
+#[test]
+fn parameters_are_merged_into_the_query() {
+    let target = redirects().target("https://www.supersmartcity.de/app?tab=1&token=old#top").unwrap();
+    let response = redirect_to(target, &[("token", "a b&c")], Some("xyz")).unwrap();
+
+    assert_eq!(response.headers().get(LOCATION).unwrap(), "https://www.supersmartcity.de/app?tab=1&token=a+b%26c&state=xyz#top");
+}
@@ -0,0 +1,70 @@
+use backend::auth::RegistrationCode::RegistrationCodeFormat;
+use backend::auth::User::PendingUser;
+use moon::{chrono, Utc};
+
+#[test]
+fn generated_codes_are_grouped() {
+    let code = RegistrationCodeFormat::default().generate();
+
+    assert_eq!(code.len(), 19);
+    assert_eq!(code.split('-').map(str::len).collect::<Vec<_>>(), vec![4, 4, 4, 4]);
+    assert!(code.chars().all(|c| c == '-' || c.is_ascii_digit() || c.is_ascii_uppercase()));
+}
+
+#[test]
+fn typed_codes_are_normalized() {
+    let format = RegistrationCodeFormat::default();
+    let code = format.generate();
+    let typed = code.replace('-', " ").to_lowercase().replace('0', "o").replace('1', "l");
+
+    assert_eq!(format.normalize(&typed), Some(code.clone()));
+    assert_eq!(format.normalize(&code.replace('-', "")), Some(code));
+}
+
+#[test]
+fn typos_are_detected() {
+    let format = RegistrationCodeFormat::default();
+    let code = format.generate();
+    let first = code.chars().next().unwrap();
+    let typo = format!("{}{}", if first == 'A' { 'B' } else { 'A' }, &code[1..]);
+
+    assert_eq!(format.normalize(&typo), None);
+    assert_eq!(format.normalize(&code[..code.len() - 1]), None);
+    assert_eq!(format.normalize("ABC)(*&^%$"), None);
+}
+
+#[test]
+fn formats_without_check_digit() {
+    let format = RegistrationCodeFormat { groups: 2, group_length: 5, check_digit: false };
+    let code = format.generate();
+
+    assert_eq!(code.len(), 11);
+    assert_eq!(format.normalize(&code.to_lowercase()), Some(code));
+}
+
+fn pending_user(issued_minutes_ago: i64, expires_in_days: i64) -> PendingUser {
+    let now = Utc::now().naive_utc();
+    PendingUser {
+        id: 1,
+        citizen: 42,
+        code: RegistrationCodeFormat::default().generate(),
+        email: Some(String::from("erika@example.org")),
+        issued: now - chrono::Duration::minutes(issued_minutes_ago),
+        expires: now + chrono::Duration::days(expires_in_days),
+    }
+}
+
+#[test]
+fn codes_expire() {
+    assert!(pending_user(0, 30).is_valid());
+    assert!(!pending_user(60 * 24 * 31, -1).is_valid());
+}
+
+#[test]
+fn codes_are_only_resent_after_the_interval() {
+    let interval = chrono::Duration::minutes(10);
+
+    assert!(!pending_user(0, 30).can_be_resent(interval));
+    assert!(!pending_user(9, 30).can_be_resent(interval));
+    assert!(pending_user(10, 30).can_be_resent(interval));
+}
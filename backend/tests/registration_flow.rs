@@ -0,0 +1,195 @@
+mod common;
+
+use std::sync::Arc;
+use backend::auth::Actions::{check_resend_origin, ensure_pending_user, find_pending_user, issue_citizen_code, rebind_pending_user, register_user, renew_pending_user};
+use backend::auth::Audit::{record_event, AuditEventType, AuditOutcome, AuditParty, NewAuditEvent, RequestOrigin};
+use backend::auth::Errors::UserRegistrationError;
+use backend::auth::Letter::LetterRenderer;
+use backend::auth::MailOutbox::{claim_due_mails, record_attempt};
+use backend::auth::MailTemplate::MailTemplates;
+use backend::auth::Mailer::MemoryMailer;
+use backend::auth::RegistrationCode::RegistrationCodeFormat;
+use backend::auth::Request::UserRegistrationRequest;
+use backend::schema::{PendingUsers, Users};
+use backend::server::{MailQueueConfig, MailServer};
+use common::citizen;
+use diesel::{Connection, ExpressionMethods, MysqlConnection, QueryDsl, RunQueryDsl};
+use moon::{chrono, Utc};
+
+fn mail_server(outbox: Arc<MemoryMailer>) -> MailServer {
+    let templates = MailTemplates::new("de", "support@example.org", None)
+        .template("de", "citizen_code", "Ihr Code", "Code: {{code}}", None);
+    MailServer::in_memory(outbox, templates, LetterRenderer::new(vec![], "http://localhost:8080"))
+}
+
+/// Sends the due mails like the mail queue of the server
+fn send_queued_mails(db: &MysqlConnection, mail: &MailServer) {
+    for queued in claim_due_mails(db, 100, chrono::Duration::minutes(5)).unwrap() {
+        let update = queued.attempt(mail.mailer.as_ref(), &MailQueueConfig::default());
+        record_attempt(db, queued.id, &update).unwrap();
+    }
+}
+
+fn registration(citizen_id: u64, code: &str, mail: &str) -> UserRegistrationRequest {
+    serde_json::from_value(serde_json::json!({
+        "username": format!("erika.{}", citizen_id),
+        "password": "correct horse battery staple",
+        "mail": mail,
+        "code": code,
+    })).unwrap()
+}
+
+/// The tests need a migrated database in `DATABASE_URL`, but no mail server. Their transaction is never committed,
+/// the audit events they record could not be removed from the hash chain afterwards
+fn database() -> MysqlConnection {
+    let db = MysqlConnection::establish(&std::env::var("DATABASE_URL").expect("DATABASE_URL is not set")).unwrap();
+    db.begin_test_transaction().unwrap();
+    db
+}
+
+/// A citizen id no other test uses
+fn test_citizen(offset: u64) -> u64 {
+    4_200_000_000 + std::process::id() as u64 * 10 + offset
+}
+
+#[test]
+#[ignore = "needs a migrated database in DATABASE_URL"]
+fn citizens_register_with_the_code_from_their_mail() {
+    let db = database();
+    let citizen_id = test_citizen(0);
+    let format = RegistrationCodeFormat::default();
+    let outbox = Arc::new(MemoryMailer::new());
+    let mail = mail_server(outbox.clone());
+
+    let info = citizen(citizen_id);
+    let code = issue_citizen_code(&db, &format, &mail.templates, &mail.letters, &info).unwrap().unwrap();
+    let repeated = issue_citizen_code(&db, &format, &mail.templates, &mail.letters, &info).unwrap();
+    send_queued_mails(&db, &mail);
+
+    let mails = mail.memory.as_ref().unwrap().mails_to("erika@example.org");
+    assert_eq!(mails.len(), 1);
+    assert!(repeated.is_none());
+    let sent_code = mails[0].text.strip_prefix("Code: ").unwrap().to_string();
+    assert_eq!(sent_code, code);
+
+    let result = register_user(&db, &format, &registration(citizen_id, &sent_code, "erika@example.org"), None, &RequestOrigin::default());
+    let registered = diesel::dsl::select(diesel::dsl::exists(Users::table.find(citizen_id))).get_result::<bool>(&db).unwrap();
+    let again = register_user(&db, &format, &registration(citizen_id, &sent_code, "erika@example.org"), None, &RequestOrigin::default());
+
+    result.unwrap();
+    assert!(registered);
+    assert!(matches!(again, Err(UserRegistrationError::InvalidCitizenCode)), "{:?}", again);
+}
+
+#[test]
+#[ignore = "needs a migrated database in DATABASE_URL"]
+fn codes_without_stored_address_need_the_address_of_the_citizen_office() {
+    let db = database();
+    let citizen_id = test_citizen(3);
+    let format = RegistrationCodeFormat::default();
+
+    let code = ensure_pending_user(&db, &format, citizen_id, None).unwrap().unwrap().0;
+    let other_address = register_user(&db, &format, &registration(citizen_id, &code, "mallory@example.org"), Some("erika@example.org"), &RequestOrigin::default());
+    let without_letter = register_user(&db, &format, &registration(citizen_id, &code, "mallory@example.org"), None, &RequestOrigin::default());
+    let directory_address = register_user(&db, &format, &registration(citizen_id, &code, "erika@example.org"), Some("erika@example.org"), &RequestOrigin::default());
+
+    assert!(matches!(other_address, Err(UserRegistrationError::MailMismatch)), "{:?}", other_address);
+    assert!(matches!(without_letter, Err(UserRegistrationError::MailMismatch)), "{:?}", without_letter);
+    directory_address.unwrap();
+}
+
+#[test]
+#[ignore = "needs a migrated database in DATABASE_URL"]
+fn codes_follow_a_changed_address() {
+    let db = database();
+    let citizen_id = test_citizen(4);
+    let format = RegistrationCodeFormat::default();
+    let mail = mail_server(Arc::new(MemoryMailer::new()));
+
+    let old_code = ensure_pending_user(&db, &format, citizen_id, Some("erika@example.org")).unwrap().unwrap().0;
+    let mut info = citizen(citizen_id);
+    info.email = Some(String::from("erika.neu@example.org"));
+    let new_code = rebind_pending_user(&db, &format, &mail.templates, &mail.letters, &info).unwrap().unwrap();
+    let unchanged = rebind_pending_user(&db, &format, &mail.templates, &mail.letters, &info).unwrap();
+    send_queued_mails(&db, &mail);
+
+    let mails = mail.memory.as_ref().unwrap().mails_to("erika.neu@example.org");
+    assert_eq!(mails.last().unwrap().text, format!("Code: {}", new_code));
+    assert!(unchanged.is_none());
+    assert!(find_pending_user(&db, None, Some("erika@example.org")).unwrap().is_none());
+
+    let old = register_user(&db, &format, &registration(citizen_id, &old_code, "erika@example.org"), Some("erika.neu@example.org"), &RequestOrigin::default());
+    assert!(matches!(old, Err(UserRegistrationError::InvalidCitizenCode)), "{:?}", old);
+    register_user(&db, &format, &registration(citizen_id, &new_code, "erika.neu@example.org"), Some("erika.neu@example.org"), &RequestOrigin::default()).unwrap();
+}
+
+#[test]
+#[ignore = "needs a migrated database in DATABASE_URL"]
+fn codes_without_address_are_found_by_citizen_id() {
+    let db = database();
+    let citizen_id = test_citizen(5);
+    let format = RegistrationCodeFormat::default();
+
+    let code = ensure_pending_user(&db, &format, citizen_id, None).unwrap().unwrap().0;
+    let by_id = find_pending_user(&db, Some(citizen_id), None).unwrap();
+    let with_address = find_pending_user(&db, Some(citizen_id), Some("erika@example.org")).unwrap();
+
+    assert_eq!(by_id.unwrap().code, code);
+    assert!(with_address.is_none());
+}
+
+#[test]
+#[ignore = "needs a migrated database in DATABASE_URL"]
+fn expired_codes_are_rejected() {
+    let db = database();
+    let citizen_id = test_citizen(1);
+    let format = RegistrationCodeFormat::default();
+
+    let code = ensure_pending_user(&db, &format, citizen_id, Some("erika@example.org")).unwrap().unwrap().0;
+    diesel::update(PendingUsers::table.filter(PendingUsers::citizen.eq(citizen_id as i64)))
+        .set(PendingUsers::expires.eq(Utc::now().naive_utc() - chrono::Duration::days(1)))
+        .execute(&db)
+        .unwrap();
+    let result = register_user(&db, &format, &registration(citizen_id, &code, "erika@example.org"), None, &RequestOrigin::default());
+
+    assert!(matches!(result, Err(UserRegistrationError::ExpiredCitizenCode)), "{:?}", result);
+}
+
+#[test]
+#[ignore = "needs a migrated database in DATABASE_URL"]
+fn codes_are_resent_at_most_every_ten_minutes() {
+    let db = database();
+    let citizen_id = test_citizen(2);
+    let format = RegistrationCodeFormat::default();
+
+    let code = ensure_pending_user(&db, &format, citizen_id, Some("erika@example.org")).unwrap().unwrap().0;
+    let throttled = renew_pending_user(&db, &format, citizen_id, Some("erika@example.org"));
+    diesel::update(PendingUsers::table.filter(PendingUsers::citizen.eq(citizen_id as i64)))
+        .set(PendingUsers::issued.eq(Utc::now().naive_utc() - chrono::Duration::minutes(11)))
+        .execute(&db)
+        .unwrap();
+    let renewed = renew_pending_user(&db, &format, citizen_id, Some("erika@example.org"));
+
+    assert!(matches!(throttled, Err(UserRegistrationError::ResendThrottled)), "{:?}", throttled);
+    assert_ne!(renewed.unwrap(), code);
+}
+
+#[test]
+#[ignore = "needs a migrated database in DATABASE_URL"]
+fn resends_are_limited_per_address() {
+    let db = database();
+    let run = Utc::now().timestamp_nanos();
+    let origin = RequestOrigin { ip: Some(format!("test-{}-a", run)), user_agent: None };
+    let other = RequestOrigin { ip: Some(format!("test-{}-b", run)), user_agent: None };
+    let request = || NewAuditEvent::new(AuditParty::Anonymous, AuditEventType::RegistrationCodeResent, AuditOutcome::Failure)
+        .origin(&origin);
+
+    let mut allowed = Vec::new();
+    for _ in 0..6 {
+        allowed.push(check_resend_origin(&db, &origin).is_ok());
+        record_event(&db, &request()).unwrap();
+    }
+
+    assert_eq!(allowed, vec![true, true, true, true, true, false]);
+    assert!(check_resend_origin(&db, &other).is_ok());
+}
@@ -0,0 +1,28 @@
+use actix_web::cookie::SameSite;
+use backend::auth::SessionCookie::SessionCookies;
+use moon::{chrono, Utc};
+
+#[test]
+fn secure_cookies_without_domain_are_host_cookies() {
+    let cookies = SessionCookies::new(None, true, SameSite::Lax);
+    let expires = Utc::now().naive_utc() + chrono::Duration::hours(24);
+    let cookie = cookies.build("user_session_token", &String::from("token"), &expires);
+
+    assert_eq!(cookie.name(), "__Host-user_session_token");
+    assert_eq!(cookie.path(), Some("/"));
+    assert_eq!(cookie.domain(), None);
+    assert_eq!(cookie.secure(), Some(true));
+    assert_eq!(cookie.http_only(), Some(true));
+    assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+    assert!(cookie.max_age().map_or(false, |age| age.whole_hours() == 23 || age.whole_hours() == 24));
+}
+
+#[test]
+fn cookies_with_domain_keep_their_name() {
+    let cookies = SessionCookies::new(Some(String::from("supersmartcity.de")), true, SameSite::Strict);
+    let cookie = cookies.build("employee_session_token", &String::from("token"), &Utc::now().naive_utc());
+
+    assert_eq!(cookie.name(), "employee_session_token");
+    assert_eq!(cookie.domain(), Some("supersmartcity.de"));
+    assert_eq!(cookies.name("employee_session_token"), "employee_session_token");
+}
@@ -39,7 +39,7 @@ fn heading_group() -> impl Element {
 
 fn register_form() -> impl Element {
     RawHtmlEl::new("form")
-        .attr("action", "/register")
+        .attr("action", "/api/v1/register")
         .attr("enctype", "application/x-www-form-urlencoded")
         .attr("method", "post")
         .child(
@@ -73,6 +73,17 @@ fn cit_id_input() -> impl Element {
     RawHtmlEl::new("input")
         .attr("placeholder", "Registrierungsschlüssel")
         .attr("name", "code")
+        .attr("value", &code_from_url().unwrap_or_default())
+}
+
+/// Code from the link in the registration mail or letter (`?code=...`)
+fn code_from_url() -> Option<String> {
+    let search = window().location().search().ok()?;
+    search
+        .trim_start_matches('?')
+        .split('&')
+        .find_map(|parameter| parameter.strip_prefix("code="))
+        .map(String::from)
 }
 
 fn pw_input() -> impl Element {